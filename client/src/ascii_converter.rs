@@ -1,21 +1,106 @@
 use core::error::Error;
-use std::io::{Write, stdout};
+use std::{
+    env,
+    io::{Write, stdout},
+};
 
+use clap::ValueEnum;
 use opencv::{
-    core::{AlgorithmHint, BORDER_DEFAULT, CV_8U, CV_32F, Mat, Point_, Size, add_weighted},
-    imgproc::{
-        COLOR_BGR2GRAY, INTER_LINEAR, canny, create_clahe, cvt_color, filter_2d, gaussian_blur,
-        resize,
-    },
+    core::{AlgorithmHint, Mat, Size},
+    imgproc::{COLOR_GRAY2BGR, INTER_AREA, INTER_LINEAR, cvt_color, resize},
     prelude::*,
 };
+use shared::{Resolution, VideoFormat, VideoFrame};
+
+use crate::{character_ramp::CharacterRamp, dither::Dither, enhancement::EnhancementChain};
 
+/// Characters of the default ramp, darkest first.
 pub const ASCII_CHARS: &[char] = &[
     ' ', '.', '^', '=', '~', '-', ',', ':', ';', '+', '*', '?', '%', 'S', '#', '@',
 ];
 
-pub const WIDTH: i32 = 92;
-pub const HEIGHT: i32 = 28;
+/// Grid a client sends when no other size is chosen.
+pub const DEFAULT_RESOLUTION: Resolution = Resolution::new(92, 28);
+
+/// Intensity of each of the six levels per channel of the 6x6x6 color cube,
+/// matching the cube in the 256-color terminal palette.
+const COLOR_CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// How video is colored in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    #[value(name = "off")]
+    Off,
+    #[value(name = "256")]
+    Ansi256,
+    #[value(name = "truecolor")]
+    TrueColor,
+}
+
+impl ColorMode {
+    /// Best mode the terminal claims to support in `COLORTERM` and `TERM`.
+    pub fn detect() -> ColorMode {
+        if let Ok(colorterm) = env::var("COLORTERM")
+            && (colorterm == "truecolor" || colorterm == "24bit")
+        {
+            return ColorMode::TrueColor;
+        }
+
+        if let Ok(term) = env::var("TERM")
+            && term.contains("256color")
+        {
+            return ColorMode::Ansi256;
+        }
+
+        return ColorMode::Off;
+    }
+
+    /// Escape sequence switching `layer` (`FOREGROUND` or `BACKGROUND`) to
+    /// `shade`.
+    fn escape(self, layer: u8, shade: Shade) -> String {
+        match (self, shade) {
+            (ColorMode::Off, _) => String::new(),
+            (ColorMode::Ansi256, Shade::Cube(index)) => {
+                format!("\x1B[{};5;{}m", layer, 16 + index.min(215) as u16)
+            }
+            (ColorMode::Ansi256, Shade::Gray(luminance)) => {
+                format!("\x1B[{};5;{}m", layer, 232 + luminance as u16 * 23 / 255)
+            }
+            (ColorMode::TrueColor, shade) => {
+                let (r, g, b) = shade.rgb();
+                format!("\x1B[{};2;{};{};{}m", layer, r, g, b)
+            }
+        }
+    }
+}
+
+const FOREGROUND: u8 = 38;
+const BACKGROUND: u8 = 48;
+
+/// A color the renderers ask the terminal for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shade {
+    /// Entry of the 6x6x6 color cube.
+    Cube(u8),
+    /// 8-bit luminance.
+    Gray(u8),
+}
+
+impl Shade {
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Shade::Cube(index) => {
+                let index = index.min(215);
+                (
+                    COLOR_CUBE_LEVELS[(index / 36) as usize],
+                    COLOR_CUBE_LEVELS[(index / 6 % 6) as usize],
+                    COLOR_CUBE_LEVELS[(index % 6) as usize],
+                )
+            }
+            Shade::Gray(luminance) => (luminance, luminance, luminance),
+        }
+    }
+}
 
 pub struct AsciiConverter {
     last_frame: Option<String>,
@@ -30,77 +115,92 @@ impl AsciiConverter {
         }
     }
 
-    pub fn frame_to_nibbles(frame: &Mat) -> Result<Vec<u8>, Box<dyn Error>> {
-        let frame = enhance_frame(frame)?;
+    /// Luminance of `frame` on a grid of `resolution` cells, laid out as
+    /// `format` after running it through `enhancement`. 4-bit formats are
+    /// quantized with `dither` and pack two cells per byte, with rows of odd
+    /// width ending in a padding cell.
+    pub fn frame_to_luminance(
+        frame: &Mat,
+        resolution: Resolution,
+        format: VideoFormat,
+        dither: Dither,
+        enhancement: &EnhancementChain,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let frame = enhancement.apply(frame)?;
+
+        let width = resolution.width as usize;
+        let height = resolution.height as usize;
 
         let mut resized = Mat::default();
-        let size = Size::new(WIDTH, HEIGHT);
+        let size = Size::new(width as i32, height as i32);
         resize(&frame, &mut resized, size, 0.0, 0.0, INTER_LINEAR)?;
 
         let data = resized.data_bytes()?;
-        let mut nibbles = Vec::with_capacity((WIDTH * HEIGHT / 2) as usize);
+        let mut mirrored = Vec::with_capacity(resolution.cell_count());
 
-        for row in 0..HEIGHT {
-            let row_start = (row * WIDTH) as usize;
+        for row in 0..height {
+            for col in 0..width {
+                mirrored.push(data[row * width + width - 1 - col]);
+            }
+        }
 
-            for col in (0..WIDTH).step_by(2) {
-                let x1 = WIDTH - 1 - col;
-                let x2 = if col + 1 < WIDTH {
-                    WIDTH - 1 - (col + 1)
-                } else {
-                    0
-                };
+        if format.luminance_bits() == 8 {
+            return Ok(mirrored);
+        }
 
-                let p1 = data[row_start + x1 as usize];
-                let nibble1 = ((p1 as u16 * 15) / 255) as u8;
+        let levels = dither.quantize(&mirrored, width, height, 16);
+        let mut nibbles = Vec::with_capacity(resolution.nibble_len());
 
-                let nibble2 = if col + 1 < WIDTH {
-                    let p2 = data[row_start + x2 as usize];
-                    ((p2 as u16 * 15) / 255) as u8
-                } else {
-                    0
-                };
+        for row in levels.chunks(width.max(1)) {
+            for pair in row.chunks(2) {
+                let high = pair[0];
+                let low = pair.get(1).copied().unwrap_or(0);
 
-                nibbles.push((nibble1 << 4) | nibble2);
+                nibbles.push((high << 4) | low);
             }
         }
 
         Ok(nibbles)
     }
 
-    pub fn nibbles_to_ascii(nibbles: &[u8], width: u16, height: u16) -> String {
-        let mut grayscale: Vec<u8> = Vec::with_capacity((WIDTH as usize) * (HEIGHT as usize));
-        for byte in nibbles {
-            let high = (byte >> 4) & 0x0F;
-            let low = byte & 0x0F;
-            grayscale.push(high);
-            grayscale.push(low);
+    /// One 6x6x6 color cube index per cell of a `resolution` grid, row by row
+    /// and mirrored like `frame_to_luminance`.
+    pub fn frame_to_colors(frame: &Mat, resolution: Resolution) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut color = Mat::default();
+        if frame.channels() == 1 {
+            cvt_color(
+                frame,
+                &mut color,
+                COLOR_GRAY2BGR,
+                0,
+                AlgorithmHint::ALGO_HINT_DEFAULT,
+            )?;
+        } else {
+            color = frame.clone();
         }
 
-        let mut ascii_art = String::with_capacity((width + 1) as usize * height as usize);
-        for y in 0..height {
-            let src_y = (y as f32 * (HEIGHT as f32 - 1.0) / (height as f32)).round() as i32;
-            let sy = (src_y.max(0).min(HEIGHT - 1)) as usize;
+        let width = resolution.width as usize;
+        let height = resolution.height as usize;
 
-            for x in 0..width {
-                let src_x = (x as f32 * (WIDTH as f32) / (width as f32)).round() as i32;
-                let sx = (src_x.max(0).min(WIDTH - 1)) as usize;
+        let mut resized = Mat::default();
+        let size = Size::new(width as i32, height as i32);
+        resize(&color, &mut resized, size, 0.0, 0.0, INTER_AREA)?;
 
-                let idx = sy * WIDTH as usize + sx;
-                let pixel = if idx < grayscale.len() {
-                    grayscale[idx]
-                } else {
-                    0
-                };
+        let data = resized.data_bytes()?;
+        let mut colors = Vec::with_capacity(resolution.cell_count());
 
-                let ascii_idx = (pixel as usize * (ASCII_CHARS.len() - 1)) / 15;
-                ascii_art.push(ASCII_CHARS[ascii_idx]);
-            }
+        for row in 0..height {
+            for col in 0..width {
+                let pixel = (row * width + width - 1 - col) * 3;
+                let b = cube_level(data[pixel]);
+                let g = cube_level(data[pixel + 1]);
+                let r = cube_level(data[pixel + 2]);
 
-            ascii_art.push('\n');
+                colors.push(36 * r + 6 * g + b);
+            }
         }
 
-        ascii_art
+        Ok(colors)
     }
 
     pub fn update_terminal_smooth(
@@ -120,15 +220,15 @@ impl AsciiConverter {
             self.last_frame = None;
         }
 
-        if let Some(ref last) = self.last_frame {
-            if self.try_differential_update(last, new_content)? {
-                self.last_frame = Some(new_content.to_string());
-                return Ok(());
-            }
+        if let Some(ref last) = self.last_frame
+            && self.try_differential_update(last, new_content)?
+        {
+            self.last_frame = Some(new_content.to_string());
+            return Ok(());
         }
 
         print!("\x1B[1;1H");
-        print!("{}", new_content);
+        print!("{}", new_content.replace('\n', "\r\n"));
 
         if let Some(ref last) = self.last_frame {
             let new_lines = new_content.lines().count();
@@ -136,7 +236,7 @@ impl AsciiConverter {
 
             if old_lines > new_lines {
                 for _ in new_lines..old_lines {
-                    print!("\x1B[K\n");
+                    print!("\x1B[K\r\n");
                 }
             }
         }
@@ -196,71 +296,540 @@ impl AsciiConverter {
     }
 }
 
-fn enhance_frame(frame: &Mat) -> Result<Mat, Box<dyn Error>> {
-    let mut gray = Mat::default();
-    if frame.channels() != 1 {
-        cvt_color(
-            frame,
-            &mut gray,
-            COLOR_BGR2GRAY,
-            0,
-            AlgorithmHint::ALGO_HINT_DEFAULT,
-        )?;
-    } else {
-        gray = frame.clone();
-    }
-
-    let mut clahe = create_clahe(1.5, Size::new(8, 8))?;
-    let mut contrast = Mat::default();
-    clahe.apply(&gray, &mut contrast)?;
-
-    let mut blurred = Mat::default();
-    gaussian_blur(
-        &contrast,
-        &mut blurred,
-        Size::new(3, 3),
-        0.0,
-        0.0,
-        BORDER_DEFAULT,
-        AlgorithmHint::ALGO_HINT_DEFAULT,
-    )?;
-
-    let kernel_data = [0.0f32, -0.5, 0.0, -0.5, 3.0, -0.5, 0.0, -0.5, 0.0];
-    let kernel = Mat::from_slice_2d(&[&kernel_data[0..3], &kernel_data[3..6], &kernel_data[6..9]])?;
-
-    let mut sharpened = Mat::default();
-    filter_2d(
-        &blurred,
-        &mut sharpened,
-        -1,
-        &kernel,
-        Point_::new(-1, -1),
-        0.0,
-        BORDER_DEFAULT,
-    )?;
-
-    let mut edges = Mat::default();
-    canny(&sharpened, &mut edges, 100.0, 200.0, 3, false)?;
-
-    let mut edges_f32 = Mat::default();
-    edges.convert_to(&mut edges_f32, CV_32F, 1.0 / 255.0, 0.0)?;
-
-    let mut sharpened_f32 = Mat::default();
-    sharpened.convert_to(&mut sharpened_f32, CV_32F, 1.0 / 255.0, 0.0)?;
-
-    let mut final_enhanced = Mat::default();
-    add_weighted(
-        &sharpened_f32,
-        1.0,
-        &edges_f32,
-        0.15,
-        0.0,
-        &mut final_enhanced,
-        -1,
-    )?;
-
-    let mut output = Mat::default();
-    final_enhanced.convert_to(&mut output, CV_8U, 255.0, 0.0)?;
-
-    Ok(output)
+/// Bit of each Braille dot, indexed by row and then column.
+const BRAILLE_DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Turns decoded video frames into terminal text.
+pub trait GlyphRenderer {
+    /// Renders `frame` into `width` x `height` terminal cells, colored
+    /// according to `color_mode`.
+    fn render(&self, frame: &VideoFrame, color_mode: ColorMode, width: u16, height: u16) -> String;
+
+    /// Frame cells this renderer draws in each terminal cell, across and
+    /// down.
+    fn cells_per_glyph(&self) -> (u16, u16);
+}
+
+/// Renderers selectable from the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RendererKind {
+    #[value(name = "ascii")]
+    Ascii,
+    #[value(name = "half-block")]
+    HalfBlock,
+    #[value(name = "braille")]
+    Braille,
+}
+
+impl RendererKind {
+    /// Renderer of this kind, drawing characters from `ramp` and using
+    /// `dither` wherever it draws fewer levels than the frame carries.
+    pub fn renderer(
+        self,
+        dither: Dither,
+        ramp: CharacterRamp,
+    ) -> Box<dyn GlyphRenderer + Send + Sync> {
+        match self {
+            RendererKind::Ascii => Box::new(AsciiRenderer { ramp }),
+            RendererKind::HalfBlock => Box::new(HalfBlockRenderer { dither }),
+            RendererKind::Braille => Box::new(BrailleRenderer { dither }),
+        }
+    }
+}
+
+/// One character of a ramp per terminal cell.
+pub struct AsciiRenderer {
+    pub ramp: CharacterRamp,
+}
+
+impl GlyphRenderer for AsciiRenderer {
+    fn render(&self, frame: &VideoFrame, color_mode: ColorMode, width: u16, height: u16) -> String {
+        let sampler = FrameSampler::new(frame, color_mode, width as u32, height as u32);
+        let mut canvas = Canvas::new(color_mode, width, height);
+
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let glyph = self.ramp.glyph(sampler.luminance(x, y));
+
+                canvas.push(glyph, sampler.color(x, y).map(Shade::Cube), None);
+            }
+
+            canvas.end_line();
+        }
+
+        canvas.finish()
+    }
+
+    fn cells_per_glyph(&self) -> (u16, u16) {
+        (1, 1)
+    }
+}
+
+/// Two samples per terminal cell, stacked vertically. In color the top one
+/// is drawn as `▀` over the bottom one's background; without color each
+/// half is either lit or dark.
+pub struct HalfBlockRenderer {
+    pub dither: Dither,
+}
+
+impl GlyphRenderer for HalfBlockRenderer {
+    fn render(&self, frame: &VideoFrame, color_mode: ColorMode, width: u16, height: u16) -> String {
+        let sampler = FrameSampler::new(frame, color_mode, width as u32, height as u32 * 2);
+        let mut canvas = Canvas::new(color_mode, width, height);
+
+        let shade = |x: u32, y: u32| {
+            sampler
+                .color(x, y)
+                .map(Shade::Cube)
+                .unwrap_or(Shade::Gray(sampler.luminance(x, y)))
+        };
+
+        let lit = match color_mode {
+            ColorMode::Off => sampler.quantize(self.dither, 2),
+            _ => Vec::new(),
+        };
+        let is_lit = |x: u32, y: u32| lit.get((y * width as u32 + x) as usize) == Some(&1);
+
+        for y in 0..height as u32 {
+            let (top, bottom) = (y * 2, y * 2 + 1);
+
+            for x in 0..width as u32 {
+                if color_mode != ColorMode::Off {
+                    canvas.push('▀', Some(shade(x, top)), Some(shade(x, bottom)));
+                    continue;
+                }
+
+                let top_lit = is_lit(x, top);
+                let bottom_lit = is_lit(x, bottom);
+
+                let glyph = match (top_lit, bottom_lit) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                };
+                canvas.push(glyph, None, None);
+            }
+
+            canvas.end_line();
+        }
+
+        canvas.finish()
+    }
+
+    fn cells_per_glyph(&self) -> (u16, u16) {
+        (1, 2)
+    }
+}
+
+/// Eight samples per terminal cell as the dots of a Braille pattern, two
+/// across and four down. A dot is raised when its sample dithers to lit.
+pub struct BrailleRenderer {
+    pub dither: Dither,
+}
+
+impl GlyphRenderer for BrailleRenderer {
+    fn render(&self, frame: &VideoFrame, color_mode: ColorMode, width: u16, height: u16) -> String {
+        let sampler = FrameSampler::new(frame, color_mode, width as u32 * 2, height as u32 * 4);
+        let mut canvas = Canvas::new(color_mode, width, height);
+
+        let lit = sampler.quantize(self.dither, 2);
+        let is_lit = |x: u32, y: u32| lit.get((y * width as u32 * 2 + x) as usize) == Some(&1);
+
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let mut dots = 0u8;
+
+                for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                    for (dx, dot) in row.iter().enumerate() {
+                        if is_lit(x * 2 + dx as u32, y * 4 + dy as u32) {
+                            dots |= dot;
+                        }
+                    }
+                }
+
+                let glyph = char::from_u32(0x2800 + dots as u32).expect("Invalid Braille pattern");
+                canvas.push(glyph, sampler.color(x * 2, y * 4).map(Shade::Cube), None);
+            }
+
+            canvas.end_line();
+        }
+
+        canvas.finish()
+    }
+
+    fn cells_per_glyph(&self) -> (u16, u16) {
+        (2, 4)
+    }
+}
+
+/// Reads a frame's cells at a chosen number of samples across and down,
+/// independent of the frame's own resolution.
+struct FrameSampler<'a> {
+    /// 8-bit luminance of every cell, `row_stride` cells per row.
+    luminance: Vec<u8>,
+    row_stride: usize,
+    colors: Option<&'a [u8]>,
+    resolution: Resolution,
+    samples_width: u32,
+    samples_height: u32,
+}
+
+impl<'a> FrameSampler<'a> {
+    /// Colors are only read when the frame carries them and `color_mode` is
+    /// not `Off`.
+    fn new(
+        frame: &'a VideoFrame,
+        color_mode: ColorMode,
+        samples_width: u32,
+        samples_height: u32,
+    ) -> Self {
+        let luminance_len = frame
+            .format
+            .luminance_len(frame.resolution)
+            .min(frame.cells.len());
+        let (packed, colors) = frame.cells.split_at(luminance_len);
+
+        let (luminance, row_stride) = match frame.format.luminance_bits() {
+            4 => {
+                let mut luminance = Vec::with_capacity(packed.len() * 2);
+                for byte in packed {
+                    luminance.push(((byte >> 4) & 0x0F) * 17);
+                    luminance.push((byte & 0x0F) * 17);
+                }
+
+                (luminance, (frame.resolution.width as usize).div_ceil(2) * 2)
+            }
+            _ => (packed.to_vec(), frame.resolution.width as usize),
+        };
+
+        let colors = match color_mode {
+            ColorMode::Off => None,
+            _ if frame.format.has_color() => Some(colors),
+            _ => None,
+        };
+
+        Self {
+            luminance,
+            row_stride,
+            colors,
+            resolution: frame.resolution,
+            samples_width,
+            samples_height,
+        }
+    }
+
+    /// Column and row of the frame cell that sample (`x`, `y`) falls on.
+    fn source_cell(&self, x: u32, y: u32) -> (usize, usize) {
+        let src_width = self.resolution.width.max(1) as i32;
+        let src_height = self.resolution.height.max(1) as i32;
+
+        let src_x = (x as f32 * (src_width as f32) / (self.samples_width as f32)).round() as i32;
+        let src_y =
+            (y as f32 * (src_height as f32 - 1.0) / (self.samples_height as f32)).round() as i32;
+
+        (
+            src_x.clamp(0, src_width - 1) as usize,
+            src_y.clamp(0, src_height - 1) as usize,
+        )
+    }
+
+    /// 8-bit luminance of sample (`x`, `y`).
+    fn luminance(&self, x: u32, y: u32) -> u8 {
+        let (sx, sy) = self.source_cell(x, y);
+
+        self.luminance
+            .get(sy * self.row_stride + sx)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every sample's luminance, row by row, quantized to `levels` levels
+    /// with `dither`.
+    fn quantize(&self, dither: Dither, levels: u8) -> Vec<u8> {
+        let mut pixels =
+            Vec::with_capacity(self.samples_width as usize * self.samples_height as usize);
+
+        for y in 0..self.samples_height {
+            for x in 0..self.samples_width {
+                pixels.push(self.luminance(x, y));
+            }
+        }
+
+        dither.quantize(
+            &pixels,
+            self.samples_width as usize,
+            self.samples_height as usize,
+            levels,
+        )
+    }
+
+    /// Color cube entry of sample (`x`, `y`), if the frame carries color.
+    fn color(&self, x: u32, y: u32) -> Option<u8> {
+        let colors = self.colors?;
+        let (sx, sy) = self.source_cell(x, y);
+
+        colors
+            .get(sy * self.resolution.width as usize + sx)
+            .copied()
+    }
+}
+
+/// Rendered lines of glyphs, switching colors only when they change and
+/// resetting them at the end of every line.
+struct Canvas {
+    output: String,
+    color_mode: ColorMode,
+    foreground: Option<Shade>,
+    background: Option<Shade>,
+}
+
+impl Canvas {
+    fn new(color_mode: ColorMode, width: u16, height: u16) -> Self {
+        Self {
+            output: String::with_capacity((width as usize + 1) * height as usize),
+            color_mode,
+            foreground: None,
+            background: None,
+        }
+    }
+
+    fn push(&mut self, glyph: char, foreground: Option<Shade>, background: Option<Shade>) {
+        if self.color_mode != ColorMode::Off {
+            if let Some(shade) = foreground
+                && self.foreground != foreground
+            {
+                self.output
+                    .push_str(&self.color_mode.escape(FOREGROUND, shade));
+                self.foreground = foreground;
+            }
+
+            if let Some(shade) = background
+                && self.background != background
+            {
+                self.output
+                    .push_str(&self.color_mode.escape(BACKGROUND, shade));
+                self.background = background;
+            }
+        }
+
+        self.output.push(glyph);
+    }
+
+    fn end_line(&mut self) {
+        if self.foreground.is_some() || self.background.is_some() {
+            self.output.push_str("\x1B[0m");
+            self.foreground = None;
+            self.background = None;
+        }
+
+        self.output.push('\n');
+    }
+
+    fn finish(self) -> String {
+        self.output
+    }
+}
+
+/// Index of the color cube level closest to `value`.
+fn cube_level(value: u8) -> u8 {
+    let (level, _) = COLOR_CUBE_LEVELS
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, level)| level.abs_diff(value))
+        .expect("Color cube has levels");
+
+    level as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x2 frame running from black to white and back, with a different
+    /// color cube entry in every cell.
+    fn frame(format: VideoFormat) -> VideoFrame {
+        VideoFrame::from_cells(
+            Resolution::new(4, 2),
+            format,
+            &[0, 85, 170, 255, 255, 170, 85, 0],
+            &[0, 5, 30, 215, 196, 46, 21, 231],
+        )
+    }
+
+    fn ascii() -> AsciiRenderer {
+        AsciiRenderer {
+            ramp: CharacterRamp::default(),
+        }
+    }
+
+    #[test]
+    fn cube_entries_match_the_terminal_palette() {
+        assert_eq!(Shade::Cube(0).rgb(), (0, 0, 0));
+        assert_eq!(Shade::Cube(36 + 6 * 2 + 3).rgb(), (95, 135, 175));
+        assert_eq!(Shade::Cube(215).rgb(), (255, 255, 255));
+
+        assert_eq!(cube_level(0), 0);
+        assert_eq!(cube_level(100), 1);
+        assert_eq!(cube_level(255), 5);
+
+        assert_eq!(
+            ColorMode::Ansi256.escape(FOREGROUND, Shade::Cube(51)),
+            "\x1B[38;5;67m"
+        );
+        assert_eq!(
+            ColorMode::Ansi256.escape(BACKGROUND, Shade::Gray(255)),
+            "\x1B[48;5;255m"
+        );
+        assert_eq!(
+            ColorMode::TrueColor.escape(FOREGROUND, Shade::Cube(51)),
+            "\x1B[38;2;95;135;175m"
+        );
+        assert_eq!(ColorMode::Off.escape(FOREGROUND, Shade::Cube(51)), "");
+    }
+
+    #[test]
+    fn sampler_skips_the_padding_nibble_of_odd_rows() {
+        let luminance = [0, 85, 255, 34, 51, 68];
+        let frame = VideoFrame::from_cells(
+            Resolution::new(3, 2),
+            VideoFormat::NibbleGray,
+            &luminance,
+            &[],
+        );
+
+        let sampler = FrameSampler::new(&frame, ColorMode::Off, 3, 2);
+        let sampled: Vec<u8> = (0..2)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| sampler.luminance(x, y))
+            .collect();
+
+        assert_eq!(sampled, luminance);
+    }
+
+    #[test]
+    fn ascii_draws_gray_frames_without_escapes() {
+        let golden = " -*@\n@*- \n";
+
+        for color_mode in [ColorMode::Off, ColorMode::Ansi256, ColorMode::TrueColor] {
+            assert_eq!(
+                ascii().render(&frame(VideoFormat::ByteGray), color_mode, 4, 2),
+                golden
+            );
+        }
+        assert_eq!(
+            ascii().render(&frame(VideoFormat::ByteColor), ColorMode::Off, 4, 2),
+            golden
+        );
+    }
+
+    #[test]
+    fn ascii_colors_glyphs_from_the_256_color_cube() {
+        let golden = "\
+\x1B[38;5;16m \x1B[38;5;21m-\x1B[38;5;46m*\x1B[38;5;231m@\x1B[0m
+\x1B[38;5;212m@\x1B[38;5;62m*\x1B[38;5;37m-\x1B[38;5;231m \x1B[0m
+";
+
+        for format in [VideoFormat::ByteColor, VideoFormat::NibbleColor] {
+            assert_eq!(
+                ascii().render(&frame(format), ColorMode::Ansi256, 4, 2),
+                golden
+            );
+        }
+    }
+
+    #[test]
+    fn ascii_colors_glyphs_in_truecolor() {
+        let golden = "\
+\x1B[38;2;0;0;0m \x1B[38;2;0;0;255m-\x1B[38;2;0;255;0m*\x1B[38;2;255;255;255m@\x1B[0m
+\x1B[38;2;255;135;215m@\x1B[38;2;95;95;215m*\x1B[38;2;0;175;175m-\x1B[38;2;255;255;255m \x1B[0m
+";
+
+        assert_eq!(
+            ascii().render(&frame(VideoFormat::ByteColor), ColorMode::TrueColor, 4, 2),
+            golden
+        );
+    }
+
+    #[test]
+    fn half_block_lights_each_half_without_color() {
+        let renderer = HalfBlockRenderer {
+            dither: Dither::None,
+        };
+
+        assert_eq!(
+            renderer.render(&frame(VideoFormat::ByteColor), ColorMode::Off, 4, 1),
+            "▄▄▀▀\n"
+        );
+    }
+
+    #[test]
+    fn half_block_stacks_two_cube_colors() {
+        let renderer = HalfBlockRenderer {
+            dither: Dither::None,
+        };
+        let golden = "\
+\x1B[38;5;16m\x1B[48;5;212m▀\x1B[38;5;21m\x1B[48;5;62m▀\x1B[38;5;46m\x1B[48;5;37m▀\x1B[38;5;231m\x1B[48;5;231m▀\x1B[0m
+";
+
+        assert_eq!(
+            renderer.render(&frame(VideoFormat::ByteColor), ColorMode::Ansi256, 4, 1),
+            golden
+        );
+    }
+
+    #[test]
+    fn half_block_shades_gray_frames_from_the_gray_ramp() {
+        let renderer = HalfBlockRenderer {
+            dither: Dither::None,
+        };
+        let golden = "\
+\x1B[38;5;232m\x1B[48;5;255m▀\x1B[38;5;239m\x1B[48;5;247m▀\x1B[38;5;247m\x1B[48;5;239m▀\x1B[38;5;255m\x1B[48;5;232m▀\x1B[0m
+";
+
+        assert_eq!(
+            renderer.render(&frame(VideoFormat::ByteGray), ColorMode::Ansi256, 4, 1),
+            golden
+        );
+    }
+
+    #[test]
+    fn braille_raises_the_dots_of_lit_samples() {
+        let renderer = BrailleRenderer {
+            dither: Dither::None,
+        };
+
+        assert_eq!(
+            renderer.render(&frame(VideoFormat::ByteGray), ColorMode::Ansi256, 2, 1),
+            "⣤⠛\n"
+        );
+        assert_eq!(
+            renderer.render(&frame(VideoFormat::ByteColor), ColorMode::Ansi256, 2, 1),
+            "\x1B[38;5;16m⣤\x1B[38;5;46m⠛\x1B[0m\n"
+        );
+    }
+
+    #[test]
+    fn renderers_fill_the_terminal_with_cells_per_glyph() {
+        let (width, height) = (5, 3);
+
+        for kind in [
+            RendererKind::Ascii,
+            RendererKind::HalfBlock,
+            RendererKind::Braille,
+        ] {
+            let renderer = kind.renderer(Dither::None, CharacterRamp::default());
+            let (across, down) = renderer.cells_per_glyph();
+            let resolution = Resolution::new(width * across, height * down);
+            let cells = vec![128; resolution.cell_count()];
+            let frame = VideoFrame::from_cells(resolution, VideoFormat::ByteGray, &cells, &[]);
+
+            let rendered = renderer.render(&frame, ColorMode::Off, width, height);
+
+            assert_eq!(rendered.lines().count(), height as usize, "{:?}", kind);
+            for line in rendered.lines() {
+                assert_eq!(line.chars().count(), width as usize, "{:?}", kind);
+            }
+        }
+    }
 }
@@ -0,0 +1,729 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use shared::{AUDIO_FRAME_SAMPLES, AUDIO_SAMPLE_RATE};
+use tokio::time::{Duration, Interval, MissedTickBehavior, interval};
+
+/// Time covered by one audio packet.
+pub const AUDIO_FRAME_DURATION: Duration =
+    Duration::from_millis(AUDIO_FRAME_SAMPLES as u64 * 1000 / AUDIO_SAMPLE_RATE as u64);
+
+/// Most packets held for one stream, about a second of sound.
+const MAX_BUFFERED_PACKETS: usize = 50;
+
+/// Where the sound sent into a call comes from.
+#[async_trait]
+pub trait AudioSource: Send {
+    /// Waits for and returns the next `AUDIO_FRAME_SAMPLES` samples, mono at
+    /// `AUDIO_SAMPLE_RATE`.
+    async fn next_chunk(&mut self) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Where the sound received in a call goes.
+pub trait AudioSink: Send {
+    /// Plays `samples`, mono at `AUDIO_SAMPLE_RATE`, after anything played
+    /// before.
+    fn play(&mut self, samples: &[i16]) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// An audio input picked on the command line.
+///
+/// Written as `none`, `wav:PATH` or, in builds with the `sound-hardware`
+/// feature, `device` for the default microphone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioInputSpec {
+    /// Send no sound.
+    None,
+    /// A WAV file, looped.
+    Wav(PathBuf),
+    #[cfg(feature = "sound-hardware")]
+    Device,
+}
+
+/// An audio output picked on the command line.
+///
+/// Written as `null`, `wav:PATH` or, in builds with the `sound-hardware`
+/// feature, `device` for the default speakers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioOutputSpec {
+    /// Discard received sound.
+    Null,
+    /// A WAV file, written with everything received, mixed.
+    Wav(PathBuf),
+    #[cfg(feature = "sound-hardware")]
+    Device,
+}
+
+impl Default for AudioInputSpec {
+    /// The microphone when this build can open one, otherwise nothing.
+    fn default() -> Self {
+        #[cfg(feature = "sound-hardware")]
+        return AudioInputSpec::Device;
+        #[cfg(not(feature = "sound-hardware"))]
+        return AudioInputSpec::None;
+    }
+}
+
+impl Default for AudioOutputSpec {
+    /// The speakers when this build can open them, otherwise nowhere.
+    fn default() -> Self {
+        #[cfg(feature = "sound-hardware")]
+        return AudioOutputSpec::Device;
+        #[cfg(not(feature = "sound-hardware"))]
+        return AudioOutputSpec::Null;
+    }
+}
+
+impl AudioInputSpec {
+    /// Opens the input, or returns `None` if no sound is to be sent.
+    pub fn open(&self) -> Result<Option<Box<dyn AudioSource>>, Box<dyn Error + Send + Sync>> {
+        match self {
+            AudioInputSpec::None => return Ok(None),
+            AudioInputSpec::Wav(path) => return Ok(Some(Box::new(WavInput::new(path)?))),
+            #[cfg(feature = "sound-hardware")]
+            AudioInputSpec::Device => return Ok(Some(Box::new(device::Microphone::new()?))),
+        }
+    }
+}
+
+impl AudioOutputSpec {
+    pub fn open(&self) -> Result<Box<dyn AudioSink>, Box<dyn Error + Send + Sync>> {
+        match self {
+            AudioOutputSpec::Null => return Ok(Box::new(NullOutput)),
+            AudioOutputSpec::Wav(path) => return Ok(Box::new(WavOutput::new(path)?)),
+            #[cfg(feature = "sound-hardware")]
+            AudioOutputSpec::Device => return Ok(Box::new(device::Speakers::new()?)),
+        }
+    }
+}
+
+impl FromStr for AudioInputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => return Ok(AudioInputSpec::None),
+            "device" => {
+                #[cfg(feature = "sound-hardware")]
+                return Ok(AudioInputSpec::Device);
+                #[cfg(not(feature = "sound-hardware"))]
+                return Err(NO_SOUND_HARDWARE.to_string());
+            }
+            _ => {}
+        }
+
+        match s.split_once(':') {
+            Some(("wav", path)) => return Ok(AudioInputSpec::Wav(PathBuf::from(path))),
+            _ => return Err(format!("Expected none, device or wav:PATH, got '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for AudioOutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" => return Ok(AudioOutputSpec::Null),
+            "device" => {
+                #[cfg(feature = "sound-hardware")]
+                return Ok(AudioOutputSpec::Device);
+                #[cfg(not(feature = "sound-hardware"))]
+                return Err(NO_SOUND_HARDWARE.to_string());
+            }
+            _ => {}
+        }
+
+        match s.split_once(':') {
+            Some(("wav", path)) => return Ok(AudioOutputSpec::Wav(PathBuf::from(path))),
+            _ => return Err(format!("Expected null, device or wav:PATH, got '{}'", s)),
+        }
+    }
+}
+
+#[cfg(not(feature = "sound-hardware"))]
+const NO_SOUND_HARDWARE: &str = "Sound devices need a client built with the sound-hardware feature";
+
+/// Plays a WAV file of any rate and channel count in a loop, paced to real
+/// time.
+pub struct WavInput {
+    samples: Vec<i16>,
+    position: usize,
+    ticker: Interval,
+}
+
+impl WavInput {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut reader = WavReader::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let spec = reader.spec();
+
+        let interleaved = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            SampleFormat::Int => {
+                let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / full_scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        let mono = downmix(&interleaved, spec.channels as usize);
+        let mut samples = Vec::new();
+        Resampler::new(spec.sample_rate, AUDIO_SAMPLE_RATE).process(&mono, &mut samples);
+
+        if samples.is_empty() {
+            return Err(format!("No audio in {}", path.display()).into());
+        }
+
+        let mut ticker = interval(AUDIO_FRAME_DURATION);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        return Ok(Self {
+            samples: samples.into_iter().map(to_i16).collect(),
+            position: 0,
+            ticker,
+        });
+    }
+}
+
+#[async_trait]
+impl AudioSource for WavInput {
+    async fn next_chunk(&mut self) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+        self.ticker.tick().await;
+
+        let chunk = (0..AUDIO_FRAME_SAMPLES)
+            .map(|i| self.samples[(self.position + i) % self.samples.len()])
+            .collect();
+        self.position = (self.position + AUDIO_FRAME_SAMPLES) % self.samples.len();
+
+        return Ok(chunk);
+    }
+}
+
+/// Writes received sound to a 16-bit mono WAV file, finished when dropped.
+pub struct WavOutput {
+    writer: WavWriter<BufWriter<File>>,
+}
+
+impl WavOutput {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: AUDIO_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        let writer = WavWriter::create(path, spec)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+        return Ok(Self { writer });
+    }
+}
+
+impl AudioSink for WavOutput {
+    fn play(&mut self, samples: &[i16]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for &sample in samples {
+            self.writer.write_sample(sample)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Discards everything played.
+pub struct NullOutput;
+
+impl AudioSink for NullOutput {
+    fn play(&mut self, _samples: &[i16]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        return Ok(());
+    }
+}
+
+/// Holds one remote stream's audio packets and hands them out one per
+/// playback tick, in sequence order.
+///
+/// Playback starts once the target latency's worth of packets is buffered.
+/// A packet missing when its turn comes is played as silence, so the
+/// stream keeps its timing; when the buffer runs dry it fills up again
+/// before playing on.
+#[derive(Debug)]
+pub struct AudioPlayout {
+    target_packets: usize,
+    packets: BTreeMap<u32, Vec<i16>>,
+    /// Sequence of the packet due next, once playing.
+    next_sequence: Option<u32>,
+}
+
+impl AudioPlayout {
+    pub fn new(target_latency: Duration) -> Self {
+        let target_packets = target_latency.as_millis() / AUDIO_FRAME_DURATION.as_millis();
+
+        Self {
+            target_packets: (target_packets as usize).clamp(1, MAX_BUFFERED_PACKETS),
+            packets: BTreeMap::new(),
+            next_sequence: None,
+        }
+    }
+
+    pub fn push(&mut self, sequence: u32, samples: Vec<i16>) {
+        if self.next_sequence.is_some_and(|next| sequence < next) {
+            return;
+        }
+
+        self.packets.insert(sequence, samples);
+
+        while self.packets.len() > MAX_BUFFERED_PACKETS {
+            self.packets.pop_first();
+        }
+    }
+
+    /// The samples to play this tick, or `None` while buffering.
+    pub fn pop(&mut self) -> Option<Vec<i16>> {
+        let next_sequence = match self.next_sequence {
+            Some(next_sequence) => next_sequence,
+            None if self.packets.len() >= self.target_packets => *self.packets.keys().next()?,
+            None => return None,
+        };
+
+        if self.packets.is_empty() {
+            self.next_sequence = None;
+            return None;
+        }
+
+        self.next_sequence = Some(next_sequence.wrapping_add(1));
+
+        return Some(
+            self.packets
+                .remove(&next_sequence)
+                .unwrap_or_else(|| vec![0; AUDIO_FRAME_SAMPLES]),
+        );
+    }
+}
+
+/// Sums the streams' samples, clipping at full scale.
+pub fn mix(chunks: &[Vec<i16>]) -> Vec<i16> {
+    let len = chunks.iter().map(Vec::len).max().unwrap_or(0);
+    let mut mixed = vec![0i16; len];
+
+    for chunk in chunks {
+        for (mixed, &sample) in mixed.iter_mut().zip(chunk) {
+            *mixed = mixed.saturating_add(sample);
+        }
+    }
+
+    return mixed;
+}
+
+/// Converts a stream of mono samples between sample rates by linear
+/// interpolation, carrying its position across calls.
+pub struct Resampler {
+    /// Input samples advanced per output sample.
+    step: f64,
+    /// Position of the next output sample, where 0 is the last sample of
+    /// the previous input and 1 the first of the next.
+    position: f64,
+    previous: f32,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Self {
+            step: from_rate as f64 / to_rate as f64,
+            position: 1.0,
+            previous: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let Some(&last) = input.last() else {
+            return;
+        };
+
+        let previous = self.previous;
+        let sample_at = |index: usize| match index {
+            0 => previous,
+            _ => input[index - 1],
+        };
+
+        while self.position <= input.len() as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+
+            // Only a position exactly on the last sample reaches past it.
+            let sample = match fraction > 0.0 {
+                true => sample_at(index) * (1.0 - fraction) + sample_at(index + 1) * fraction,
+                false => sample_at(index),
+            };
+
+            output.push(sample);
+            self.position += self.step;
+        }
+
+        self.position -= input.len() as f64;
+        self.previous = last;
+    }
+}
+
+/// Averages interleaved frames of `channels` samples down to one channel.
+pub fn downmix(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+
+    return interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+}
+
+pub fn to_i16(sample: f32) -> i16 {
+    return (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+}
+
+/// Microphone and speakers through the platform's audio API.
+#[cfg(feature = "sound-hardware")]
+mod device {
+    use std::{
+        collections::VecDeque,
+        error::Error,
+        sync::{Arc, Mutex, mpsc},
+        thread,
+    };
+
+    use async_trait::async_trait;
+    use cpal::{
+        FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig,
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+    };
+    use shared::{AUDIO_FRAME_SAMPLES, AUDIO_SAMPLE_RATE};
+    use tokio::sync::mpsc as tokio_mpsc;
+
+    use super::{AudioSink, AudioSource, Resampler, downmix, to_i16};
+
+    /// Longest the speakers' queue may grow to, in device samples per
+    /// channel, before the oldest sound is skipped: half a second at 48 kHz.
+    const MAX_QUEUED_SAMPLES: usize = 24_000;
+
+    pub struct Microphone {
+        captured: tokio_mpsc::UnboundedReceiver<Vec<f32>>,
+        resampler: Resampler,
+        pending: Vec<f32>,
+        /// Dropped to stop the capture thread.
+        _stop: mpsc::Sender<()>,
+    }
+
+    pub struct Speakers {
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        resampler: Resampler,
+        resampled: Vec<f32>,
+        _stop: mpsc::Sender<()>,
+    }
+
+    impl Microphone {
+        pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+            let (captured_tx, captured) = tokio_mpsc::unbounded_channel();
+
+            let (stop, sample_rate) = run_stream(move || {
+                let device = cpal::default_host()
+                    .default_input_device()
+                    .ok_or("No microphone found")?;
+                let supported = device.default_input_config()?;
+                let config = supported.config();
+                let channels = config.channels as usize;
+
+                let stream = match supported.sample_format() {
+                    SampleFormat::F32 => {
+                        input_stream::<f32>(&device, &config, channels, captured_tx)
+                    }
+                    SampleFormat::I16 => {
+                        input_stream::<i16>(&device, &config, channels, captured_tx)
+                    }
+                    SampleFormat::U16 => {
+                        input_stream::<u16>(&device, &config, channels, captured_tx)
+                    }
+                    format => {
+                        return Err(format!("Unsupported microphone format {}", format).into());
+                    }
+                }?;
+
+                return Ok((stream, config.sample_rate.0));
+            })?;
+
+            return Ok(Self {
+                captured,
+                resampler: Resampler::new(sample_rate, AUDIO_SAMPLE_RATE),
+                pending: Vec::new(),
+                _stop: stop,
+            });
+        }
+    }
+
+    #[async_trait]
+    impl AudioSource for Microphone {
+        async fn next_chunk(&mut self) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+            while self.pending.len() < AUDIO_FRAME_SAMPLES {
+                let captured = self.captured.recv().await.ok_or("Microphone stopped")?;
+                self.resampler.process(&captured, &mut self.pending);
+            }
+
+            return Ok(self
+                .pending
+                .drain(..AUDIO_FRAME_SAMPLES)
+                .map(to_i16)
+                .collect());
+        }
+    }
+
+    impl Speakers {
+        pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let stream_queue = queue.clone();
+
+            let (stop, sample_rate) = run_stream(move || {
+                let device = cpal::default_host()
+                    .default_output_device()
+                    .ok_or("No speakers found")?;
+                let supported = device.default_output_config()?;
+                let config = supported.config();
+                let channels = config.channels as usize;
+
+                let stream = match supported.sample_format() {
+                    SampleFormat::F32 => {
+                        output_stream::<f32>(&device, &config, channels, stream_queue)
+                    }
+                    SampleFormat::I16 => {
+                        output_stream::<i16>(&device, &config, channels, stream_queue)
+                    }
+                    SampleFormat::U16 => {
+                        output_stream::<u16>(&device, &config, channels, stream_queue)
+                    }
+                    format => return Err(format!("Unsupported speaker format {}", format).into()),
+                }?;
+
+                return Ok((stream, config.sample_rate.0));
+            })?;
+
+            return Ok(Self {
+                queue,
+                resampler: Resampler::new(AUDIO_SAMPLE_RATE, sample_rate),
+                resampled: Vec::new(),
+                _stop: stop,
+            });
+        }
+    }
+
+    impl AudioSink for Speakers {
+        fn play(&mut self, samples: &[i16]) -> Result<(), Box<dyn Error + Send + Sync>> {
+            let samples: Vec<f32> = samples.iter().map(|&s| f32::from_sample(s)).collect();
+
+            self.resampled.clear();
+            self.resampler.process(&samples, &mut self.resampled);
+
+            let mut queue = self.queue.lock().map_err(|_| "Speaker queue poisoned")?;
+            queue.extend(&self.resampled);
+
+            let excess = queue.len().saturating_sub(MAX_QUEUED_SAMPLES);
+            queue.drain(..excess);
+
+            return Ok(());
+        }
+    }
+
+    /// Builds and plays a stream on its own thread, since streams cannot
+    /// move between threads, and keeps it playing until the returned sender
+    /// is dropped. Also returns the stream's sample rate.
+    fn run_stream<F>(build: F) -> Result<(mpsc::Sender<()>, u32), Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce() -> Result<(Stream, u32), Box<dyn Error + Send + Sync>> + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::sync_channel(1);
+
+        thread::spawn(move || {
+            let stream = match build().and_then(|(stream, sample_rate)| {
+                stream.play()?;
+                Ok((stream, sample_rate))
+            }) {
+                Ok((stream, sample_rate)) => {
+                    let _ = started_tx.send(Ok(sample_rate));
+                    stream
+                }
+                Err(e) => {
+                    let _ = started_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+
+            // Returns once the sender is dropped.
+            let _ = stopped.recv();
+            drop(stream);
+        });
+
+        let sample_rate = started.recv().map_err(|_| "Audio thread exited")??;
+
+        return Ok((stop, sample_rate));
+    }
+
+    fn input_stream<T>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        channels: usize,
+        captured: tokio_mpsc::UnboundedSender<Vec<f32>>,
+    ) -> Result<Stream, Box<dyn Error + Send + Sync>>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let stream = device.build_input_stream(
+            config,
+            move |data: &[T], _| {
+                let samples: Vec<f32> = data.iter().map(|&s| s.to_sample::<f32>()).collect();
+                let _ = captured.send(downmix(&samples, channels));
+            },
+            |e| eprintln!("Microphone error: {}", e),
+            None,
+        )?;
+
+        return Ok(stream);
+    }
+
+    fn output_stream<T>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        channels: usize,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+    ) -> Result<Stream, Box<dyn Error + Send + Sync>>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = match queue.lock() {
+                    Ok(queue) => queue,
+                    Err(_) => return,
+                };
+
+                for frame in data.chunks_mut(channels) {
+                    let sample = T::from_sample(queue.pop_front().unwrap_or(0.0));
+                    frame.fill(sample);
+                }
+            },
+            |e| eprintln!("Speaker error: {}", e),
+            None,
+        )?;
+
+        return Ok(stream);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(value: i16) -> Vec<i16> {
+        vec![value; AUDIO_FRAME_SAMPLES]
+    }
+
+    fn first(samples: Option<Vec<i16>>) -> Option<i16> {
+        samples.map(|samples| samples[0])
+    }
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!("none".parse(), Ok(AudioInputSpec::None));
+        assert_eq!(
+            "wav:in.wav".parse(),
+            Ok(AudioInputSpec::Wav(PathBuf::from("in.wav")))
+        );
+        assert_eq!("null".parse(), Ok(AudioOutputSpec::Null));
+        assert_eq!(
+            "wav:out.wav".parse(),
+            Ok(AudioOutputSpec::Wav(PathBuf::from("out.wav")))
+        );
+        assert!("mp3:in.mp3".parse::<AudioInputSpec>().is_err());
+    }
+
+    #[test]
+    fn plays_in_order_after_buffering() {
+        let mut playout = AudioPlayout::new(AUDIO_FRAME_DURATION * 2);
+
+        playout.push(11, packet(11));
+        assert_eq!(first(playout.pop()), None);
+
+        playout.push(10, packet(10));
+        assert_eq!(first(playout.pop()), Some(10));
+        assert_eq!(first(playout.pop()), Some(11));
+
+        // Dry, so buffers again.
+        assert_eq!(first(playout.pop()), None);
+    }
+
+    #[test]
+    fn fills_lost_packets_with_silence_and_drops_late_ones() {
+        let mut playout = AudioPlayout::new(AUDIO_FRAME_DURATION);
+
+        playout.push(0, packet(1));
+        playout.push(2, packet(3));
+
+        assert_eq!(first(playout.pop()), Some(1));
+        assert_eq!(first(playout.pop()), Some(0));
+
+        // Packet 1 arrives after its turn was played as silence.
+        playout.push(1, packet(2));
+        assert_eq!(first(playout.pop()), Some(3));
+    }
+
+    #[test]
+    fn mixes_with_clipping() {
+        let mixed = mix(&[vec![100, i16::MAX], vec![-50, 10, 7]]);
+
+        assert_eq!(mixed, vec![50, i16::MAX, 7]);
+    }
+
+    #[test]
+    fn resamples_across_calls() {
+        let input: Vec<f32> = (0..480).map(|i| i as f32).collect();
+        let mut resampler = Resampler::new(48_000, 16_000);
+        let mut output = Vec::new();
+
+        for chunk in input.chunks(100) {
+            resampler.process(chunk, &mut output);
+        }
+
+        assert_eq!(output.len(), 160);
+        assert!(output.iter().enumerate().all(|(i, &s)| s == (i * 3) as f32));
+    }
+
+    #[tokio::test]
+    async fn wav_output_reads_back_as_input() {
+        let path = std::env::temp_dir().join(format!("audio-test-{}.wav", std::process::id()));
+        let samples: Vec<i16> = (0..AUDIO_FRAME_SAMPLES as i16).map(|i| i * 50).collect();
+
+        {
+            let mut output = WavOutput::new(&path).unwrap();
+            output.play(&samples).unwrap();
+        }
+
+        let mut input = WavInput::new(&path).unwrap();
+        let chunk = input.next_chunk().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let error = chunk
+            .iter()
+            .zip(&samples)
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(error <= 1, "largest error {}", error);
+    }
+}
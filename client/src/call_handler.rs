@@ -1,54 +1,233 @@
-use crossterm::terminal::{self};
-use shared::{RoomStreamID, StreamID, TcpCommandType, read_command_from_tcp_stream};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal::{self},
+};
+use shared::{
+    CHAT_PROTOCOL_VERSION, COMPOSITE_RSID, Capabilities, ChatScope, Features, FrameFormats,
+    FrameReassembler, MAX_DATAGRAM_LEN, MEDIA_HEADER_LEN, MediaHeader, MediaKind, Message,
+    MessageStream, ProtocolError, Resolution, RoomMode, RoomStreamID, StreamID, VideoDecoder,
+    VideoEncoder, VideoFormat, VideoFrame, decode_audio, encode_audio, fragment_frame,
+    parity_fragments,
+};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 use tokio::sync::watch;
-use tokio::time::{Duration, Instant, sleep};
+use tokio::time::{Duration, Instant, MissedTickBehavior, sleep};
 use tokio::{net::UdpSocket, sync::Mutex};
 
-use crate::ascii_converter::{AsciiConverter, HEIGHT, WIDTH};
-use crate::camera::CameraKind;
-use crate::camera::{MAX_FRAME_RATE, RealCamera, TestCamera, TestPatten};
+use crate::ascii_converter::{
+    AsciiConverter, ColorMode, DEFAULT_RESOLUTION, GlyphRenderer, RendererKind,
+};
+use crate::audio::{AUDIO_FRAME_DURATION, AudioInputSpec, AudioOutputSpec, AudioPlayout, mix};
+use crate::camera::{MAX_FRAME_RATE, SourceSpec};
+use crate::character_ramp::CharacterRamp;
+use crate::chat::{ChatPanel, notification_line, overlay_panel, panel_width};
+use crate::concealment::{fade, fade_amount};
+use crate::dither::Dither;
+use crate::enhancement::EnhancementChain;
+use crate::jitter_buffer::JitterBuffer;
+use crate::media_receiver::MediaReceiver;
+use crate::rate_control::{FramePacer, RateBounds, RateController};
+
+/// How long a partially received frame is kept waiting for its remaining
+/// fragments.
+const REASSEMBLY_DEADLINE: Duration = Duration::from_millis(250);
+
+/// Shortest time between two keyframe requests for the same stream.
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the screen is redrawn with whatever frames have come due.
+const RENDER_INTERVAL: Duration = Duration::from_millis(1000 / 60);
+
+/// How often each remote stream's reception is reported to its sender.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the key reader checks whether the call has ended.
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct CallHandler {}
 
+/// Choices made on the command line that shape a call.
+#[derive(Clone)]
+pub struct CallSettings {
+    pub source: SourceSpec,
+    pub audio_input: AudioInputSpec,
+    pub audio_output: AudioOutputSpec,
+    /// Largest grid to send video at.
+    pub max_resolution: Resolution,
+    pub rate_bounds: RateBounds,
+    /// How long remote frames are held to smooth out uneven arrival.
+    pub target_latency: Duration,
+    /// Data fragments covered by each parity fragment sent, or `None` to
+    /// send no parity.
+    pub fec_group_size: Option<u8>,
+    pub color_mode: ColorMode,
+    pub renderer: RendererKind,
+    pub dither: Dither,
+    pub ramp: CharacterRamp,
+    /// Chain the call starts with, and returns to when reset.
+    pub enhancement: EnhancementChain,
+}
+
+/// What the client and server agreed on in the handshake.
+#[derive(Clone, Copy)]
+pub struct Negotiated {
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+}
+
+/// Keeps the terminal in raw mode, so hotkeys arrive without Enter, until
+/// dropped.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct RemoteStream {
+    /// Frame currently on screen.
+    frame: VideoFrame,
+    /// When `frame` came off the jitter buffer.
+    shown_at: Instant,
+    /// How faded `frame` was last drawn.
+    drawn_fade: f64,
+    jitter_buffer: JitterBuffer,
+    audio: AudioPlayout,
+    receiver: MediaReceiver,
+    reassembler: FrameReassembler,
+    decoder: VideoDecoder,
+    last_keyframe_request: Option<Instant>,
+}
+
+impl RemoteStream {
+    fn new(capabilities: &Capabilities, target_latency: Duration) -> Self {
+        Self {
+            frame: empty_frame(),
+            shown_at: Instant::now(),
+            drawn_fade: 0.0,
+            jitter_buffer: JitterBuffer::new(target_latency.as_millis() as u64),
+            audio: AudioPlayout::new(target_latency),
+            receiver: MediaReceiver::default(),
+            reassembler: FrameReassembler::new(REASSEMBLY_DEADLINE, capabilities.max_frame_size),
+            decoder: VideoDecoder::default(),
+            last_keyframe_request: None,
+        }
+    }
+
+    /// Whether enough time has passed to ask this stream's sender for
+    /// another keyframe.
+    fn should_request_keyframe(&mut self) -> bool {
+        let now = Instant::now();
+
+        if let Some(last_keyframe_request) = self.last_keyframe_request
+            && now.duration_since(last_keyframe_request) < KEYFRAME_REQUEST_INTERVAL
+        {
+            return false;
+        }
+
+        self.last_keyframe_request = Some(now);
+        return true;
+    }
+}
+
 impl CallHandler {
-    pub async fn handle_call(
+    pub async fn handle_call<S: AsyncRead + AsyncWrite + Unpin>(
         room_name: &str,
         sid: StreamID,
-        test_pattern: Option<TestPatten>,
-        tcp_stream: &mut TcpStream,
+        mode: RoomMode,
+        negotiated: Negotiated,
+        settings: &CallSettings,
+        stream: &mut MessageStream<S>,
         udp_socket: UdpSocket,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Negotiated {
+            protocol_version,
+            capabilities,
+        } = negotiated;
         let udp_socket = udp_socket;
+        let max_resolution = settings.max_resolution;
+        let dither = settings.dither;
+        let renderer = settings.renderer.renderer(dither, settings.ramp.clone());
         let udp_socket_arc = Arc::new(udp_socket);
 
         println!("Joining {}...", room_name);
-        println!("Starting camera ASCII feed... Press Ctrl+C to exit");
+        println!("Starting camera ASCII feed... Press q or Ctrl+C to leave");
+        println!("Tab selects an enhancement stage, Space toggles it, +/- adjust it, r resets");
 
-        let mut camera = match test_pattern {
-            Some(test_camera_type) => {
-                CameraKind::Test(TestCamera::new(WIDTH, HEIGHT, test_camera_type)?)
-            }
-            None => CameraKind::Real(RealCamera::new()?),
+        // Servers from before chat would not understand it, so it stays off.
+        let chat_supported = protocol_version >= CHAT_PROTOCOL_VERSION;
+        if chat_supported {
+            println!("t opens the chat line, Enter sends it to the room and Esc closes it");
+        }
+
+        let mut camera = settings.source.open(max_resolution).await?;
+        println!("Video source initialized successfully!");
+
+        // Servers without audio drop audio packets, so none are sent to them.
+        let (audio_source, audio_sink) = match capabilities.features.contains(Features::AUDIO) {
+            true => (
+                settings.audio_input.open()?,
+                Some(settings.audio_output.open()?),
+            ),
+            false => (None, None),
         };
-        println!("Camera initialized successfully!");
 
         let mut ascii_converter = AsciiConverter::new();
+        let mut chat = ChatPanel::default();
 
-        let (current_frame_tx, mut current_frame_rx) = watch::channel(Vec::new());
+        let (enhancement_tx, enhancement_rx) = watch::channel(settings.enhancement.clone());
+
+        let _raw_mode = RawMode::enable()?;
+        let (key_tx, mut key_rx) = mpsc::unbounded_channel();
+        let reading_keys = Arc::new(AtomicBool::new(true));
+        let key_task = tokio::task::spawn_blocking({
+            let reading_keys = reading_keys.clone();
+            move || read_keys(key_tx, reading_keys)
+        });
+
+        let (current_frame_tx, mut current_frame_rx) = watch::channel(empty_frame());
+        let (requested_resolution_tx, requested_resolution_rx) = watch::channel(None);
+        let mut preferred_resolution = None;
+        let (supported_frame_formats_tx, supported_frame_formats_rx) = watch::channel(None);
+        let (rate_tx, rate_rx) = watch::channel(RateController::new(settings.rate_bounds));
+        let mut report_interval = tokio::time::interval(REPORT_INTERVAL);
+        let mut render_interval = tokio::time::interval(RENDER_INTERVAL);
+        render_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let target_latency = settings.target_latency;
+        let fec_group_size = settings.fec_group_size;
+
+        let keyframe_requested = Arc::new(AtomicBool::new(false));
+        let keyframe_requested_clone = keyframe_requested.clone();
+        let (keyframe_request_tx, mut keyframe_request_rx) = mpsc::unbounded_channel();
 
         let socket_receiver = udp_socket_arc.clone();
+        let audio_socket = udp_socket_arc.clone();
         let socket_sender = udp_socket_arc;
 
         let (task_ender_tx, mut send_task_ender) = broadcast::channel(1);
         let mut recv_task_ender = send_task_ender.resubscribe();
+        let mut audio_send_task_ender = send_task_ender.resubscribe();
+        let mut audio_play_task_ender = send_task_ender.resubscribe();
 
         let send_task = tokio::spawn(async move {
-            let mut udp_payload = Vec::with_capacity(1500);
-            let mut frame_count = 0u64;
+            let mut udp_payload = Vec::with_capacity(MAX_DATAGRAM_LEN);
+            let mut pacer = FramePacer::default();
+            let mut sequence = 0u32;
+            let mut encoder = VideoEncoder::new(rate_rx.borrow().keyframe_interval());
 
             loop {
                 tokio::select! {
@@ -59,20 +238,38 @@ impl CallHandler {
                     _ = async {
                         let start_time = Instant::now();
 
-                        udp_payload.clear();
-                        udp_payload.extend_from_slice(&sid);
-
-                        let frame = match camera.get_frame().await {
+                        let frame = match camera.next_frame().await {
                             Ok(f) => f,
                             Err(e) => {
                                 eprintln!("Failed to get frame: {}", e);
                                 return;
                             }
                         };
+                        let timestamp = unix_millis();
 
-                        frame_count += 1;
-                        if frame_count % 2 == 0 {
-                            let frame_bytes = match AsciiConverter::frame_to_nibbles(frame) {
+                        let rate = *rate_rx.borrow();
+                        if pacer.ready(start_time, rate.frame_interval()) {
+                            let full_resolution = match *requested_resolution_rx.borrow() {
+                                Some(requested) => max_resolution.min(requested),
+                                None => max_resolution,
+                            };
+                            let supported_frame_formats = *supported_frame_formats_rx.borrow();
+                            let format = send_format(capabilities, supported_frame_formats);
+
+                            // A keyframe of this size fits the negotiated
+                            // maximum, and deltas are only sent when smaller.
+                            let resolution = format.fit_frame_size(
+                                rate.resolution(full_resolution),
+                                capabilities.max_frame_size as usize,
+                            );
+
+                            let mut cells = match AsciiConverter::frame_to_luminance(
+                                frame,
+                                resolution,
+                                format,
+                                dither,
+                                &enhancement_rx.borrow(),
+                            ) {
                                 Ok(fb) => fb,
                                 Err(e) => {
                                     eprintln!("Failed to convert frame: {}", e);
@@ -80,14 +277,63 @@ impl CallHandler {
                                 }
                             };
 
-                            udp_payload.extend_from_slice(&frame_bytes);
+                            if format.has_color() {
+                                match AsciiConverter::frame_to_colors(frame, resolution) {
+                                    Ok(colors) => cells.extend(colors),
+                                    Err(e) => {
+                                        eprintln!("Failed to convert frame colors: {}", e);
+                                        return;
+                                    }
+                                }
+                            }
 
-                            if let Err(e) = current_frame_tx.send(frame_bytes) {
-                                eprintln!("Error sending to current_frame_tx: {}", e);
-                                return;
+                            let video_frame = VideoFrame { resolution, format, cells };
+
+                            encoder.set_keyframe_interval(rate.keyframe_interval());
+                            if keyframe_requested_clone.swap(false, Ordering::Relaxed) {
+                                encoder.request_keyframe();
                             }
 
-                            if let Err(_) = socket_sender.send(&udp_payload).await {
+                            let (kind, frame_bytes) = encoder.encode(sequence, &video_frame);
+
+                            let fragments = match fragment_frame(
+                                kind,
+                                sequence,
+                                timestamp,
+                                &frame_bytes,
+                            ) {
+                                Ok(fragments) => fragments,
+                                Err(e) => {
+                                    eprintln!("Failed to fragment frame: {}", e);
+                                    return;
+                                }
+                            };
+                            sequence = sequence.wrapping_add(1);
+
+                            let parity = match fec_group_size {
+                                Some(group_size) => parity_fragments(&fragments, group_size),
+                                None => Vec::new(),
+                            };
+
+                            let datagrams = fragments.iter().copied().chain(
+                                parity
+                                    .iter()
+                                    .map(|(header, payload)| (*header, payload.as_slice())),
+                            );
+
+                            for (header, payload) in datagrams {
+                                udp_payload.clear();
+                                udp_payload.extend_from_slice(&sid);
+                                header.encode(&mut udp_payload);
+                                udp_payload.extend_from_slice(payload);
+
+                                if socket_sender.send(&udp_payload).await.is_err() {
+                                    return;
+                                }
+                            }
+
+                            if let Err(e) = current_frame_tx.send(video_frame) {
+                                eprintln!("Error sending to current_frame_tx: {}", e);
                                 return;
                             }
                         }
@@ -103,13 +349,98 @@ impl CallHandler {
             }
         });
 
-        let woppa_dopaa: Arc<Mutex<HashMap<RoomStreamID, Vec<u8>>>> =
+        let woppa_dopaa: Arc<Mutex<HashMap<RoomStreamID, RemoteStream>>> =
             Arc::new(Mutex::new(HashMap::new()));
         let woppa_dopaa_clone = woppa_dopaa.clone();
+        let audio_streams = woppa_dopaa.clone();
+
+        let audio_send_task = tokio::spawn(async move {
+            let mut audio_source = match audio_source {
+                Some(audio_source) => audio_source,
+                None => return,
+            };
+
+            let mut udp_payload = Vec::with_capacity(MAX_DATAGRAM_LEN);
+            let mut sequence = 0u32;
+            let mut step_index = 0u8;
+
+            loop {
+                tokio::select! {
+                    _ = audio_send_task_ender.recv() => {
+                        return;
+                    }
+
+                    result = audio_source.next_chunk() => {
+                        let samples = match result {
+                            Ok(samples) => samples,
+                            Err(e) => {
+                                eprintln!("Failed to capture audio: {}", e);
+                                return;
+                            }
+                        };
+
+                        let payload = encode_audio(&samples, &mut step_index);
+                        let header = MediaHeader {
+                            kind: MediaKind::Audio,
+                            sequence,
+                            timestamp: unix_millis(),
+                            fragment_index: 0,
+                            fragment_count: 1,
+                            payload_len: payload.len() as u16,
+                        };
+                        sequence = sequence.wrapping_add(1);
+
+                        udp_payload.clear();
+                        udp_payload.extend_from_slice(&sid);
+                        header.encode(&mut udp_payload);
+                        udp_payload.extend_from_slice(&payload);
+
+                        if audio_socket.send(&udp_payload).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        let audio_play_task = tokio::spawn(async move {
+            let mut audio_sink = match audio_sink {
+                Some(audio_sink) => audio_sink,
+                None => return,
+            };
+
+            let mut playback_interval = tokio::time::interval(AUDIO_FRAME_DURATION);
+
+            loop {
+                tokio::select! {
+                    _ = audio_play_task_ender.recv() => {
+                        return;
+                    }
+
+                    _ = playback_interval.tick() => {
+                        let chunks: Vec<Vec<i16>> = audio_streams
+                            .lock()
+                            .await
+                            .values_mut()
+                            .filter_map(|remote_stream| remote_stream.audio.pop())
+                            .collect();
+
+                        if chunks.is_empty() {
+                            continue;
+                        }
+
+                        if let Err(e) = audio_sink.play(&mix(&chunks)) {
+                            eprintln!("Failed to play audio: {}", e);
+                            return;
+                        }
+                    }
+                }
+            }
+        });
 
         let recv_task = tokio::spawn({
             async move {
-                let mut buf = [0u8; 1500];
+                let mut buf = [0u8; MAX_DATAGRAM_LEN];
 
                 loop {
                     tokio::select! {
@@ -120,11 +451,59 @@ impl CallHandler {
                         result = socket_receiver.recv(&mut buf) => {
                             match result {
                                 Ok(n) => {
+                                    if n < 1 + MEDIA_HEADER_LEN {
+                                        continue;
+                                    }
+
                                     let user_stream_id = buf[0];
-                                    let frame_from_network_bytes = &buf[1..n];
+                                    let (header, fragment) = match MediaHeader::decode(&buf[1..n]) {
+                                        Ok(packet) => packet,
+                                        Err(_) => continue,
+                                    };
+
                                     let mut guard = woppa_dopaa.lock().await;
-                                    if let Some(x) = guard.get_mut(&[user_stream_id]) {
-                                        *x = Vec::from(frame_from_network_bytes);
+                                    let remote_stream = match guard.get_mut(&[user_stream_id]) {
+                                        Some(remote_stream) => remote_stream,
+                                        None => continue,
+                                    };
+
+                                    if header.kind == MediaKind::Audio {
+                                        if let Ok(samples) = decode_audio(fragment) {
+                                            remote_stream.audio.push(header.sequence, samples);
+                                        }
+                                        continue;
+                                    }
+
+                                    let frame_option =
+                                        remote_stream.reassembler.push(&header, fragment);
+
+                                    let frame = match frame_option {
+                                        Some(frame) => frame,
+                                        None => continue,
+                                    };
+
+                                    let arrival = unix_millis();
+
+                                    if !remote_stream.receiver.accept(
+                                        frame.sequence,
+                                        frame.timestamp,
+                                        arrival,
+                                    ) {
+                                        continue;
+                                    }
+
+                                    match remote_stream.decoder.decode(&frame) {
+                                        Ok(video_frame) => remote_stream.jitter_buffer.push(
+                                            frame.timestamp,
+                                            video_frame,
+                                            arrival,
+                                        ),
+                                        Err(ProtocolError::MissingKeyframe(_)) => {
+                                            if remote_stream.should_request_keyframe() {
+                                                let _ = keyframe_request_tx.send([user_stream_id]);
+                                            }
+                                        }
+                                        Err(_) => continue,
                                     }
                                 }
                                 Err(_) => {
@@ -139,43 +518,201 @@ impl CallHandler {
 
         loop {
             tokio::select! {
-                result = read_command_from_tcp_stream(tcp_stream) => {
-                    let command = match result? {
-                        Some(command) => command,
-                        None => {
+                result = stream.read_message() => {
+                    let message = match result {
+                        Ok(Some(message)) => message,
+                        Ok(None) => {
                             break;
                         },
+                        Err(e) if e.is_recoverable() => continue,
+                        Err(e) => return Err(e.into()),
                     };
 
-                    match command {
-                        shared::TcpCommand::WithRoomStreamIDPayload {command_type: TcpCommandType::OtherUserJoinedRoom, payload } => {
-                            let rsid = payload;
-                            woppa_dopaa_clone.lock().await.insert(rsid, vec![]);
+                    match message {
+                        Message::OtherUserJoinedRoom { rsid } => {
+                            let remote_stream = RemoteStream::new(&capabilities, target_latency);
+                            woppa_dopaa_clone.lock().await.insert(rsid, remote_stream);
                         },
-                        shared::TcpCommand::WithRoomStreamIDPayload {command_type: TcpCommandType::OtherUserLeftRoom, payload } => {
-                            let rsid = payload;
+                        Message::OtherUserLeftRoom { rsid } => {
                             woppa_dopaa_clone.lock().await.remove(&rsid);
                         },
+                        Message::RequestResolution { resolution } => {
+                            requested_resolution_tx.send_replace(Some(resolution));
+                        },
+                        Message::SupportedFrameFormats { frame_formats } => {
+                            supported_frame_formats_tx.send_replace(Some(frame_formats));
+                        },
+                        Message::KeyframeRequested => {
+                            keyframe_requested.store(true, Ordering::Relaxed);
+                        },
+                        Message::ReceptionFeedback { report } => {
+                            rate_tx.send_modify(|rate| rate.on_report(report));
+                        },
+                        message => {
+                            if let Some(line) = notification_line(&message) {
+                                chat.push(line);
+                            }
+                        }
+                    }
+                }
+
+                Some(rsid) = keyframe_request_rx.recv() => {
+                    stream.write_message(Message::RequestKeyframe { rsid }).await?;
+                }
+
+                _ = report_interval.tick() => {
+                    let reports: Vec<Message> = woppa_dopaa_clone
+                        .lock()
+                        .await
+                        .iter_mut()
+                        .filter_map(|(rsid, remote_stream)| {
+                            remote_stream.receiver.take_report().map(|report| {
+                                Message::ReportReception { rsid: *rsid, report }
+                            })
+                        })
+                        .collect();
+
+                    for message in reports {
+                        stream.write_message(message).await?;
+                    }
+                }
+
+                Some(key) = key_rx.recv() => {
+                    match key.code {
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            break;
+                        },
+                        _ if chat.is_typing() => {
+                            if let Some(text) = chat.handle_key(key) {
+                                let message = Message::SendChat { scope: ChatScope::Room, text };
+                                stream.write_message(message).await?;
+                            }
+                        },
+                        KeyCode::Char('q') => break,
+                        KeyCode::Char('t') if chat_supported => chat.open_input(),
+                        KeyCode::Tab => {
+                            enhancement_tx.send_modify(EnhancementChain::select_next);
+                        },
+                        KeyCode::Char(' ') => {
+                            enhancement_tx.send_modify(EnhancementChain::toggle_selected);
+                        },
+                        KeyCode::Char('+' | '=') => {
+                            enhancement_tx.send_modify(|chain| chain.adjust_selected(1));
+                        },
+                        KeyCode::Char('-') => {
+                            enhancement_tx.send_modify(|chain| chain.adjust_selected(-1));
+                        },
+                        KeyCode::Char('r') => {
+                            enhancement_tx.send_replace(settings.enhancement.clone());
+                        },
                         _ => {}
                     }
                 }
 
-                result = current_frame_rx.changed() => {
-                    result?;
+                _ = render_interval.tick() => {
+                    let mut changed = current_frame_rx.has_changed()?;
+                    changed |= chat.take_changed();
 
                     let mut all_frames = Vec::new();
-                    let current_frame = current_frame_rx.borrow().clone();
+                    let current_frame = current_frame_rx.borrow_and_update().clone();
                     all_frames.push(current_frame);
 
-                    let other_frames_snapshot: Vec<Vec<u8>> = {
-                        let guard = woppa_dopaa_clone.lock().await;
-                        guard.values().cloned().collect()
+                    let stats_line = {
+                        let mut guard = woppa_dopaa_clone.lock().await;
+                        let mut stats_line = format!(
+                            "{} | sending {:.0} fps",
+                            *enhancement_tx.borrow(),
+                            rate_tx.borrow().fps()
+                        );
+
+                        let now = unix_millis();
+                        let shown_at = Instant::now();
+
+                        for (rsid, remote_stream) in guard.iter_mut() {
+                            // Composite rooms send all video as one grid;
+                            // the others' own streams only carry audio.
+                            if mode == RoomMode::Composite && *rsid != COMPOSITE_RSID {
+                                continue;
+                            }
+
+                            if let Some(frame) = remote_stream.jitter_buffer.pop_due(now) {
+                                remote_stream.frame = frame;
+                                remote_stream.shown_at = shown_at;
+                                changed = true;
+                            }
+
+                            // Without a new frame, the last one is held and
+                            // faded so the stream reads as frozen.
+                            let stale = shown_at.duration_since(remote_stream.shown_at);
+                            let fade_by = fade_amount(stale);
+                            if fade_by != remote_stream.drawn_fade {
+                                remote_stream.drawn_fade = fade_by;
+                                changed = true;
+                            }
+
+                            if fade_by > 0.0 {
+                                all_frames.push(fade(&remote_stream.frame, fade_by));
+                            } else {
+                                all_frames.push(remote_stream.frame.clone());
+                            }
+
+                            if !stats_line.is_empty() {
+                                stats_line.push_str(" | ");
+                            }
+                            stats_line.push_str(&format!(
+                                "#{}: {}, {} ({} buffered, {} recovered){}",
+                                rsid[0],
+                                remote_stream.receiver.stats(),
+                                remote_stream.jitter_buffer.stats(),
+                                remote_stream.jitter_buffer.buffered(),
+                                remote_stream.reassembler.recovered(),
+                                if fade_by > 0.0 { ", frozen" } else { "" }
+                            ));
+                        }
+
+                        stats_line
                     };
 
-                    all_frames.extend(other_frames_snapshot.iter().map(|s| s.clone()));
+                    if !changed {
+                        continue;
+                    }
 
                     let (width, height) = terminal::size()?;
-                    let rendered_content = render_frames_to_string(all_frames, width - 1, height - 1);
+
+                    // Ask for as many cells as the renderer can draw, which
+                    // for half-block and Braille is more than the terminal
+                    // has.
+                    let (cells_across, cells_down) = renderer.cells_per_glyph();
+                    let video_area = Resolution::new(
+                        (width - 1).saturating_mul(cells_across),
+                        (height - 2).saturating_mul(cells_down),
+                    );
+                    if preferred_resolution != Some(video_area) {
+                        let message = Message::SetPreferredResolution { resolution: video_area };
+                        stream.write_message(message).await?;
+                        preferred_resolution = Some(video_area);
+                    }
+
+                    let chat_width = match chat.is_visible() {
+                        true => panel_width(width),
+                        false => 0,
+                    };
+
+                    let stats_line: String = stats_line.chars().take((width - 1) as usize).collect();
+                    let mut frames_content = render_frames_to_string(
+                        all_frames,
+                        renderer.as_ref(),
+                        settings.color_mode,
+                        width - 1 - chat_width,
+                        height - 2,
+                    );
+
+                    if chat_width > 0 {
+                        let panel = chat.render(chat_width, height - 2);
+                        frames_content = overlay_panel(&frames_content, &panel, width - chat_width);
+                    }
+
+                    let rendered_content = format!("{}\n{}", frames_content, stats_line);
 
                     if let Err(e) = ascii_converter.update_terminal_smooth(&rendered_content, width, height) {
                         eprintln!("Error updating terminal: {}", e);
@@ -185,43 +722,102 @@ impl CallHandler {
         }
 
         let _ = task_ender_tx.send(());
+        reading_keys.store(false, Ordering::Relaxed);
         recv_task.await?;
         send_task.await?;
+        audio_send_task.await?;
+        audio_play_task.await?;
+        key_task.await?;
 
         Ok(())
     }
 }
 
-fn render_frames_to_string(frames: Vec<Vec<u8>>, width: u16, height: u16) -> String {
-    match frames.len() {
-        1 => {
-            let my_nibbles = &frames[0];
-            AsciiConverter::nibbles_to_ascii(my_nibbles, width, height)
+/// Forwards key presses to the call until `reading` is cleared.
+fn read_keys(key_tx: mpsc::UnboundedSender<KeyEvent>, reading: Arc<AtomicBool>) {
+    while reading.load(Ordering::Relaxed) {
+        match event::poll(KEY_POLL_INTERVAL) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => return,
         }
+
+        if let Ok(Event::Key(key)) = event::read()
+            && key.kind == KeyEventKind::Press
+            && key_tx.send(key).is_err()
+        {
+            return;
+        }
+    }
+}
+
+fn empty_frame() -> VideoFrame {
+    VideoFrame {
+        resolution: DEFAULT_RESOLUTION,
+        format: VideoFormat::NibbleGray,
+        cells: Vec::new(),
+    }
+}
+
+/// Formats to send in, most preferred first.
+const SEND_FORMATS: [VideoFormat; 4] = [
+    VideoFormat::ByteColor,
+    VideoFormat::NibbleColor,
+    VideoFormat::ByteGray,
+    VideoFormat::NibbleGray,
+];
+
+/// Most preferred format both this session and everyone else in the call can
+/// decode.
+fn send_format(capabilities: Capabilities, others: Option<FrameFormats>) -> VideoFormat {
+    let usable = capabilities
+        .frame_formats
+        .intersection(others.unwrap_or(FrameFormats::all()));
+
+    return SEND_FORMATS
+        .into_iter()
+        .find(|format| usable.contains(format.frame_format()))
+        .unwrap_or(VideoFormat::NibbleGray);
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn render_frames_to_string(
+    frames: Vec<VideoFrame>,
+    renderer: &dyn GlyphRenderer,
+    color_mode: ColorMode,
+    width: u16,
+    height: u16,
+) -> String {
+    match frames.len() {
+        1 => renderer.render(&frames[0], color_mode, width, height),
         2 => {
-            let my_nibbles = &frames[0];
-            let your_nibbles = &frames[1];
+            let my_frame = &frames[0];
+            let your_frame = &frames[1];
 
             if width as f64 * 0.38f64 < height as f64 {
-                let frame1 = AsciiConverter::nibbles_to_ascii(my_nibbles, width, (height - 1) / 2);
-                let frame2 =
-                    AsciiConverter::nibbles_to_ascii(your_nibbles, width, (height - 1) / 2);
+                let frame1 = renderer.render(my_frame, color_mode, width, (height - 1) / 2);
+                let frame2 = renderer.render(your_frame, color_mode, width, (height - 1) / 2);
                 format!("{}\n{}", frame1, frame2)
             } else {
-                let frame1 = AsciiConverter::nibbles_to_ascii(my_nibbles, (width - 1) / 2, height);
-                let frame2 =
-                    AsciiConverter::nibbles_to_ascii(your_nibbles, (width - 1) / 2, height);
+                let frame1 = renderer.render(my_frame, color_mode, (width - 1) / 2, height);
+                let frame2 = renderer.render(your_frame, color_mode, (width - 1) / 2, height);
                 frames_side_by_side_to_string(&frame1, &frame2)
             }
         }
         len => {
-            let num_rows = ((len + 1) / 2) as u16;
+            let num_rows = len.div_ceil(2) as u16;
             let frame_height = (height - num_rows + 1) / num_rows;
             let frame_width = (width - 2) / 2;
 
             let ascii_frames: Vec<String> = frames
                 .iter()
-                .map(|f| AsciiConverter::nibbles_to_ascii(f, frame_width, frame_height))
+                .map(|f| renderer.render(f, color_mode, frame_width, frame_height))
                 .collect();
 
             let mut result = String::new();
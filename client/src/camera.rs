@@ -1,30 +1,193 @@
-use std::ops::Mul;
+use std::{
+    error::Error,
+    fmt, fs,
+    ops::Mul,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use tokio::time::{Duration, Instant, sleep};
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    time::{Duration, Instant, sleep},
+};
 
 use crate::ascii_converter::ASCII_CHARS;
+use async_trait::async_trait;
 use clap::ValueEnum;
 use opencv::{
     core::{CV_8UC1, Mat, MatExprTraitConst, MatTrait, MatTraitConst},
-    videoio::{CAP_ANY, VideoCapture, VideoCaptureTrait, VideoCaptureTraitConst},
+    imgcodecs::{IMREAD_COLOR, imread},
+    videoio::{
+        CAP_ANY, CAP_AVFOUNDATION, CAP_DSHOW, CAP_FFMPEG, CAP_GSTREAMER, CAP_MSMF, CAP_PROP_FPS,
+        CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH, CAP_PROP_POS_FRAMES, CAP_V4L2, VideoCapture,
+        VideoCaptureTrait, VideoCaptureTraitConst,
+    },
 };
+use shared::Resolution;
 
 pub const MAX_FRAME_RATE: u64 = 120;
 
-pub enum CameraKind {
-    Real(RealCamera),
-    Test(TestCamera),
+/// Time between frames for sources without a frame rate of their own.
+const FRAME_DURATION: Duration = Duration::from_millis(1000 / MAX_FRAME_RATE);
+
+/// How long each picture of an image source is shown before the next one.
+const IMAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// Path that stands for standard input.
+const STDIN_PATH: &str = "-";
+
+/// Device indices tried when listing cameras.
+const MAX_PROBED_CAMERAS: i32 = 10;
+
+/// Largest width or height accepted from a Y4M header, so a corrupt one
+/// cannot ask for an enormous frame buffer.
+const MAX_Y4M_DIMENSION: usize = 8192;
+
+/// Where the video sent into a call comes from.
+#[async_trait]
+pub trait FrameSource: Send {
+    /// Waits for and returns the next frame, as 8-bit BGR or grayscale.
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn Error + Send + Sync>>;
+}
+
+/// A frame source picked on the command line.
+///
+/// Written as `camera`, `video:PATH`, `image:PATH`, `y4m:PATH` or
+/// `raw:WIDTHxHEIGHT:PATH`, where a `PATH` of `-` reads standard input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceSpec {
+    Camera(CaptureSettings),
+    TestPattern(TestPatten),
+    /// A video file, looped.
+    Video(PathBuf),
+    /// An image, or every image in a directory, looped.
+    Image(PathBuf),
+    /// A YUV4MPEG2 stream, of which only the luma plane is used.
+    Y4m(PathBuf),
+    /// Headerless 8-bit grayscale frames of a fixed size.
+    RawGray {
+        resolution: Resolution,
+        path: PathBuf,
+    },
 }
 
-impl CameraKind {
-    pub async fn get_frame(&mut self) -> Result<&Mat, Box<dyn std::error::Error + Send + Sync>> {
+impl SourceSpec {
+    /// Opens the source. Test patterns are drawn at `resolution`.
+    pub async fn open(
+        &self,
+        resolution: Resolution,
+    ) -> Result<Box<dyn FrameSource>, Box<dyn Error + Send + Sync>> {
         match self {
-            CameraKind::Real(cam) => cam.get_frame().await,
-            CameraKind::Test(cam) => cam.get_frame().await,
+            SourceSpec::Camera(capture) => return Ok(Box::new(RealCamera::new(capture)?)),
+            SourceSpec::TestPattern(test_pattern) => {
+                return Ok(Box::new(TestCamera::new(
+                    resolution.width as i32,
+                    resolution.height as i32,
+                    *test_pattern,
+                )?));
+            }
+            SourceSpec::Video(path) => return Ok(Box::new(VideoFile::new(path)?)),
+            SourceSpec::Image(path) => return Ok(Box::new(ImageFiles::new(path)?)),
+            SourceSpec::Y4m(path) => {
+                let mut reader = open_stream(path).await?;
+                let header = read_y4m_header(&mut reader).await?;
+                return Ok(Box::new(PipedFrames::new(reader, PipedLayout::Y4m(header))));
+            }
+            SourceSpec::RawGray { resolution, path } => {
+                let reader = open_stream(path).await?;
+                let layout = PipedLayout::Raw {
+                    width: resolution.width as usize,
+                    height: resolution.height as usize,
+                };
+                return Ok(Box::new(PipedFrames::new(reader, layout)));
+            }
         }
     }
 }
 
+impl FromStr for SourceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "camera" {
+            return Ok(SourceSpec::Camera(CaptureSettings::default()));
+        }
+
+        let (kind, path) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected camera or KIND:PATH, got '{}'", s))?;
+
+        let spec = match kind {
+            "video" => SourceSpec::Video(PathBuf::from(path)),
+            "image" => SourceSpec::Image(PathBuf::from(path)),
+            "y4m" => SourceSpec::Y4m(PathBuf::from(path)),
+            "raw" => {
+                let (resolution, path) = path
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected raw:WIDTHxHEIGHT:PATH, got '{}'", s))?;
+
+                SourceSpec::RawGray {
+                    resolution: resolution.parse()?,
+                    path: PathBuf::from(path),
+                }
+            }
+            _ => return Err(format!("Unknown source '{}'", kind)),
+        };
+
+        return Ok(spec);
+    }
+}
+
+/// A camera, by OpenCV device index or by path, e.g. `/dev/video2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CameraDevice {
+    Index(i32),
+    Path(String),
+}
+
+/// OpenCV capture backends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CameraBackend {
+    /// Whichever backend OpenCV picks.
+    #[default]
+    #[value(name = "any")]
+    Any,
+    #[value(name = "v4l2")]
+    V4l2,
+    #[value(name = "avfoundation")]
+    AvFoundation,
+    #[value(name = "dshow")]
+    DirectShow,
+    #[value(name = "msmf")]
+    MediaFoundation,
+    #[value(name = "gstreamer")]
+    Gstreamer,
+    #[value(name = "ffmpeg")]
+    Ffmpeg,
+}
+
+/// Which camera to open and how to configure it. Properties left as `None`
+/// keep the device's defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureSettings {
+    pub device: CameraDevice,
+    pub backend: CameraBackend,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+}
+
+/// A camera found by `probe_cameras`, with the mode it opened in.
+#[derive(Debug, Clone)]
+pub struct CameraInfo {
+    pub index: i32,
+    pub backend_name: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
 pub struct RealCamera {
     cam: VideoCapture,
     frame: Mat,
@@ -38,7 +201,7 @@ pub struct TestCamera {
     frame: Mat,
 }
 
-#[derive(Clone, Copy, Debug, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
 pub enum TestPatten {
     #[value(name = "lines")]
     BrokenOldTv,
@@ -48,19 +211,184 @@ pub enum TestPatten {
     PoopPov,
 }
 
+/// A video file played at its own frame rate, from the start again once it
+/// ends.
+pub struct VideoFile {
+    capture: VideoCapture,
+    path: PathBuf,
+    frame_duration: Duration,
+    frame: Mat,
+}
+
+/// Still images, each shown for `IMAGE_DURATION` in turn.
+pub struct ImageFiles {
+    images: Vec<Mat>,
+    index: usize,
+    shown_since: Instant,
+}
+
+/// How frames follow each other in a piped stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PipedLayout {
+    Y4m(Y4mHeader),
+    Raw { width: usize, height: usize },
+}
+
+/// Uncompressed frames read from a file, FIFO or standard input.
+pub struct PipedFrames {
+    reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
+    layout: PipedLayout,
+    luma: Vec<u8>,
+    frame: Mat,
+}
+
+/// What a YUV4MPEG2 header says about the frames after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Y4mHeader {
+    width: usize,
+    height: usize,
+    /// Frames per second as a fraction, if given.
+    frame_rate: Option<(u32, u32)>,
+    /// Bytes of chroma following each frame's luma plane.
+    chroma_len: usize,
+}
+
+impl Default for CameraDevice {
+    fn default() -> Self {
+        CameraDevice::Index(0)
+    }
+}
+
+impl fmt::Display for CameraDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraDevice::Index(index) => write!(f, "camera {}", index),
+            CameraDevice::Path(path) => write!(f, "camera {}", path),
+        }
+    }
+}
+
+impl FromStr for CameraDevice {
+    type Err = String;
+
+    /// Parses a device index, or takes anything else as a path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("Expected a camera index or path".to_string());
+        }
+
+        match s.parse() {
+            Ok(index) => return Ok(CameraDevice::Index(index)),
+            Err(_) => return Ok(CameraDevice::Path(s.to_string())),
+        }
+    }
+}
+
+impl CameraBackend {
+    fn api_preference(self) -> i32 {
+        match self {
+            CameraBackend::Any => CAP_ANY,
+            CameraBackend::V4l2 => CAP_V4L2,
+            CameraBackend::AvFoundation => CAP_AVFOUNDATION,
+            CameraBackend::DirectShow => CAP_DSHOW,
+            CameraBackend::MediaFoundation => CAP_MSMF,
+            CameraBackend::Gstreamer => CAP_GSTREAMER,
+            CameraBackend::Ffmpeg => CAP_FFMPEG,
+        }
+    }
+}
+
 impl RealCamera {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let cam: VideoCapture = VideoCapture::new(0, CAP_ANY)?;
+    pub fn new(capture: &CaptureSettings) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let device = &capture.device;
+        let api_preference = capture.backend.api_preference();
+
+        let cam = match device {
+            CameraDevice::Index(index) => VideoCapture::new(*index, api_preference),
+            CameraDevice::Path(path) => VideoCapture::from_file(path, api_preference),
+        }
+        .map_err(|e| format!("Could not open {}: {}", device, e))?;
 
         if !cam.is_opened()? {
-            return Err("Error: Could not open camera".into());
+            return Err(format!("Could not open {}", device).into());
+        }
+
+        let mut camera = Self {
+            cam,
+            frame: Mat::default(),
+        };
+
+        let properties = [
+            ("width", CAP_PROP_FRAME_WIDTH, capture.width.map(f64::from)),
+            (
+                "height",
+                CAP_PROP_FRAME_HEIGHT,
+                capture.height.map(f64::from),
+            ),
+            ("fps", CAP_PROP_FPS, capture.fps),
+        ];
+
+        for (name, property, value) in properties {
+            if let Some(value) = value {
+                camera.set_property(device, name, property, value)?;
+            }
+        }
+
+        return Ok(camera);
+    }
+
+    fn set_property(
+        &mut self,
+        device: &CameraDevice,
+        name: &str,
+        property: i32,
+        value: f64,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let accepted = self
+            .cam
+            .set(property, value)
+            .map_err(|e| format!("Could not set {} of {} to {}: {}", name, device, value, e))?;
+
+        if !accepted {
+            return Err(
+                format!("{} does not support setting {} to {}", device, name, value).into(),
+            );
+        }
+
+        return Ok(());
+    }
+}
+
+/// Opens every device index up to `MAX_PROBED_CAMERAS` with `backend` and
+/// reports the ones that work.
+pub fn probe_cameras(backend: CameraBackend) -> Vec<CameraInfo> {
+    let mut cameras = Vec::new();
+
+    for index in 0..MAX_PROBED_CAMERAS {
+        let cam = match VideoCapture::new(index, backend.api_preference()) {
+            Ok(cam) => cam,
+            Err(_) => continue,
+        };
+
+        if !cam.is_opened().unwrap_or(false) {
+            continue;
         }
 
-        let frame = Mat::default();
-        Ok(Self { cam, frame })
+        cameras.push(CameraInfo {
+            index,
+            backend_name: cam.get_backend_name().unwrap_or_default(),
+            width: cam.get(CAP_PROP_FRAME_WIDTH).unwrap_or(0.0) as u32,
+            height: cam.get(CAP_PROP_FRAME_HEIGHT).unwrap_or(0.0) as u32,
+            fps: cam.get(CAP_PROP_FPS).unwrap_or(0.0),
+        });
     }
 
-    pub async fn get_frame(&mut self) -> Result<&Mat, Box<dyn std::error::Error + Send + Sync>> {
+    return cameras;
+}
+
+#[async_trait]
+impl FrameSource for RealCamera {
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn Error + Send + Sync>> {
         let start_time = Instant::now();
 
         self.cam.read(&mut self.frame)?;
@@ -69,14 +397,274 @@ impl RealCamera {
             return Err("Empty frame captured".into());
         }
 
-        let target_frame_duration = Duration::from_millis(1000 / MAX_FRAME_RATE);
-        let elapsed = start_time.elapsed();
+        pace(start_time, FRAME_DURATION).await;
 
-        if elapsed < target_frame_duration {
-            sleep(target_frame_duration - elapsed).await;
+        Ok(&self.frame)
+    }
+}
+
+impl VideoFile {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let capture = VideoCapture::from_file(&path.to_string_lossy(), CAP_ANY)?;
+
+        if !capture.is_opened()? {
+            return Err(format!("Could not open video {}", path.display()).into());
         }
 
-        Ok(&self.frame)
+        let fps = capture.get(CAP_PROP_FPS)?;
+        let frame_duration = if fps > 0.0 {
+            Duration::from_secs_f64(1.0 / fps)
+        } else {
+            FRAME_DURATION
+        };
+
+        return Ok(Self {
+            capture,
+            path: path.to_path_buf(),
+            frame_duration,
+            frame: Mat::default(),
+        });
+    }
+}
+
+#[async_trait]
+impl FrameSource for VideoFile {
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn Error + Send + Sync>> {
+        let start_time = Instant::now();
+
+        if !self.capture.read(&mut self.frame)? || self.frame.empty() {
+            self.capture.set(CAP_PROP_POS_FRAMES, 0.0)?;
+
+            if !self.capture.read(&mut self.frame)? || self.frame.empty() {
+                return Err(format!("No frames in {}", self.path.display()).into());
+            }
+        }
+
+        pace(start_time, self.frame_duration).await;
+
+        return Ok(&self.frame);
+    }
+}
+
+impl ImageFiles {
+    /// Loads the image at `path`, or every readable image in it if it is a
+    /// directory, in file name order.
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let paths = if path.is_dir() {
+            let mut paths = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            paths
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut images = Vec::new();
+        for path in paths {
+            let image = imread(&path.to_string_lossy(), IMREAD_COLOR)?;
+
+            if !image.empty() {
+                images.push(image);
+            }
+        }
+
+        if images.is_empty() {
+            return Err(format!("No readable images at {}", path.display()).into());
+        }
+
+        return Ok(Self {
+            images,
+            index: 0,
+            shown_since: Instant::now(),
+        });
+    }
+}
+
+#[async_trait]
+impl FrameSource for ImageFiles {
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn Error + Send + Sync>> {
+        let start_time = Instant::now();
+
+        if self.shown_since.elapsed() >= IMAGE_DURATION {
+            self.index = (self.index + 1) % self.images.len();
+            self.shown_since = start_time;
+        }
+
+        pace(start_time, FRAME_DURATION).await;
+
+        return Ok(&self.images[self.index]);
+    }
+}
+
+impl PipedFrames {
+    fn new(reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>, layout: PipedLayout) -> Self {
+        Self {
+            reader,
+            layout,
+            luma: Vec::new(),
+            frame: Mat::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl FrameSource for PipedFrames {
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn Error + Send + Sync>> {
+        let start_time = Instant::now();
+
+        let (width, height) = match self.layout {
+            PipedLayout::Y4m(header) => {
+                read_y4m_frame(&mut self.reader, &header, &mut self.luma).await?;
+                (header.width, header.height)
+            }
+            PipedLayout::Raw { width, height } => {
+                self.luma.resize(width * height, 0);
+                self.reader.read_exact(&mut self.luma).await?;
+                (width, height)
+            }
+        };
+
+        self.frame =
+            Mat::new_rows_cols_with_data(height as i32, width as i32, &self.luma)?.try_clone()?;
+
+        // Piped input arrives as fast as it is produced, so only files need
+        // slowing down to their frame rate.
+        if let PipedLayout::Y4m(Y4mHeader {
+            frame_rate: Some((numerator, denominator)),
+            ..
+        }) = self.layout
+            && numerator > 0
+        {
+            let frame_duration = Duration::from_secs_f64(denominator as f64 / numerator as f64);
+            pace(start_time, frame_duration).await;
+        }
+
+        return Ok(&self.frame);
+    }
+}
+
+/// Opens `path` for reading, or standard input for `-`.
+async fn open_stream(
+    path: &Path,
+) -> Result<BufReader<Box<dyn AsyncRead + Unpin + Send>>, Box<dyn Error + Send + Sync>> {
+    let reader: Box<dyn AsyncRead + Unpin + Send> = if path.as_os_str() == STDIN_PATH {
+        Box::new(tokio::io::stdin())
+    } else {
+        Box::new(File::open(path).await?)
+    };
+
+    return Ok(BufReader::new(reader));
+}
+
+async fn read_y4m_header(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Y4mHeader, Box<dyn Error + Send + Sync>> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).await?;
+
+    return Ok(parse_y4m_header(&String::from_utf8_lossy(&line))?);
+}
+
+/// Reads the next YUV4MPEG2 frame into `luma`, dropping its chroma.
+async fn read_y4m_frame(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    header: &Y4mHeader,
+    luma: &mut Vec<u8>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Err("Video input ended".into());
+    }
+
+    if !line.starts_with(b"FRAME") {
+        return Err("Expected a Y4M FRAME marker".into());
+    }
+
+    luma.resize(header.width * header.height, 0);
+    reader.read_exact(luma).await?;
+
+    let mut chroma = reader.take(header.chroma_len as u64);
+    tokio::io::copy(&mut chroma, &mut tokio::io::sink()).await?;
+
+    if chroma.limit() > 0 {
+        return Err("Video input ended mid-frame".into());
+    }
+
+    return Ok(());
+}
+
+/// Parses a `YUV4MPEG2 W640 H480 F30:1 C420jpeg ...` header line.
+fn parse_y4m_header(line: &str) -> Result<Y4mHeader, String> {
+    let mut params = line.split_whitespace();
+
+    if params.next() != Some("YUV4MPEG2") {
+        return Err("Not a YUV4MPEG2 stream".to_string());
+    }
+
+    let mut width = None;
+    let mut height = None;
+    let mut frame_rate = None;
+    let mut colorspace = "420jpeg";
+
+    for param in params {
+        let tag = match param.chars().next() {
+            Some(tag) if tag.is_ascii() => tag,
+            _ => return Err(format!("Invalid Y4M header parameter '{}'", param)),
+        };
+        let value = &param[tag.len_utf8()..];
+
+        match tag {
+            'W' => width = value.parse::<usize>().ok(),
+            'H' => height = value.parse::<usize>().ok(),
+            'F' => {
+                frame_rate = value
+                    .split_once(':')
+                    .and_then(|(n, d)| Some((n.parse().ok()?, d.parse().ok()?)));
+            }
+            'C' => colorspace = value,
+            _ => {}
+        }
+    }
+
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+        _ => return Err("Y4M header is missing its size".to_string()),
+    };
+
+    if width > MAX_Y4M_DIMENSION || height > MAX_Y4M_DIMENSION {
+        return Err(format!(
+            "Y4M frames of {}x{} are larger than the {}x{} supported",
+            width, height, MAX_Y4M_DIMENSION, MAX_Y4M_DIMENSION
+        ));
+    }
+
+    let half_width = width.div_ceil(2);
+    let half_height = height.div_ceil(2);
+
+    let chroma_len = match colorspace {
+        "420jpeg" | "420paldv" | "420mpeg2" | "420" => 2 * half_width * half_height,
+        "422" => 2 * half_width * height,
+        "444" => 2 * width * height,
+        "444alpha" => 3 * width * height,
+        "mono" => 0,
+        _ => return Err(format!("Unsupported Y4M colorspace '{}'", colorspace)),
+    };
+
+    return Ok(Y4mHeader {
+        width,
+        height,
+        frame_rate,
+        chroma_len,
+    });
+}
+
+/// Sleeps until `frame_duration` has passed since `start_time`.
+async fn pace(start_time: Instant, frame_duration: Duration) {
+    let elapsed = start_time.elapsed();
+
+    if elapsed < frame_duration {
+        sleep(frame_duration - elapsed).await;
     }
 }
 
@@ -95,8 +683,11 @@ impl TestCamera {
             frame,
         })
     }
+}
 
-    pub async fn get_frame(&mut self) -> Result<&Mat, Box<dyn std::error::Error + Send + Sync>> {
+#[async_trait]
+impl FrameSource for TestCamera {
+    async fn next_frame(&mut self) -> Result<&Mat, Box<dyn std::error::Error + Send + Sync>> {
         let start_time = Instant::now();
         self.frame_count += 1;
         let time = self.frame_count * 20 / MAX_FRAME_RATE as i32;
@@ -147,7 +738,7 @@ impl TestCamera {
                             + (x as f32 / 10.0 + time as f32 / 3.0).sin() * amp3)
                             as i32;
 
-                        let current_y = y as i32;
+                        let current_y = y;
 
                         let mut count = 0;
                         if (current_y - wave1_y).abs() <= thickness {
@@ -173,14 +764,109 @@ impl TestCamera {
             }
         }
 
-        let target_frame_duration = Duration::from_millis(1000 / MAX_FRAME_RATE);
-        let elapsed = start_time.elapsed();
-
-        if elapsed < target_frame_duration {
-            sleep(target_frame_duration - elapsed).await;
-        }
+        pace(start_time, FRAME_DURATION).await;
 
         self.frame = output;
         Ok(&self.frame)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_source_specs() {
+        assert_eq!(
+            "camera".parse(),
+            Ok(SourceSpec::Camera(CaptureSettings::default()))
+        );
+        assert_eq!(
+            "video:clips/call.mp4".parse(),
+            Ok(SourceSpec::Video(PathBuf::from("clips/call.mp4")))
+        );
+        assert_eq!("y4m:-".parse(), Ok(SourceSpec::Y4m(PathBuf::from("-"))));
+        assert_eq!(
+            "raw:64x48:/tmp/frames".parse(),
+            Ok(SourceSpec::RawGray {
+                resolution: Resolution::new(64, 48),
+                path: PathBuf::from("/tmp/frames"),
+            })
+        );
+        assert!("raw:/tmp/frames".parse::<SourceSpec>().is_err());
+        assert!("webcam:1".parse::<SourceSpec>().is_err());
+    }
+
+    #[test]
+    fn parses_camera_devices() {
+        assert_eq!("2".parse(), Ok(CameraDevice::Index(2)));
+        assert_eq!(
+            "/dev/video2".parse(),
+            Ok(CameraDevice::Path("/dev/video2".to_string()))
+        );
+        assert!("".parse::<CameraDevice>().is_err());
+    }
+
+    #[test]
+    fn parses_y4m_headers() {
+        assert_eq!(
+            parse_y4m_header("YUV4MPEG2 W5 H3 F30000:1001 Ip A1:1 C420jpeg\n"),
+            Ok(Y4mHeader {
+                width: 5,
+                height: 3,
+                frame_rate: Some((30000, 1001)),
+                chroma_len: 2 * 3 * 2,
+            })
+        );
+        assert_eq!(
+            parse_y4m_header("YUV4MPEG2 W4 H2 Cmono").map(|header| header.chroma_len),
+            Ok(0)
+        );
+        assert!(parse_y4m_header("YUV4MPEG2 W4 H2 C420p10").is_err());
+        assert!(parse_y4m_header("YUV4MPEG2 W4").is_err());
+        assert!(parse_y4m_header("P5 4 2 255").is_err());
+        assert!(parse_y4m_header("YUV4MPEG2 W4 H2 \u{FFFD}x").is_err());
+        assert!(parse_y4m_header("YUV4MPEG2 W100000 H100000").is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_y4m_luma_and_skips_chroma() {
+        let mut stream = b"YUV4MPEG2 W2 H2 C420\n".to_vec();
+        stream.extend(b"FRAME\n\x01\x02\x03\x04\x80\x80");
+        stream.extend(b"FRAME Ixyz\n\x05\x06\x07\x08\x80\x80");
+        let mut reader = BufReader::new(&stream[..]);
+
+        let header = read_y4m_header(&mut reader).await.unwrap();
+        let mut luma = Vec::new();
+
+        read_y4m_frame(&mut reader, &header, &mut luma)
+            .await
+            .unwrap();
+        assert_eq!(luma, [1, 2, 3, 4]);
+
+        read_y4m_frame(&mut reader, &header, &mut luma)
+            .await
+            .unwrap();
+        assert_eq!(luma, [5, 6, 7, 8]);
+
+        assert!(
+            read_y4m_frame(&mut reader, &header, &mut luma)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_y4m_frames() {
+        let stream = b"YUV4MPEG2 W2 H2 C420\nFRAME\n\x01\x02\x03\x04\x80";
+        let mut reader = BufReader::new(&stream[..]);
+
+        let header = read_y4m_header(&mut reader).await.unwrap();
+
+        assert!(
+            read_y4m_frame(&mut reader, &header, &mut Vec::new())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fs, path::Path};
+
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use clap::ValueEnum;
+
+use crate::ascii_converter::ASCII_CHARS;
+
+/// Pixel height glyphs are rasterized at when measuring their coverage.
+const CALIBRATION_SCALE: f32 = 64.0;
+
+/// Characters used to draw luminance, from darkest to brightest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharacterRamp {
+    chars: Vec<char>,
+}
+
+/// Built-in ramps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RampPreset {
+    /// The original 16 characters.
+    #[value(name = "default")]
+    Default,
+    /// Paul Bourke's 10 characters.
+    #[value(name = "classic")]
+    Classic,
+    /// Paul Bourke's 70 characters.
+    #[value(name = "detailed")]
+    Detailed,
+    /// Unicode shade blocks.
+    #[value(name = "blocks")]
+    Blocks,
+}
+
+impl RampPreset {
+    pub fn ramp(self) -> CharacterRamp {
+        let chars = match self {
+            RampPreset::Default => ASCII_CHARS.to_vec(),
+            RampPreset::Classic => " .:-=+*#%@".chars().collect(),
+            RampPreset::Detailed => {
+                " .'`^\",:;Il!i><~+_-?][}{1)(|\\/tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$"
+                    .chars()
+                    .collect()
+            }
+            RampPreset::Blocks => " ░▒▓█".chars().collect(),
+        };
+
+        CharacterRamp { chars }
+    }
+}
+
+impl Default for CharacterRamp {
+    fn default() -> Self {
+        RampPreset::Default.ramp()
+    }
+}
+
+impl CharacterRamp {
+    /// Uses `chars` as given, darkest first.
+    pub fn new(chars: &str) -> Result<Self, String> {
+        let chars: Vec<char> = chars.chars().collect();
+
+        if chars.len() < 2 {
+            return Err("A character ramp needs at least two characters".to_string());
+        }
+
+        return Ok(Self { chars });
+    }
+
+    /// Orders `chars` by how much of a cell each one inks when drawn in the
+    /// font at `font_path`, emptiest first.
+    pub fn calibrate(
+        chars: &str,
+        font_path: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let font = FontVec::try_from_vec(fs::read(font_path)?)?;
+        let scaled_font = font.as_scaled(PxScale::from(CALIBRATION_SCALE));
+
+        let cell_width = scaled_font.h_advance(font.glyph_id('M'));
+        let cell_area = cell_width * (scaled_font.ascent() - scaled_font.descent());
+
+        let mut coverages = Vec::new();
+        for c in chars.chars() {
+            let glyph_id = font.glyph_id(c);
+
+            if glyph_id.0 == 0 && !c.is_whitespace() {
+                return Err(format!("'{}' has no glyph in {}", c, font_path.display()).into());
+            }
+
+            let mut inked = 0.0;
+            if glyph_id.0 != 0
+                && let Some(outline) = font.outline_glyph(scaled_font.scaled_glyph(c))
+            {
+                outline.draw(|_, _, coverage| inked += coverage);
+            }
+
+            coverages.push((c, inked / cell_area.max(1.0)));
+        }
+
+        let ordered = order_by_coverage(coverages);
+        return Ok(Self::new(&ordered.into_iter().collect::<String>())?);
+    }
+
+    /// Character for 8-bit `luminance`.
+    pub fn glyph(&self, luminance: u8) -> char {
+        let index = (luminance as usize * (self.chars.len() - 1) + 127) / 255;
+        return self.chars[index];
+    }
+}
+
+/// Characters sorted by ascending coverage, keeping the first of any
+/// duplicates.
+fn order_by_coverage(mut coverages: Vec<(char, f32)>) -> Vec<char> {
+    let mut seen = Vec::new();
+    coverages.retain(|(c, _)| {
+        let first = !seen.contains(c);
+        seen.push(*c);
+        first
+    });
+
+    coverages.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    return coverages.into_iter().map(|(c, _)| c).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_ramp_matches_nibble_levels() {
+        let ramp = CharacterRamp::default();
+
+        for (level, &c) in ASCII_CHARS.iter().enumerate() {
+            assert_eq!(ramp.glyph(level as u8 * 17), c);
+        }
+    }
+
+    #[test]
+    fn glyph_spans_whole_ramp() {
+        let ramp = RampPreset::Detailed.ramp();
+
+        assert_eq!(ramp.glyph(0), ' ');
+        assert_eq!(ramp.glyph(255), '$');
+    }
+
+    #[test]
+    fn rejects_short_ramps() {
+        assert!(CharacterRamp::new("#").is_err());
+        assert!(CharacterRamp::new(" #").is_ok());
+    }
+
+    #[test]
+    fn orders_by_coverage_and_drops_duplicates() {
+        let coverages = vec![('#', 0.6), (' ', 0.0), ('.', 0.05), ('#', 0.6), (':', 0.1)];
+
+        assert_eq!(order_by_coverage(coverages), vec![' ', '.', ':', '#']);
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+use crossterm::event::{KeyCode, KeyEvent};
+use shared::{ChatScope, MAX_CHAT_LEN, Message};
+
+/// Chat lines kept for the sidebar; older ones scroll away.
+const MAX_HISTORY: usize = 100;
+
+/// Narrowest and widest the chat sidebar is drawn, in columns.
+const MIN_PANEL_WIDTH: u16 = 20;
+const MAX_PANEL_WIDTH: u16 = 40;
+
+/// One line describing a chat or direct message the server sent without
+/// being asked, as shown at the prompt and in the sidebar. `None` for any
+/// other message.
+pub fn notification_line(message: &Message) -> Option<String> {
+    let line = match message {
+        Message::ChatMessage {
+            scope: ChatScope::Lobby,
+            sender,
+            timestamp,
+            text,
+        } => format!(
+            "{} [lobby] {}: {}",
+            clock(*timestamp),
+            sender,
+            printable(text)
+        ),
+        Message::ChatMessage {
+            scope: ChatScope::Room,
+            sender,
+            timestamp,
+            text,
+        } => format!(
+            "{} [room] {}: {}",
+            clock(*timestamp),
+            sender,
+            printable(text)
+        ),
+        Message::DirectMessageReceived {
+            sender,
+            timestamp,
+            text,
+        } => format!(
+            "{} [from {}] {}",
+            clock(*timestamp),
+            sender,
+            printable(text)
+        ),
+        Message::DirectMessageDelivered {
+            recipient,
+            timestamp,
+        } => format!("{} [to {}] delivered", clock(*timestamp), recipient),
+        Message::InvalidChat { reason } | Message::InvalidDirectMessage { reason } => {
+            reason.clone()
+        }
+        _ => return None,
+    };
+
+    return Some(line);
+}
+
+/// Local time of day of a server timestamp.
+fn clock(timestamp: u64) -> String {
+    return DateTime::from_timestamp_millis(timestamp as i64)
+        .map(|time| time.with_timezone(&Local).format("%H:%M").to_string())
+        .unwrap_or_else(|| "--:--".to_string());
+}
+
+/// `text` without control characters. The server refuses them, but they
+/// would let anyone else move the cursor around this terminal, so they are
+/// never printed.
+fn printable(text: &str) -> String {
+    return text.chars().filter(|c| !c.is_control()).collect();
+}
+
+/// Columns the chat sidebar takes up in a terminal `width` columns wide.
+pub fn panel_width(width: u16) -> u16 {
+    return (width / 3)
+        .clamp(MIN_PANEL_WIDTH, MAX_PANEL_WIDTH)
+        .min(width / 2);
+}
+
+/// Chat sidebar shown next to the tiles during a call, with its input line.
+#[derive(Default)]
+pub struct ChatPanel {
+    history: VecDeque<String>,
+    /// Text being typed, or `None` while keys go to the call.
+    input: Option<String>,
+    changed: bool,
+}
+
+impl ChatPanel {
+    pub fn push(&mut self, line: String) {
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(line);
+        self.changed = true;
+    }
+
+    /// Whether the panel takes up room on screen.
+    pub fn is_visible(&self) -> bool {
+        !self.history.is_empty() || self.input.is_some()
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open_input(&mut self) {
+        if self.input.is_none() {
+            self.input = Some(String::new());
+            self.changed = true;
+        }
+    }
+
+    /// Edits the input line with `key`. Returns the text to send when Enter
+    /// finishes a non-empty line; Esc drops it.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<String> {
+        let input = self.input.as_mut()?;
+
+        match key.code {
+            KeyCode::Char(c) if input.len() + c.len_utf8() <= MAX_CHAT_LEN => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => {
+                let text = self.input.take().unwrap_or_default();
+                self.changed = true;
+
+                if !text.trim().is_empty() {
+                    return Some(text);
+                }
+            }
+            _ => return None,
+        }
+
+        self.changed = true;
+        return None;
+    }
+
+    /// Whether anything on the panel changed since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The panel as `height` rows of at most `width` columns: the newest
+    /// history wrapped to fit, above the input line.
+    pub fn render(&self, width: u16, height: u16) -> Vec<String> {
+        let text_width = (width as usize).saturating_sub(2).max(1);
+        let history_height = (height as usize).saturating_sub(1);
+
+        let mut wrapped: Vec<String> = Vec::new();
+        for line in self.history.iter() {
+            let chars: Vec<char> = line.chars().collect();
+
+            for chunk in chars.chunks(text_width) {
+                wrapped.push(chunk.iter().collect());
+            }
+        }
+
+        let skip = wrapped.len().saturating_sub(history_height);
+        let mut rows: Vec<String> = vec![String::new(); history_height - (wrapped.len() - skip)];
+        rows.extend(wrapped.into_iter().skip(skip));
+
+        let input_line = match &self.input {
+            Some(input) => {
+                // Only the end of a long line fits; that is where typing happens.
+                let shown: Vec<char> = input.chars().collect();
+                let start = shown.len().saturating_sub(text_width.saturating_sub(3));
+                format!("> {}_", shown[start..].iter().collect::<String>())
+            }
+            None => "t to chat".to_string(),
+        };
+        rows.push(input_line);
+
+        return rows
+            .into_iter()
+            .take(height as usize)
+            .map(|row| format!("│ {}", row))
+            .collect();
+    }
+}
+
+/// Draws `panel` over the right of `content`, starting at 1-based `column`.
+/// Each row moves the cursor there and clears the rest of the line first,
+/// so the panel lines up whatever width the content's lines have.
+pub fn overlay_panel(content: &str, panel: &[String], column: u16) -> String {
+    let content_lines: Vec<&str> = content.lines().collect();
+    let rows = content_lines.len().max(panel.len());
+
+    let mut result = String::new();
+    for row in 0..rows {
+        if row > 0 {
+            result.push('\n');
+        }
+
+        result.push_str(content_lines.get(row).copied().unwrap_or(""));
+
+        if let Some(panel_line) = panel.get(row) {
+            result.push_str(&format!(
+                "\x1B[0m\x1B[{};{}H\x1B[K{}",
+                row + 1,
+                column,
+                panel_line
+            ));
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn strips_control_characters_from_chat_lines() {
+        let message = Message::ChatMessage {
+            scope: ChatScope::Room,
+            sender: "alice".to_string(),
+            timestamp: 0,
+            text: "hi\x1B[2Jthere".to_string(),
+        };
+
+        let line = notification_line(&message).unwrap();
+
+        assert!(line.ends_with("[room] alice: hi[2Jthere"));
+    }
+
+    #[test]
+    fn describes_direct_messages_and_only_notifications() {
+        let received = Message::DirectMessageReceived {
+            sender: "bob".to_string(),
+            timestamp: 0,
+            text: "psst".to_string(),
+        };
+        let delivered = Message::DirectMessageDelivered {
+            recipient: "bob".to_string(),
+            timestamp: 0,
+        };
+
+        assert!(
+            notification_line(&received)
+                .unwrap()
+                .ends_with("[from bob] psst")
+        );
+        assert!(
+            notification_line(&delivered)
+                .unwrap()
+                .ends_with("[to bob] delivered")
+        );
+        assert_eq!(notification_line(&Message::KeyframeRequested), None);
+    }
+
+    #[test]
+    fn edits_and_sends_the_input_line() {
+        let mut panel = ChatPanel::default();
+        assert_eq!(panel.handle_key(key(KeyCode::Char('x'))), None);
+        assert!(!panel.is_typing());
+
+        panel.open_input();
+        for c in "hey!".chars() {
+            panel.handle_key(key(KeyCode::Char(c)));
+        }
+        panel.handle_key(key(KeyCode::Backspace));
+
+        assert_eq!(
+            panel.handle_key(key(KeyCode::Enter)),
+            Some("hey".to_string())
+        );
+        assert!(!panel.is_typing());
+
+        panel.open_input();
+        assert_eq!(panel.handle_key(key(KeyCode::Enter)), None);
+    }
+
+    #[test]
+    fn renders_the_newest_history_above_the_input_line() {
+        let mut panel = ChatPanel::default();
+        panel.push("first".to_string());
+        panel.push("second line".to_string());
+
+        let rows = panel.render(8, 4);
+
+        assert_eq!(rows, ["│ first", "│ second", "│  line", "│ t to chat"]);
+    }
+
+    #[test]
+    fn overlays_every_row_of_the_panel() {
+        let panel = vec!["│ a".to_string(), "│ b".to_string()];
+
+        let result = overlay_panel("video", &panel, 10);
+
+        assert_eq!(
+            result,
+            "video\x1B[0m\x1B[1;10H\x1B[K│ a\n\x1B[0m\x1B[2;10H\x1B[K│ b"
+        );
+    }
+}
@@ -1,20 +1,26 @@
-use tokio::io::AsyncWriteExt;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::io::{self, AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 use shared::{
-    TCP_PORT, TcpCommand, TcpCommandType, UDP_PORT, read_command_from_tcp_stream,
-    write_command_to_tcp_stream,
+    Capabilities, ChatScope, Message, MessageStream, PROTOCOL_VERSION, TCP_PORT, UDP_PORT,
+    is_supported_protocol_version,
 };
 use tokio::net::{TcpStream, UdpSocket};
 
-use crate::call_handler::CallHandler;
-use crate::camera::TestPatten;
+use crate::call_handler::{CallHandler, CallSettings, Negotiated};
+use crate::camera::{CameraBackend, SourceSpec, probe_cameras};
+use crate::chat::notification_line;
 use crate::user_input_handler::{UserCommand, UserInputHandler};
 
-pub struct Client {
-    tcp_stream: TcpStream,
+pub struct Client<S = TcpStream> {
+    stream: MessageStream<S>,
     udp_socket_option: Option<UdpSocket>,
     username: String,
+    protocol_version: u16,
+    capabilities: Capabilities,
+    /// Kept across prompts, so a line being read while chat arrives is not
+    /// lost.
+    input: Lines<BufReader<Stdin>>,
 }
 
 const PROMPT: &str = "> ";
@@ -23,6 +29,7 @@ impl Client {
     pub async fn connect(
         server_addr: &str,
         username: &str,
+        capabilities: Capabilities,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let server_tcp_addr = format!("{}:{}", server_addr, TCP_PORT);
         let server_udp_addr = format!("{}:{}", server_addr, UDP_PORT);
@@ -30,65 +37,151 @@ impl Client {
         let udp_socket = UdpSocket::bind(&"0.0.0.0:0").await?;
         udp_socket.connect(&server_udp_addr).await?;
 
-        let mut tcp_stream = TcpStream::connect(server_tcp_addr).await?;
+        let tcp_stream = TcpStream::connect(server_tcp_addr).await?;
 
-        let hello_command = TcpCommand::WithStringPayload {
-            command_type: shared::TcpCommandType::HelloFromClient,
-            payload: username.to_string(),
+        return Self::handshake(tcp_stream, udp_socket, username, capabilities).await;
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    /// Performs the hello handshake over an already connected control stream,
+    /// offering `capabilities` to the server.
+    pub async fn handshake(
+        stream: S,
+        udp_socket: UdpSocket,
+        username: &str,
+        capabilities: Capabilities,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut stream = MessageStream::new(stream);
+
+        let hello_message = Message::HelloFromClient {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities,
+            username: username.to_string(),
         };
 
-        write_command_to_tcp_stream(hello_command, &mut tcp_stream).await?;
+        stream.write_message(hello_message).await?;
 
-        let resonse_command = match read_command_from_tcp_stream(&mut tcp_stream).await? {
-            Some(command) => command,
+        let response_message = match stream.read_message().await? {
+            Some(message) => message,
             None => return Err("Server closed the connection".into()),
         };
 
-        let username = match resonse_command {
-            TcpCommand::Simple(TcpCommandType::HelloFromServer) => username.to_string(),
-            TcpCommand::WithStringPayload {
-                command_type: TcpCommandType::InvalidUsername,
-                payload,
-            } => return Err(payload.into()),
+        let (protocol_version, capabilities) = match response_message {
+            Message::HelloFromServer {
+                protocol_version,
+                capabilities,
+            } => (protocol_version, capabilities),
+            Message::InvalidUsername { reason } | Message::IncompatibleProtocol { reason } => {
+                return Err(reason.into());
+            }
             _ => return Err("Server sent invalid response".into()),
         };
 
+        if !is_supported_protocol_version(protocol_version) {
+            return Err(format!(
+                "Server chose protocol version {}, but this client only supports version {}",
+                protocol_version, PROTOCOL_VERSION
+            )
+            .into());
+        }
+
         return Ok(Self {
-            tcp_stream,
-            username,
+            stream,
+            username: username.to_string(),
             udp_socket_option: Some(udp_socket),
+            protocol_version,
+            capabilities,
+            input: BufReader::new(io::stdin()).lines(),
         });
     }
 
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version
+    }
+
+    /// Sends `message` if the negotiated protocol version has it, and
+    /// otherwise tells the user the server does not support `feature`.
+    async fn send_if_supported(
+        &mut self,
+        message: Message,
+        feature: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if message.min_protocol_version() > self.protocol_version {
+            println!("This server does not support {}.", feature);
+            return Ok(());
+        }
+
+        self.stream.write_message(message).await?;
+
+        return Ok(());
+    }
+
+    async fn read_response(&mut self) -> Result<Message, Box<dyn std::error::Error + Send + Sync>> {
+        loop {
+            match self.stream.read_message().await? {
+                Some(Message::MalformedMessage { reason }) => {
+                    return Err(format!(
+                        "Server could not understand the request ({}). Try updating your client.",
+                        reason
+                    )
+                    .into());
+                }
+                Some(message) => match notification_line(&message) {
+                    Some(line) => print_notification(&line),
+                    None => return Ok(message),
+                },
+                None => return Err("Connection closed by the server".into()),
+            }
+        }
+    }
+
+    /// Reads the next command, printing chat that arrives meanwhile above
+    /// the prompt.
+    async fn read_command(&mut self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        print_prompt(PROMPT).await?;
+
+        loop {
+            tokio::select! {
+                result = self.input.next_line() => {
+                    return Ok(result?.unwrap_or_default().trim().to_string());
+                }
+
+                result = self.stream.read_message() => {
+                    match result {
+                        Ok(Some(message)) => {
+                            if let Some(line) = notification_line(&message) {
+                                print_notification(&line);
+                            }
+                        }
+                        Ok(None) => return Err("Connection closed by the server".into()),
+                        Err(e) if e.is_recoverable() => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+
+                    print_prompt(PROMPT).await?;
+                }
+            }
+        }
+    }
+
     pub async fn run(
         &mut self,
-        test_pattern: Option<TestPatten>,
+        settings: &CallSettings,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         loop {
-            let line = read_line(PROMPT).await?;
+            let line = self.read_command().await?;
             match UserInputHandler::handle(&line).await? {
                 UserCommand::Close => {
                     println!("Exiting...");
                     return Ok(());
                 }
                 UserCommand::ListUsers => {
-                    write_command_to_tcp_stream(
-                        TcpCommand::Simple(TcpCommandType::GetActiveUsers),
-                        &mut self.tcp_stream,
-                    )
-                    .await?;
-                    let command_option = read_command_from_tcp_stream(&mut self.tcp_stream).await?;
-
-                    let command = match command_option {
-                        Some(cmd) => cmd,
-                        None => return Err("Connection closed by the server".into()),
-                    };
+                    self.stream.write_message(Message::GetActiveUsers).await?;
+                    let message = self.read_response().await?;
 
-                    let active_users = match command {
-                        TcpCommand::WithMultiStringPayload {
-                            command_type: TcpCommandType::ReturnActiveUsers,
-                            payload,
-                        } => payload,
+                    let active_users = match message {
+                        Message::ReturnActiveUsers { usernames } => usernames,
                         _ => return Err("Invalid response from server".into()),
                     };
 
@@ -110,27 +203,15 @@ impl Client {
                     println!("╚══════════════════════════════════╝\n");
                 }
                 UserCommand::ListRooms => {
-                    write_command_to_tcp_stream(
-                        TcpCommand::Simple(TcpCommandType::GetRooms),
-                        &mut self.tcp_stream,
-                    )
-                    .await?;
-                    let command_option = read_command_from_tcp_stream(&mut self.tcp_stream).await?;
-
-                    let command = match command_option {
-                        Some(cmd) => cmd,
-                        None => return Err("Connection closed by the server".into()),
-                    };
+                    self.stream.write_message(Message::GetRooms).await?;
+                    let message = self.read_response().await?;
 
-                    let rooms = match command {
-                        TcpCommand::WithMultiStringPayload {
-                            command_type: TcpCommandType::ReturnRooms,
-                            payload,
-                        } => payload,
+                    let rooms = match message {
+                        Message::ReturnRooms { rooms } => rooms,
                         _ => return Err("Invalid response from server".into()),
                     };
 
-                    if rooms.len() == 0 {
+                    if rooms.is_empty() {
                         println!("\n╔══════════════════════════════════╗");
                         println!("║ Available Rooms       (total: 0) ║");
                         println!("╚══════════════════════════════════╝\n");
@@ -149,79 +230,86 @@ impl Client {
                         println!("╚══════════════════════════════════╝\n");
                     }
                 }
-                UserCommand::CreateRoom(room_name) => {
-                    let command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::CreateRoom,
-                        payload: room_name.clone(),
+                UserCommand::ListCameras => {
+                    let backend = match &settings.source {
+                        SourceSpec::Camera(capture) => capture.backend,
+                        _ => CameraBackend::Any,
                     };
-                    write_command_to_tcp_stream(command, &mut self.tcp_stream).await?;
 
-                    let command_option = read_command_from_tcp_stream(&mut self.tcp_stream).await?;
+                    println!("Probing cameras...");
+                    let cameras = probe_cameras(backend);
+
+                    let total_string = cameras.len().to_string();
+                    println!("\n╔══════════════════════════════════╗");
+                    println!(
+                        "║ Cameras {}(total: {}) ║",
+                        " ".repeat(15 - total_string.len()),
+                        total_string
+                    );
 
-                    let command = match command_option {
-                        Some(cmd) => cmd,
-                        None => return Err("Connection closed by the server".into()),
+                    if !cameras.is_empty() {
+                        println!("╠══════════════════════════════════╣");
+                    }
+                    for camera in cameras {
+                        let description = format!(
+                            "{}: {}x{} @ {:.0} fps {}",
+                            camera.index,
+                            camera.width,
+                            camera.height,
+                            camera.fps,
+                            camera.backend_name
+                        );
+                        println!("║ • {:30} ║", description);
+                    }
+                    println!("╚══════════════════════════════════╝\n");
+                }
+                UserCommand::CreateRoom(room_name, mode) => {
+                    let message = Message::CreateRoom {
+                        room: room_name.clone(),
+                        mode,
                     };
+                    self.stream.write_message(message).await?;
 
-                    match command {
-                        TcpCommand::WithStringPayload {
-                            command_type: TcpCommandType::InvalidRoomName,
-                            payload,
-                        } => {
-                            println!("{}", payload);
+                    let message = self.read_response().await?;
+
+                    match message {
+                        Message::InvalidRoomName { reason } => {
+                            println!("{}", reason);
                         }
-                        TcpCommand::Simple(TcpCommandType::CreateRoomSuccess) => {
+                        Message::CreateRoomSuccess => {
                             println!("Successfully created room: '{}'", room_name);
                         }
                         _ => return Err("Invalid response from server".into()),
                     };
                 }
                 UserCommand::DeleteRoom(room_name) => {
-                    let command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::DeleteRoom,
-                        payload: room_name.clone(),
+                    let message = Message::DeleteRoom {
+                        room: room_name.clone(),
                     };
-                    write_command_to_tcp_stream(command, &mut self.tcp_stream).await?;
-
-                    let command_option = read_command_from_tcp_stream(&mut self.tcp_stream).await?;
+                    self.stream.write_message(message).await?;
 
-                    let command = match command_option {
-                        Some(cmd) => cmd,
-                        None => return Err("Connection closed by the server".into()),
-                    };
+                    let message = self.read_response().await?;
 
-                    match command {
-                        TcpCommand::WithStringPayload {
-                            command_type: TcpCommandType::InvalidRoomName,
-                            payload,
-                        } => {
-                            println!("{}", payload);
+                    match message {
+                        Message::InvalidRoomName { reason } => {
+                            println!("{}", reason);
                         }
-                        TcpCommand::Simple(TcpCommandType::DeleteRoomSuccess) => {
+                        Message::DeleteRoomSuccess => {
                             println!("Successfully deleted room: '{}'", room_name);
                         }
                         _ => return Err("Invalid response from server".into()),
                     };
                 }
                 UserCommand::JoinRoom(room_name) => {
-                    let command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::JoinRoom,
-                        payload: room_name.clone(),
+                    let message = Message::JoinRoom {
+                        room: room_name.clone(),
                     };
-                    write_command_to_tcp_stream(command, &mut self.tcp_stream).await?;
-
-                    let command_option = read_command_from_tcp_stream(&mut self.tcp_stream).await?;
+                    self.stream.write_message(message).await?;
 
-                    let command = match command_option {
-                        Some(cmd) => cmd,
-                        None => return Err("Connection closed by the server".into()),
-                    };
+                    let message = self.read_response().await?;
 
-                    match command {
-                        TcpCommand::WithStreamIDPayload {
-                            command_type: TcpCommandType::JoinRoomSuccess,
-                            payload,
-                        } => {
+                    match message {
+                        Message::JoinRoomSuccess { sid, mode } => {
                             let udp_socket = self
                                 .udp_socket_option
                                 .take()
@@ -229,40 +317,52 @@ impl Client {
 
                             CallHandler::handle_call(
                                 &room_name,
-                                payload,
-                                test_pattern,
-                                &mut self.tcp_stream,
+                                sid,
+                                mode,
+                                Negotiated {
+                                    protocol_version: self.protocol_version,
+                                    capabilities: self.capabilities,
+                                },
+                                settings,
+                                &mut self.stream,
                                 udp_socket,
                             )
                             .await?;
                             return Ok(());
                         }
-                        TcpCommand::WithStringPayload {
-                            command_type: TcpCommandType::InvalidJoinRoom,
-                            payload,
-                        } => {
-                            println!("{}", payload);
+                        Message::InvalidJoinRoom { reason } => {
+                            println!("{}", reason);
                         }
                         _ => return Err("Invalid response from server".into()),
                     };
                 }
+                UserCommand::Chat(text) => {
+                    let message = Message::SendChat {
+                        scope: ChatScope::Lobby,
+                        text,
+                    };
+                    self.send_if_supported(message, "chat").await?;
+                }
+                UserCommand::DirectMessage(recipient, text) => {
+                    let message = Message::DirectMessage { recipient, text };
+                    self.send_if_supported(message, "direct messages").await?;
+                }
                 UserCommand::KeepAlive => continue,
             }
         }
     }
 }
 
-pub async fn read_line(prompt: &str) -> io::Result<String> {
+async fn print_prompt(prompt: &str) -> io::Result<()> {
     let mut stdout = io::stdout();
     stdout.write_all(prompt.as_bytes()).await?;
     stdout.flush().await?;
 
-    let stdin = io::stdin();
-    let mut reader = BufReader::new(stdin).lines();
+    return Ok(());
+}
 
-    if let Some(line) = reader.next_line().await? {
-        return Ok(line.trim().to_string());
-    } else {
-        return Ok(String::new());
-    }
+/// Prints a line about something the server sent without being asked,
+/// over the prompt.
+fn print_notification(line: &str) {
+    println!("\r\x1B[K{}", line);
 }
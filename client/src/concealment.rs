@@ -0,0 +1,108 @@
+use shared::{VideoFormat, VideoFrame};
+use tokio::time::Duration;
+
+/// How long a remote stream can go without a new frame before it is drawn
+/// faded, so a frozen picture is not mistaken for a still one.
+const FREEZE_AFTER: Duration = Duration::from_millis(500);
+
+/// How long a frozen stream takes to fade as far as it will go.
+const FADE_DURATION: Duration = Duration::from_secs(2);
+
+/// Most a frozen stream is dimmed by, so the last picture stays readable.
+const MAX_FADE: f64 = 0.6;
+
+/// Levels per channel of the 6x6x6 color cube.
+const CUBE_LEVELS: u8 = 6;
+
+/// How much to dim a stream whose last frame arrived `stale` ago, from 0
+/// (not frozen) to `MAX_FADE`.
+pub fn fade_amount(stale: Duration) -> f64 {
+    let frozen_for = match stale.checked_sub(FREEZE_AFTER) {
+        Some(frozen_for) if !frozen_for.is_zero() => frozen_for,
+        _ => return 0.0,
+    };
+
+    let progress = (frozen_for.as_secs_f64() / FADE_DURATION.as_secs_f64()).min(1.0);
+
+    return progress * MAX_FADE;
+}
+
+/// Copy of `frame` with luminance and color dimmed by `amount`, where 0
+/// leaves it unchanged and 1 turns it black.
+pub fn fade(frame: &VideoFrame, amount: f64) -> VideoFrame {
+    let keep = 1.0 - amount.clamp(0.0, 1.0);
+    let mut faded = frame.clone();
+
+    let luminance_len = frame
+        .format
+        .luminance_len(frame.resolution)
+        .min(faded.cells.len());
+    let (luminance, colors) = faded.cells.split_at_mut(luminance_len);
+
+    for byte in luminance.iter_mut() {
+        *byte = match frame.format {
+            VideoFormat::NibbleGray | VideoFormat::NibbleColor => {
+                let high = scale(*byte >> 4, keep);
+                let low = scale(*byte & 0x0f, keep);
+                (high << 4) | low
+            }
+            VideoFormat::ByteGray | VideoFormat::ByteColor => scale(*byte, keep),
+        };
+    }
+
+    if frame.format.has_color() {
+        for index in colors.iter_mut() {
+            let r = *index / (CUBE_LEVELS * CUBE_LEVELS);
+            let g = *index / CUBE_LEVELS % CUBE_LEVELS;
+            let b = *index % CUBE_LEVELS;
+
+            *index = (scale(r, keep) * CUBE_LEVELS + scale(g, keep)) * CUBE_LEVELS + scale(b, keep);
+        }
+    }
+
+    return faded;
+}
+
+fn scale(level: u8, keep: f64) -> u8 {
+    return (level as f64 * keep).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Resolution;
+
+    #[test]
+    fn fades_only_after_freezing() {
+        assert_eq!(fade_amount(Duration::from_millis(100)), 0.0);
+        assert_eq!(fade_amount(FREEZE_AFTER), 0.0);
+        assert!(fade_amount(FREEZE_AFTER + FADE_DURATION / 2) > 0.0);
+        assert_eq!(fade_amount(Duration::from_secs(60)), MAX_FADE);
+    }
+
+    #[test]
+    fn dims_both_nibbles_and_color_channels() {
+        let frame = VideoFrame {
+            resolution: Resolution::new(2, 1),
+            format: VideoFormat::NibbleColor,
+            // Luminance 15 and 8, then white and pure red.
+            cells: vec![0xf8, 215, 5 * 36],
+        };
+
+        let faded = fade(&frame, 0.5);
+
+        assert_eq!(faded.cells, vec![0x84, 3 * 36 + 3 * 6 + 3, 3 * 36]);
+        assert_eq!(fade(&frame, 0.0), frame);
+    }
+
+    #[test]
+    fn dims_byte_luminance() {
+        let frame = VideoFrame {
+            resolution: Resolution::new(3, 1),
+            format: VideoFormat::ByteGray,
+            cells: vec![0, 100, 255],
+        };
+
+        assert_eq!(fade(&frame, 0.5).cells, vec![0, 50, 128]);
+    }
+}
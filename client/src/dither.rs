@@ -0,0 +1,212 @@
+use clap::ValueEnum;
+
+/// 4x4 ordered dither thresholds, in sixteenths.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// How 8-bit luminance is reduced to a handful of levels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Dither {
+    /// Each pixel goes to its nearest level.
+    #[value(name = "none")]
+    None,
+    /// Each pixel's rounding error is spread onto its unvisited neighbours.
+    #[value(name = "floyd-steinberg")]
+    FloydSteinberg,
+    /// Pixels are nudged by a repeating 4x4 threshold pattern.
+    #[value(name = "bayer")]
+    Bayer,
+}
+
+impl Dither {
+    /// Quantizes a row-major `width` x `height` grid of 8-bit `pixels` to
+    /// values in `0..levels`.
+    pub fn quantize(self, pixels: &[u8], width: usize, height: usize, levels: u8) -> Vec<u8> {
+        let levels = levels.max(2);
+        let len = pixels.len().min(width * height);
+
+        match self {
+            Dither::None => {
+                return pixels[..len]
+                    .iter()
+                    .map(|&pixel| nearest_level(pixel as i32, levels))
+                    .collect();
+            }
+            Dither::FloydSteinberg => return floyd_steinberg(&pixels[..len], width, levels),
+            Dither::Bayer => {
+                let step = 255.0 / (levels - 1) as f32;
+
+                return pixels[..len]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &pixel)| {
+                        let threshold = BAYER_4X4[(i / width) % 4][(i % width) % 4];
+                        let offset = ((threshold as f32 + 0.5) / 16.0 - 0.5) * step;
+
+                        nearest_level((pixel as f32 + offset).round() as i32, levels)
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+fn floyd_steinberg(pixels: &[u8], width: usize, levels: u8) -> Vec<u8> {
+    let mut values: Vec<i32> = pixels.iter().map(|&pixel| pixel as i32).collect();
+    let mut quantized = Vec::with_capacity(pixels.len());
+
+    for i in 0..values.len() {
+        let x = i % width;
+        let level = nearest_level(values[i], levels);
+        let error = values[i] - level_value(level, levels);
+        quantized.push(level);
+
+        let mut spread = |index: usize, weight: i32| {
+            if let Some(value) = values.get_mut(index) {
+                *value += error * weight / 16;
+            }
+        };
+
+        if x + 1 < width {
+            spread(i + 1, 7);
+            spread(i + width + 1, 1);
+        }
+        if x > 0 {
+            spread(i + width - 1, 3);
+        }
+        spread(i + width, 5);
+    }
+
+    return quantized;
+}
+
+/// Level in `0..levels` closest to `value`, clamping out of range values.
+fn nearest_level(value: i32, levels: u8) -> u8 {
+    let max_level = (levels - 1) as i32;
+    let level = (value.clamp(0, 255) * max_level + 127) / 255;
+
+    return level as u8;
+}
+
+/// 8-bit luminance that `level` stands for.
+fn level_value(level: u8, levels: u8) -> i32 {
+    return level as i32 * 255 / (levels - 1) as i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 4;
+
+    /// A horizontal ramp from black to white, the same on every row.
+    fn ramp() -> Vec<u8> {
+        (0..HEIGHT)
+            .flat_map(|_| (0..WIDTH).map(|x| (x * 255 / (WIDTH - 1)) as u8))
+            .collect()
+    }
+
+    /// Renders quantized levels as one hex digit per pixel, one row per line.
+    fn to_golden(levels: &[u8]) -> String {
+        levels
+            .chunks(WIDTH)
+            .map(|row| row.iter().map(|level| format!("{:x}", level)).collect())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn none_maps_ramp_to_every_level() {
+        let golden = "\
+0123456789abcdef
+0123456789abcdef
+0123456789abcdef
+0123456789abcdef";
+
+        assert_eq!(
+            to_golden(&Dither::None.quantize(&ramp(), WIDTH, HEIGHT, 16)),
+            golden
+        );
+    }
+
+    #[test]
+    fn none_thresholds_at_half_brightness() {
+        let golden = "\
+0000000011111111
+0000000011111111
+0000000011111111
+0000000011111111";
+
+        assert_eq!(
+            to_golden(&Dither::None.quantize(&ramp(), WIDTH, HEIGHT, 2)),
+            golden
+        );
+    }
+
+    #[test]
+    fn floyd_steinberg_two_levels() {
+        let golden = "\
+0000001010111111
+0000100101101111
+0000010101011111
+0001001011110111";
+
+        assert_eq!(
+            to_golden(&Dither::FloydSteinberg.quantize(&ramp(), WIDTH, HEIGHT, 2)),
+            golden
+        );
+    }
+
+    #[test]
+    fn bayer_two_levels() {
+        let golden = "\
+0000000101010111
+0010101010111111
+0000010101011111
+0000101011111111";
+
+        assert_eq!(
+            to_golden(&Dither::Bayer.quantize(&ramp(), WIDTH, HEIGHT, 2)),
+            golden
+        );
+    }
+
+    #[test]
+    fn dithering_keeps_average_brightness() {
+        let pixels = vec![100u8; WIDTH * HEIGHT];
+
+        for dither in [Dither::FloydSteinberg, Dither::Bayer] {
+            let levels = dither.quantize(&pixels, WIDTH, HEIGHT, 2);
+            let lit = levels.iter().filter(|&&level| level == 1).count();
+
+            // 100 / 255 of the pixels, give or take a row.
+            assert!(
+                lit.abs_diff(WIDTH * HEIGHT * 100 / 255) <= WIDTH,
+                "{:?}: {}",
+                dither,
+                lit
+            );
+        }
+    }
+
+    #[test]
+    fn black_and_white_rows_stay_at_the_extreme_levels() {
+        let pixels: Vec<u8> = (0..HEIGHT)
+            .flat_map(|y| vec![if y % 2 == 0 { 0 } else { 255 }; WIDTH])
+            .collect();
+        let golden = "\
+0000000000000000
+ffffffffffffffff
+0000000000000000
+ffffffffffffffff";
+
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+            assert_eq!(
+                to_golden(&dither.quantize(&pixels, WIDTH, HEIGHT, 16)),
+                golden,
+                "{:?}",
+                dither
+            );
+        }
+    }
+}
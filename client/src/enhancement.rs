@@ -0,0 +1,479 @@
+use core::error::Error;
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr};
+
+use opencv::{
+    core::{AlgorithmHint, BORDER_DEFAULT, Mat, Point_, Size, add_weighted},
+    imgproc::{COLOR_BGR2GRAY, canny, create_clahe, cvt_color, filter_2d, gaussian_blur},
+    prelude::*,
+};
+
+/// One step of the enhancement chain, working on an 8-bit grayscale image.
+///
+/// Stages are written as `name` or `name:key=value,key=value`, e.g.
+/// `clahe:clip=2,tile=8`. Parameters that are left out keep their default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Brightens midtones for `gamma` above 1 and darkens them below.
+    Gamma {
+        gamma: f64,
+    },
+    /// Contrast limited adaptive histogram equalization.
+    Clahe {
+        clip_limit: f64,
+        tile_size: i32,
+    },
+    /// Gaussian blur with an odd `kernel_size`.
+    Blur {
+        kernel_size: i32,
+    },
+    /// 3x3 sharpening kernel. 0 leaves the image unchanged.
+    Sharpen {
+        amount: f64,
+    },
+    /// Adds Canny edges found between thresholds `low` and `high` at `weight`.
+    Edges {
+        weight: f64,
+        low: f64,
+        high: f64,
+    },
+    Invert,
+    /// `pixel * contrast + brightness`.
+    Levels {
+        brightness: f64,
+        contrast: f64,
+    },
+    /// Scales the image so its mean luminance lands on `target`.
+    AutoExposure {
+        target: f64,
+    },
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Gamma { .. } => "gamma",
+            Stage::Clahe { .. } => "clahe",
+            Stage::Blur { .. } => "blur",
+            Stage::Sharpen { .. } => "sharpen",
+            Stage::Edges { .. } => "edges",
+            Stage::Invert => "invert",
+            Stage::Levels { .. } => "levels",
+            Stage::AutoExposure { .. } => "exposure",
+        }
+    }
+
+    /// Nudges the stage's main parameter up or down by `steps` increments.
+    pub fn adjust(&mut self, steps: i32) {
+        let steps = steps as f64;
+
+        match self {
+            Stage::Gamma { gamma } => *gamma = (*gamma + 0.1 * steps).max(0.1),
+            Stage::Clahe { clip_limit, .. } => *clip_limit = (*clip_limit + 0.25 * steps).max(0.25),
+            Stage::Blur { kernel_size } => *kernel_size = (*kernel_size + 2 * steps as i32).max(1),
+            Stage::Sharpen { amount } => *amount = (*amount + 0.1 * steps).max(0.0),
+            Stage::Edges { weight, .. } => *weight = (*weight + 0.05 * steps).max(0.0),
+            Stage::Invert => {}
+            Stage::Levels { contrast, .. } => *contrast = (*contrast + 0.1 * steps).max(0.0),
+            Stage::AutoExposure { target } => *target = (*target + 8.0 * steps).clamp(8.0, 248.0),
+        }
+    }
+
+    pub fn apply(&self, image: &Mat) -> Result<Mat, Box<dyn Error>> {
+        let mut output = Mat::default();
+
+        match *self {
+            Stage::Gamma { gamma } => {
+                return map_pixels(image, |pixel| 255.0 * (pixel / 255.0).powf(1.0 / gamma));
+            }
+            Stage::Clahe {
+                clip_limit,
+                tile_size,
+            } => {
+                let mut clahe = create_clahe(clip_limit, Size::new(tile_size, tile_size))?;
+                clahe.apply(image, &mut output)?;
+            }
+            Stage::Blur { kernel_size } => {
+                gaussian_blur(
+                    image,
+                    &mut output,
+                    Size::new(kernel_size, kernel_size),
+                    0.0,
+                    0.0,
+                    BORDER_DEFAULT,
+                    AlgorithmHint::ALGO_HINT_DEFAULT,
+                )?;
+            }
+            Stage::Sharpen { amount } => {
+                let amount = amount as f32;
+                let kernel_data = [
+                    0.0,
+                    -amount,
+                    0.0,
+                    -amount,
+                    1.0 + 4.0 * amount,
+                    -amount,
+                    0.0,
+                    -amount,
+                    0.0,
+                ];
+                let kernel = Mat::from_slice_2d(&[
+                    &kernel_data[0..3],
+                    &kernel_data[3..6],
+                    &kernel_data[6..9],
+                ])?;
+
+                filter_2d(
+                    image,
+                    &mut output,
+                    -1,
+                    &kernel,
+                    Point_::new(-1, -1),
+                    0.0,
+                    BORDER_DEFAULT,
+                )?;
+            }
+            Stage::Edges { weight, low, high } => {
+                let mut edges = Mat::default();
+                canny(image, &mut edges, low, high, 3, false)?;
+                add_weighted(image, 1.0, &edges, weight, 0.0, &mut output, -1)?;
+            }
+            Stage::Invert => return map_pixels(image, |pixel| 255.0 - pixel),
+            Stage::Levels {
+                brightness,
+                contrast,
+            } => return map_pixels(image, |pixel| pixel * contrast + brightness),
+            Stage::AutoExposure { target } => {
+                let data = image.data_bytes()?;
+                let total: u64 = data.iter().map(|&pixel| pixel as u64).sum();
+                let mean = total as f64 / data.len().max(1) as f64;
+                let gain = target / mean.max(1.0);
+
+                return map_pixels(image, |pixel| pixel * gain);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+
+        match self {
+            Stage::Gamma { gamma } => write!(f, ":gamma={}", gamma),
+            Stage::Clahe {
+                clip_limit,
+                tile_size,
+            } => write!(f, ":clip={},tile={}", clip_limit, tile_size),
+            Stage::Blur { kernel_size } => write!(f, ":size={}", kernel_size),
+            Stage::Sharpen { amount } => write!(f, ":amount={}", amount),
+            Stage::Edges { weight, low, high } => {
+                write!(f, ":weight={},low={},high={}", weight, low, high)
+            }
+            Stage::Invert => Ok(()),
+            Stage::Levels {
+                brightness,
+                contrast,
+            } => write!(f, ":brightness={},contrast={}", brightness, contrast),
+            Stage::AutoExposure { target } => write!(f, ":target={}", target),
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        let name = name.trim();
+        let mut params = StageParams::parse(name, params)?;
+
+        let stage = match name {
+            "gamma" => Stage::Gamma {
+                gamma: params.take("gamma", 1.5),
+            },
+            "clahe" => Stage::Clahe {
+                clip_limit: params.take("clip", 1.5),
+                tile_size: params.take("tile", 8.0) as i32,
+            },
+            "blur" => Stage::Blur {
+                kernel_size: params.take("size", 3.0) as i32,
+            },
+            "sharpen" => Stage::Sharpen {
+                amount: params.take("amount", 0.5),
+            },
+            "edges" => Stage::Edges {
+                weight: params.take("weight", 0.15),
+                low: params.take("low", 100.0),
+                high: params.take("high", 200.0),
+            },
+            "invert" => Stage::Invert,
+            "levels" => Stage::Levels {
+                brightness: params.take("brightness", 0.0),
+                contrast: params.take("contrast", 1.2),
+            },
+            "exposure" => Stage::AutoExposure {
+                target: params.take("target", 128.0),
+            },
+            _ => return Err(format!("Unknown enhancement stage '{}'", name)),
+        };
+
+        params.finish()?;
+
+        let valid = match stage {
+            Stage::Gamma { gamma } => gamma > 0.0,
+            Stage::Clahe {
+                clip_limit,
+                tile_size,
+            } => clip_limit > 0.0 && tile_size >= 1,
+            Stage::Blur { kernel_size } => kernel_size >= 1 && kernel_size % 2 == 1,
+            Stage::Sharpen { amount } => amount >= 0.0,
+            Stage::Edges { weight, low, high } => weight >= 0.0 && low <= high,
+            Stage::Invert => true,
+            Stage::Levels { contrast, .. } => contrast >= 0.0,
+            Stage::AutoExposure { target } => (1.0..=255.0).contains(&target),
+        };
+
+        if !valid {
+            return Err(format!("Invalid parameters for enhancement stage '{}'", s));
+        }
+
+        return Ok(stage);
+    }
+}
+
+/// The `key=value` list of a stage, consumed as the stage reads it.
+struct StageParams<'a> {
+    stage: &'a str,
+    values: HashMap<&'a str, f64>,
+}
+
+impl<'a> StageParams<'a> {
+    fn parse(stage: &'a str, params: &'a str) -> Result<Self, String> {
+        let mut values = HashMap::new();
+
+        for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value in '{}', got '{}'", stage, param))?;
+
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid value '{}' for '{}'", value, key))?;
+
+            values.insert(key.trim(), value);
+        }
+
+        return Ok(Self { stage, values });
+    }
+
+    fn take(&mut self, key: &str, default: f64) -> f64 {
+        return self.values.remove(key).unwrap_or(default);
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.values.keys().next() {
+            Some(key) => Err(format!(
+                "Unknown parameter '{}' for enhancement stage '{}'",
+                key, self.stage
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Ordered enhancement stages applied to every captured frame. Stages can be
+/// switched off and back on without losing their place or parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnhancementChain {
+    stages: Vec<(Stage, bool)>,
+    selected: usize,
+}
+
+impl Default for EnhancementChain {
+    /// CLAHE, a light blur, sharpening and an edge overlay.
+    fn default() -> Self {
+        Self::new(vec![
+            Stage::Clahe {
+                clip_limit: 1.5,
+                tile_size: 8,
+            },
+            Stage::Blur { kernel_size: 3 },
+            Stage::Sharpen { amount: 0.5 },
+            Stage::Edges {
+                weight: 0.15,
+                low: 100.0,
+                high: 200.0,
+            },
+        ])
+    }
+}
+
+impl EnhancementChain {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Self {
+            stages: stages.into_iter().map(|stage| (stage, true)).collect(),
+            selected: 0,
+        }
+    }
+
+    /// Stages from the `config` file, one per line with `#` comments,
+    /// followed by `stages`. Without either, the default chain.
+    pub fn from_config(
+        config: Option<&Path>,
+        stages: Vec<Stage>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let config = match config {
+            Some(config) => config,
+            None if stages.is_empty() => return Ok(Self::default()),
+            None => return Ok(Self::new(stages)),
+        };
+
+        let contents = fs::read_to_string(config)?;
+        let mut loaded = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let stage = line
+                .parse()
+                .map_err(|e| format!("{}:{}: {}", config.display(), line_number + 1, e))?;
+            loaded.push(stage);
+        }
+
+        loaded.extend(stages);
+        return Ok(Self::new(loaded));
+    }
+
+    /// Converts `frame` to grayscale and runs it through every enabled stage.
+    pub fn apply(&self, frame: &Mat) -> Result<Mat, Box<dyn Error>> {
+        let mut image = Mat::default();
+        if frame.channels() != 1 {
+            cvt_color(
+                frame,
+                &mut image,
+                COLOR_BGR2GRAY,
+                0,
+                AlgorithmHint::ALGO_HINT_DEFAULT,
+            )?;
+        } else {
+            image = frame.clone();
+        }
+
+        for (stage, enabled) in &self.stages {
+            if *enabled {
+                image = stage.apply(&image)?;
+            }
+        }
+
+        Ok(image)
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.stages.is_empty() {
+            self.selected = (self.selected + 1) % self.stages.len();
+        }
+    }
+
+    pub fn toggle_selected(&mut self) {
+        if let Some((_, enabled)) = self.stages.get_mut(self.selected) {
+            *enabled = !*enabled;
+        }
+    }
+
+    pub fn adjust_selected(&mut self, steps: i32) {
+        if let Some((stage, _)) = self.stages.get_mut(self.selected) {
+            stage.adjust(steps);
+        }
+    }
+}
+
+impl fmt::Display for EnhancementChain {
+    /// The selected stage in brackets, disabled stages crossed out with `-`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (stage, enabled)) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+
+            let disabled = if *enabled { "" } else { "-" };
+
+            if i == self.selected {
+                write!(f, "[{}{}]", disabled, stage)?;
+            } else {
+                write!(f, "{}{}", disabled, stage.name())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps every pixel of an 8-bit `image` through `f`, clamping the result.
+fn map_pixels(image: &Mat, f: impl Fn(f64) -> f64) -> Result<Mat, Box<dyn Error>> {
+    let mut table = [0u8; 256];
+    for (pixel, entry) in table.iter_mut().enumerate() {
+        *entry = f(pixel as f64).round().clamp(0.0, 255.0) as u8;
+    }
+
+    let mut output = image.try_clone()?;
+    for pixel in output.data_bytes_mut()? {
+        *pixel = table[*pixel as usize];
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_defaults_and_parameters() {
+        assert_eq!("blur".parse(), Ok(Stage::Blur { kernel_size: 3 }));
+        assert_eq!(
+            "clahe:clip=2, tile=4".parse(),
+            Ok(Stage::Clahe {
+                clip_limit: 2.0,
+                tile_size: 4
+            })
+        );
+        assert_eq!("invert".parse(), Ok(Stage::Invert));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for stage in [
+            "gamma:gamma=1.8",
+            "edges:weight=0.3,low=50,high=150",
+            "invert",
+        ] {
+            let parsed: Stage = stage.parse().unwrap();
+            assert_eq!(parsed.to_string().parse(), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_stages() {
+        assert!("sparkle".parse::<Stage>().is_err());
+        assert!("blur:size=4".parse::<Stage>().is_err());
+        assert!("blur:radius=3".parse::<Stage>().is_err());
+        assert!("gamma:gamma=bright".parse::<Stage>().is_err());
+    }
+
+    #[test]
+    fn hotkey_edits_only_touch_selected_stage() {
+        let mut chain = EnhancementChain::default();
+        chain.select_next();
+        chain.toggle_selected();
+        chain.adjust_selected(1);
+
+        assert_eq!(chain.to_string(), "clahe [-blur:size=5] sharpen edges");
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, fmt};
+
+use shared::VideoFrame;
+
+/// Most frames held for one stream. Anything beyond this is more than a
+/// second of video at normal frame rates, so the oldest are dropped.
+const MAX_BUFFERED_FRAMES: usize = 64;
+
+/// Holds one remote stream's decoded frames until their playout time, so they
+/// are shown spaced as they were captured rather than as they arrived.
+///
+/// A frame's playout time is its capture timestamp, shifted onto the local
+/// clock by the smallest transit time seen so far, plus the target latency.
+/// The smallest transit stands for a frame that met no queueing on the way,
+/// so every frame delayed by less than the target latency still plays on
+/// time.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    target_latency_ms: u64,
+    /// Frames waiting to be shown, by capture timestamp.
+    frames: BTreeMap<u64, VideoFrame>,
+    /// Smallest arrival time minus capture time seen, in milliseconds.
+    base_transit: Option<i64>,
+    /// Capture timestamp of the last frame shown.
+    last_played: Option<u64>,
+    stats: PlayoutStats,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PlayoutStats {
+    pub played: u64,
+    /// Frames that arrived after their playout time.
+    pub late: u64,
+    /// Frames never shown, because a newer one was shown first or the
+    /// buffer overflowed.
+    pub dropped: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(target_latency_ms: u64) -> Self {
+        Self {
+            target_latency_ms,
+            frames: BTreeMap::new(),
+            base_transit: None,
+            last_played: None,
+            stats: PlayoutStats::default(),
+        }
+    }
+
+    /// Queues `frame`, captured at `timestamp` and arrived at `arrival` (both
+    /// in milliseconds since the Unix epoch).
+    pub fn push(&mut self, timestamp: u64, frame: VideoFrame, arrival: u64) {
+        let transit = arrival as i64 - timestamp as i64;
+        let base_transit = match self.base_transit {
+            Some(base_transit) => base_transit.min(transit),
+            None => transit,
+        };
+        self.base_transit = Some(base_transit);
+
+        if arrival > self.playout_time(timestamp) {
+            self.stats.late += 1;
+        }
+
+        if self
+            .last_played
+            .is_some_and(|last_played| timestamp <= last_played)
+        {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        self.frames.insert(timestamp, frame);
+
+        while self.frames.len() > MAX_BUFFERED_FRAMES {
+            self.frames.pop_first();
+            self.stats.dropped += 1;
+        }
+    }
+
+    /// The newest frame whose playout time has come by `now`, if any. Older
+    /// frames that also came due are skipped, so a render clock slower than
+    /// the stream shows the latest picture instead of falling behind.
+    pub fn pop_due(&mut self, now: u64) -> Option<VideoFrame> {
+        let mut due = None;
+
+        while let Some(&timestamp) = self.frames.keys().next()
+            && self.playout_time(timestamp) <= now
+        {
+            if due.is_some() {
+                self.stats.dropped += 1;
+            }
+            due = self.frames.pop_first();
+        }
+
+        let (timestamp, frame) = due?;
+        self.last_played = Some(timestamp);
+        self.stats.played += 1;
+
+        return Some(frame);
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn stats(&self) -> PlayoutStats {
+        self.stats
+    }
+
+    fn playout_time(&self, timestamp: u64) -> u64 {
+        let base_transit = self.base_transit.unwrap_or(0);
+
+        return (timestamp as i64 + base_transit) as u64 + self.target_latency_ms;
+    }
+}
+
+impl fmt::Display for PlayoutStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} played, {} late, {} dropped",
+            self.played, self.late, self.dropped
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Resolution, VideoFormat};
+
+    const LATENCY: u64 = 100;
+
+    /// A frame told apart from others by its single cell.
+    fn frame(id: u8) -> VideoFrame {
+        VideoFrame {
+            resolution: Resolution::new(1, 1),
+            format: VideoFormat::ByteGray,
+            cells: vec![id],
+        }
+    }
+
+    fn id(frame: Option<VideoFrame>) -> Option<u8> {
+        frame.map(|frame| frame.cells[0])
+    }
+
+    #[test]
+    fn holds_frames_for_target_latency() {
+        let mut buffer = JitterBuffer::new(LATENCY);
+
+        buffer.push(1_000, frame(1), 1_020);
+
+        assert_eq!(id(buffer.pop_due(1_119)), None);
+        assert_eq!(id(buffer.pop_due(1_120)), Some(1));
+        assert_eq!(buffer.buffered(), 0);
+    }
+
+    #[test]
+    fn smooths_out_bursty_arrival() {
+        let mut buffer = JitterBuffer::new(LATENCY);
+
+        // Captured 40ms apart, but the second and third arrive together.
+        buffer.push(1_000, frame(1), 1_020);
+        buffer.push(1_040, frame(2), 1_130);
+        buffer.push(1_080, frame(3), 1_130);
+
+        assert_eq!(id(buffer.pop_due(1_120)), Some(1));
+        assert_eq!(id(buffer.pop_due(1_140)), None);
+        assert_eq!(id(buffer.pop_due(1_160)), Some(2));
+        assert_eq!(id(buffer.pop_due(1_200)), Some(3));
+        assert_eq!(buffer.stats().late, 0);
+    }
+
+    #[test]
+    fn plays_newest_due_frame_and_drops_the_rest() {
+        let mut buffer = JitterBuffer::new(LATENCY);
+
+        for i in 0..4 {
+            buffer.push(1_000 + i * 10, frame(i as u8), 1_020 + i * 10);
+        }
+
+        assert_eq!(id(buffer.pop_due(1_200)), Some(3));
+        assert_eq!(
+            buffer.stats(),
+            PlayoutStats {
+                played: 1,
+                late: 0,
+                dropped: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn counts_late_frames_and_drops_ones_older_than_shown() {
+        let mut buffer = JitterBuffer::new(LATENCY);
+
+        buffer.push(1_000, frame(1), 1_020);
+        buffer.push(1_040, frame(2), 1_060);
+        assert_eq!(id(buffer.pop_due(1_160)), Some(2));
+
+        // Arrives 200ms after capture, behind a frame that was already shown.
+        buffer.push(1_020, frame(3), 1_220);
+        // Arrives after its playout time, but is still the newest.
+        buffer.push(1_080, frame(4), 1_250);
+
+        assert_eq!(id(buffer.pop_due(1_250)), Some(4));
+        assert_eq!(
+            buffer.stats(),
+            PlayoutStats {
+                played: 2,
+                late: 2,
+                dropped: 2,
+            }
+        );
+    }
+}
@@ -1,13 +1,35 @@
 mod ascii_converter;
+mod audio;
 mod call_handler;
 mod camera;
+mod character_ramp;
+mod chat;
 mod client;
+mod concealment;
+mod dither;
+mod enhancement;
+mod jitter_buffer;
+mod media_receiver;
+mod rate_control;
 mod user_input_handler;
 
-use crate::{ascii_converter::AsciiConverter, camera::TestPatten, client::Client};
+use std::{io::ErrorKind, path::PathBuf, time::Duration};
+
+use crate::{
+    ascii_converter::{AsciiConverter, ColorMode, DEFAULT_RESOLUTION, RendererKind},
+    audio::{AudioInputSpec, AudioOutputSpec},
+    call_handler::CallSettings,
+    camera::{CameraBackend, CameraDevice, CaptureSettings, SourceSpec, TestPatten},
+    character_ramp::{CharacterRamp, RampPreset},
+    client::Client,
+    dither::Dither,
+    enhancement::{EnhancementChain, Stage},
+    rate_control::RateBounds,
+};
 use chrono::Local;
 use clap::Parser;
 use rand::{Rng, rng, seq::IndexedRandom};
+use shared::{Capabilities, FrameFormats, ProtocolError, Resolution};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -19,6 +41,106 @@ struct Args {
 
     #[arg(short, long)]
     test_pattern: Option<TestPatten>,
+
+    /// Where to take video from instead of the camera: `video:PATH`,
+    /// `image:PATH`, `y4m:PATH` or `raw:WIDTHxHEIGHT:PATH`, with `-` as the
+    /// path for standard input.
+    #[arg(long, value_name = "SOURCE", conflicts_with = "test_pattern")]
+    source: Option<SourceSpec>,
+
+    /// Where to take sound from: `none`, `wav:PATH` or `device` for the
+    /// default microphone. Defaults to the microphone in builds with the
+    /// `sound-hardware` feature and to `none` otherwise.
+    #[arg(long, value_name = "INPUT")]
+    audio_input: Option<AudioInputSpec>,
+
+    /// Where to play received sound: `null`, `wav:PATH` or `device` for the
+    /// default speakers. Defaults to the speakers in builds with the
+    /// `sound-hardware` feature and to `null` otherwise.
+    #[arg(long, value_name = "OUTPUT")]
+    audio_output: Option<AudioOutputSpec>,
+
+    /// Camera to use, as an OpenCV device index or a device path.
+    #[arg(long, value_name = "INDEX|PATH", default_value = "0")]
+    camera: CameraDevice,
+
+    /// OpenCV backend to open the camera with.
+    #[arg(long, value_enum, default_value_t = CameraBackend::Any)]
+    camera_backend: CameraBackend,
+
+    /// Width in pixels to ask the camera to capture at.
+    #[arg(long, value_name = "PIXELS")]
+    capture_width: Option<u32>,
+
+    /// Height in pixels to ask the camera to capture at.
+    #[arg(long, value_name = "PIXELS")]
+    capture_height: Option<u32>,
+
+    /// Frame rate to ask the camera to capture at.
+    #[arg(long, value_name = "FPS")]
+    capture_fps: Option<f64>,
+
+    /// Largest grid to send video at, as WIDTHxHEIGHT.
+    #[arg(short, long, default_value_t = DEFAULT_RESOLUTION)]
+    resolution: Resolution,
+
+    /// Smallest grid to scale video down to on a congested network.
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "24x8")]
+    min_resolution: Resolution,
+
+    /// Lowest frame rate to fall back to on a congested network.
+    #[arg(long, value_name = "FPS", default_value_t = 5.0)]
+    min_fps: f64,
+
+    /// Highest frame rate to send at.
+    #[arg(long, value_name = "FPS", default_value_t = 60.0)]
+    max_fps: f64,
+
+    /// Milliseconds remote video is held back to smooth out uneven arrival.
+    #[arg(long, value_name = "MS", default_value_t = 100)]
+    target_latency: u64,
+
+    /// Parity sent per data fragment, from 0 (none) to 1 (one parity
+    /// fragment for each), letting receivers rebuild a lost fragment.
+    #[arg(long, value_name = "RATIO", default_value_t = 0.0)]
+    fec: f64,
+
+    /// How to color video. Detected from the terminal when not given.
+    #[arg(short, long)]
+    color: Option<ColorMode>,
+
+    /// How to draw video: one character, two half blocks or eight Braille
+    /// dots per terminal cell.
+    #[arg(long, value_enum, default_value_t = RendererKind::Ascii)]
+    renderer: RendererKind,
+
+    /// How luminance is dithered before sending and when drawing half
+    /// blocks or Braille.
+    #[arg(long, value_enum, default_value_t = Dither::None)]
+    dither: Dither,
+
+    /// Enhancement stage applied to the camera image, e.g. `gamma:gamma=1.8`
+    /// or `clahe:clip=2,tile=8`. Repeat to build a chain.
+    #[arg(long = "enhance", value_name = "STAGE")]
+    enhance: Vec<Stage>,
+
+    /// File with one enhancement stage per line, run before any `--enhance`
+    /// stages.
+    #[arg(long, value_name = "PATH")]
+    enhance_config: Option<PathBuf>,
+
+    /// Built-in character ramp to draw luminance with.
+    #[arg(long, value_enum, default_value_t = RampPreset::Default)]
+    ramp: RampPreset,
+
+    /// Characters to draw luminance with, darkest first. Replaces `--ramp`.
+    #[arg(long, value_name = "CHARS")]
+    ramp_chars: Option<String>,
+
+    /// Font to order the `--ramp-chars` by measured glyph coverage in, so they
+    /// can be given in any order.
+    #[arg(long, value_name = "PATH", requires = "ramp_chars")]
+    calibrate_font: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -30,31 +152,111 @@ async fn main() {
         None => generate_username(),
     };
 
-    let mut client = match Client::connect(&args.server_address, &username).await {
+    let color_mode = args.color.unwrap_or_else(ColorMode::detect);
+
+    let mut capabilities = Capabilities::default();
+    if color_mode == ColorMode::Off {
+        capabilities.frame_formats = FrameFormats::NIBBLE_GRAY.union(FrameFormats::BYTE_GRAY);
+    }
+
+    let enhancement =
+        match EnhancementChain::from_config(args.enhance_config.as_deref(), args.enhance) {
+            Ok(enhancement) => enhancement,
+            Err(e) => {
+                eprintln!("Error loading enhancement stages: {}", e);
+                return;
+            }
+        };
+
+    let ramp = match (args.ramp_chars, args.calibrate_font) {
+        (Some(chars), Some(font)) => CharacterRamp::calibrate(&chars, &font),
+        (Some(chars), None) => CharacterRamp::new(&chars).map_err(Into::into),
+        (None, _) => Ok(args.ramp.ramp()),
+    };
+
+    let ramp = match ramp {
+        Ok(ramp) => ramp,
+        Err(e) => {
+            eprintln!("Error building character ramp: {}", e);
+            return;
+        }
+    };
+
+    if !(args.min_fps > 0.0 && args.min_fps <= args.max_fps) {
+        eprintln!("Error: --min-fps must be above 0 and no more than --max-fps");
+        return;
+    }
+
+    if !(0.0..=1.0).contains(&args.fec) {
+        eprintln!("Error: --fec must be between 0 and 1");
+        return;
+    }
+
+    let fec_group_size =
+        (args.fec > 0.0).then(|| (1.0 / args.fec).round().clamp(1.0, u8::MAX as f64) as u8);
+
+    let rate_bounds = RateBounds {
+        min_fps: args.min_fps,
+        max_fps: args.max_fps,
+        min_resolution: args.min_resolution.min(args.resolution),
+    };
+
+    let capture = CaptureSettings {
+        device: args.camera,
+        backend: args.camera_backend,
+        width: args.capture_width,
+        height: args.capture_height,
+        fps: args.capture_fps,
+    };
+
+    let source = match (args.test_pattern, args.source) {
+        (Some(test_pattern), _) => SourceSpec::TestPattern(test_pattern),
+        (None, None | Some(SourceSpec::Camera(_))) => SourceSpec::Camera(capture),
+        (None, Some(source)) => source,
+    };
+
+    let settings = CallSettings {
+        source,
+        audio_input: args.audio_input.unwrap_or_default(),
+        audio_output: args.audio_output.unwrap_or_default(),
+        max_resolution: args.resolution,
+        rate_bounds,
+        target_latency: Duration::from_millis(args.target_latency),
+        fec_group_size,
+        color_mode,
+        renderer: args.renderer,
+        dither: args.dither,
+        enhancement,
+        ramp,
+    };
+
+    let mut client = match Client::connect(&args.server_address, &username, capabilities).await {
         Ok(client) => client,
         Err(e) => {
-            eprintln!("Error connecting: {}", e);
+            eprintln!("Error connecting: {}", describe_error(e.as_ref()));
             return;
         }
     };
 
-    print_connected_message(username, args.server_address);
+    print_connected_message(username, args.server_address, client.protocol_version());
 
-    if let Err(e) = client.run(args.test_pattern).await {
-        eprintln!("Error: {}", e);
+    if let Err(e) = client.run(&settings).await {
+        eprintln!("Error: {}", describe_error(e.as_ref()));
         return;
     }
 }
 
-fn print_connected_message(username: String, server_addr: String) {
+fn print_connected_message(username: String, server_addr: String, protocol_version: u16) {
     AsciiConverter::clear_terminal();
 
     let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     let connection_status = "Connection OK";
+    let protocol = format!("v{}", protocol_version);
     let info = [
         ("Time", timestamp.as_str()),
         ("Server", server_addr.as_str()),
         ("User", username.as_str()),
+        ("Protocol", protocol.as_str()),
         ("Status", connection_status),
     ];
 
@@ -79,13 +281,47 @@ fn print_available_commands() {
     println!("Available Commands:");
     println!("    - list users                  : Show all connected users");
     println!("    - list rooms                  : Show all available rooms");
+    println!("    - list cameras                : Show the cameras that can be opened");
     println!("    - create room <name>          : Create a new room");
+    println!("    - create room <name> composite: Create a room the server mixes into one grid");
     println!("    - delete room <name>          : Delete a room");
     println!("    - join room <name>            : Connect to a specific room");
+    println!("    - chat <message>              : Send a message to everyone online");
+    println!("    - msg <user> <message>        : Send a message to one user");
     println!("    - exit                        : Quit the application");
     println!("\nType a command to get started:\n");
 }
 
+fn describe_error(error: &(dyn std::error::Error + Send + Sync + 'static)) -> String {
+    let io_error = match error.downcast_ref::<ProtocolError>() {
+        Some(ProtocolError::Io(e)) => Some(e),
+        Some(ProtocolError::UnknownOpcode(_)) => {
+            return format!(
+                "{}. The server is probably newer than this client, try updating it.",
+                error
+            );
+        }
+        Some(ProtocolError::InvalidUtf8(_) | ProtocolError::TruncatedFrame) => {
+            return format!(
+                "{}. The server sent a malformed message, try reconnecting.",
+                error
+            );
+        }
+        Some(_) => None,
+        None => error.downcast_ref::<std::io::Error>(),
+    };
+
+    match io_error.map(|e| e.kind()) {
+        Some(ErrorKind::ConnectionRefused) => {
+            format!("{}. Is the server running and reachable?", error)
+        }
+        Some(ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe) => {
+            format!("{}. The server closed the connection unexpectedly.", error)
+        }
+        _ => error.to_string(),
+    }
+}
+
 fn generate_username() -> String {
     let adjectives = ["fast", "lazy", "cool", "smart", "brave"];
     let nouns = ["tiger", "eagle", "lion", "panda", "wolf"];
@@ -0,0 +1,166 @@
+use std::fmt;
+
+use shared::ReceptionReport;
+
+/// Follows the sequence numbers of one remote stream, so that frames which
+/// arrive after a newer one can be dropped and losses can be counted.
+#[derive(Debug, Default)]
+pub struct MediaReceiver {
+    highest_sequence: Option<u32>,
+    stats: ReceiveStats,
+    /// `stats` when the last report was taken.
+    reported: ReceiveStats,
+    /// Arrival time minus capture time of the last accepted frame, in
+    /// milliseconds. Clock offset between the two sides cancels out.
+    last_transit: Option<i64>,
+    /// Interarrival jitter in milliseconds.
+    jitter: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReceiveStats {
+    pub received: u64,
+    pub lost: u64,
+    /// Frames dropped because a newer one had already been received.
+    pub late: u64,
+}
+
+impl MediaReceiver {
+    /// Records the frame numbered `sequence`, captured at `timestamp` and
+    /// arrived at `arrival` (both in milliseconds since the Unix epoch), and
+    /// returns whether it should be used.
+    pub fn accept(&mut self, sequence: u32, timestamp: u64, arrival: u64) -> bool {
+        let highest_sequence = match self.highest_sequence {
+            Some(highest_sequence) => highest_sequence,
+            None => {
+                self.highest_sequence = Some(sequence);
+                self.stats.received += 1;
+                self.update_jitter(timestamp, arrival);
+                return true;
+            }
+        };
+
+        let distance = sequence.wrapping_sub(highest_sequence) as i32;
+
+        if distance <= 0 {
+            self.stats.late += 1;
+            return false;
+        }
+
+        self.stats.lost += distance as u64 - 1;
+        self.stats.received += 1;
+        self.highest_sequence = Some(sequence);
+        self.update_jitter(timestamp, arrival);
+
+        return true;
+    }
+
+    pub fn stats(&self) -> ReceiveStats {
+        self.stats
+    }
+
+    /// Loss and jitter since the previous report, or `None` if no frame has
+    /// arrived since then.
+    pub fn take_report(&mut self) -> Option<ReceptionReport> {
+        let received = self.stats.received - self.reported.received;
+        let lost = self.stats.lost - self.reported.lost;
+        self.reported = self.stats;
+
+        if received == 0 {
+            return None;
+        }
+
+        let loss_permille = lost * 1000 / (received + lost);
+
+        return Some(ReceptionReport {
+            loss_permille: loss_permille as u16,
+            jitter_ms: self.jitter.round().min(u16::MAX as f64) as u16,
+        });
+    }
+
+    /// Smooths the change in transit time as RFC 3550 does, so one slow frame
+    /// moves the estimate by a sixteenth.
+    fn update_jitter(&mut self, timestamp: u64, arrival: u64) {
+        let transit = arrival as i64 - timestamp as i64;
+
+        if let Some(last_transit) = self.last_transit {
+            let difference = (transit - last_transit).abs() as f64;
+            self.jitter += (difference - self.jitter) / 16.0;
+        }
+
+        self.last_transit = Some(transit);
+    }
+}
+
+impl ReceiveStats {
+    pub fn loss_percent(&self) -> f64 {
+        let expected = self.received + self.lost;
+
+        if expected == 0 {
+            return 0.0;
+        }
+
+        return self.lost as f64 * 100.0 / expected as f64;
+    }
+}
+
+impl fmt::Display for ReceiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} received, {} lost ({:.1}%), {} late",
+            self.received,
+            self.lost,
+            self.loss_percent(),
+            self.late
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_loss_since_last_report() {
+        let mut receiver = MediaReceiver::default();
+
+        for sequence in [0, 1, 2, 5, 6] {
+            receiver.accept(sequence, 0, 0);
+        }
+        assert_eq!(receiver.take_report().unwrap().loss_permille, 285);
+
+        for sequence in 7..10 {
+            receiver.accept(sequence, 0, 0);
+        }
+        assert_eq!(receiver.take_report().unwrap().loss_permille, 0);
+
+        assert_eq!(receiver.take_report(), None);
+    }
+
+    #[test]
+    fn steady_transit_has_no_jitter() {
+        let mut receiver = MediaReceiver::default();
+
+        for sequence in 0..10 {
+            let timestamp = 1_000 + sequence as u64 * 16;
+            receiver.accept(sequence, timestamp, timestamp + 40);
+        }
+
+        assert_eq!(receiver.take_report().unwrap().jitter_ms, 0);
+    }
+
+    #[test]
+    fn varying_transit_raises_jitter() {
+        let mut receiver = MediaReceiver::default();
+
+        for sequence in 0..200 {
+            let timestamp = 1_000 + sequence as u64 * 16;
+            let delay = if sequence % 2 == 0 { 20 } else { 60 };
+            receiver.accept(sequence, timestamp, timestamp + delay);
+        }
+
+        let jitter_ms = receiver.take_report().unwrap().jitter_ms;
+        assert!((38..=40).contains(&jitter_ms), "{}", jitter_ms);
+    }
+}
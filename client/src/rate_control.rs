@@ -0,0 +1,245 @@
+use shared::{ReceptionReport, Resolution};
+use tokio::time::{Duration, Instant};
+
+/// Loss above which receivers are taken to be congested.
+const CONGESTED_LOSS_PERCENT: f64 = 5.0;
+
+/// Jitter above which receivers are taken to be congested.
+const CONGESTED_JITTER_MS: u16 = 80;
+
+/// Loss below which the sender may try sending more.
+const CLEAR_LOSS_PERCENT: f64 = 1.0;
+
+/// Jitter below which the sender may try sending more.
+const CLEAR_JITTER_MS: u16 = 30;
+
+/// Factor frame rate, and then size, are cut by on congestion.
+const BACKOFF: f64 = 0.75;
+
+/// Frame rate added per clear report once full size is reached.
+const FPS_STEP: f64 = 5.0;
+
+/// Size added per clear report until full size is reached.
+const SCALE_STEP: f64 = 0.1;
+
+/// Smallest fraction of the full size video is ever scaled down to.
+const MIN_SCALE: f64 = 0.25;
+
+/// Limits the sender adapts within.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateBounds {
+    pub min_fps: f64,
+    pub max_fps: f64,
+    /// Smallest grid video is scaled down to.
+    pub min_resolution: Resolution,
+}
+
+/// Picks the frame rate, size and keyframe spacing to send at from what
+/// receivers report back: multiplicative decrease on congestion, additive
+/// increase while reception is clear.
+///
+/// Frame rate gives way first, so motion stays readable for longer than
+/// detail; size recovers first for the same reason.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateController {
+    bounds: RateBounds,
+    fps: f64,
+    /// Fraction of the full grid to send.
+    scale: f64,
+    lossy: bool,
+}
+
+/// Spaces sent frames by a frame interval that may change between frames.
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    next_send: Option<Instant>,
+}
+
+impl RateController {
+    /// Starts at the highest frame rate and full size.
+    pub fn new(bounds: RateBounds) -> Self {
+        Self {
+            bounds,
+            fps: bounds.max_fps,
+            scale: 1.0,
+            lossy: false,
+        }
+    }
+
+    pub fn on_report(&mut self, report: ReceptionReport) {
+        let loss_percent = report.loss_percent();
+
+        if loss_percent > CONGESTED_LOSS_PERCENT || report.jitter_ms > CONGESTED_JITTER_MS {
+            if self.fps > self.bounds.min_fps {
+                self.fps = (self.fps * BACKOFF).max(self.bounds.min_fps);
+            } else {
+                self.scale = (self.scale * BACKOFF).max(MIN_SCALE);
+            }
+        } else if loss_percent < CLEAR_LOSS_PERCENT && report.jitter_ms < CLEAR_JITTER_MS {
+            if self.scale < 1.0 {
+                self.scale = (self.scale + SCALE_STEP).min(1.0);
+            } else {
+                self.fps = (self.fps + FPS_STEP).min(self.bounds.max_fps);
+            }
+        }
+
+        self.lossy = loss_percent >= CLEAR_LOSS_PERCENT;
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.fps)
+    }
+
+    /// `max` scaled down to the current size, but no smaller than the
+    /// minimum resolution.
+    pub fn resolution(&self, max: Resolution) -> Resolution {
+        let scaled = Resolution::new(
+            (max.width as f64 * self.scale).round() as u16,
+            (max.height as f64 * self.scale).round() as u16,
+        );
+
+        return scaled.max(self.bounds.min_resolution).min(max);
+    }
+
+    /// Frames between scheduled keyframes: one second's worth, or half a
+    /// second's while frames are being lost so receivers recover sooner.
+    pub fn keyframe_interval(&self) -> u32 {
+        let seconds = if self.lossy { 0.5 } else { 1.0 };
+
+        return ((self.fps * seconds).round() as u32).max(1);
+    }
+}
+
+impl FramePacer {
+    /// Whether a frame available at `now` should be sent to keep to
+    /// `interval`. Frames up to a quarter interval early count as on time,
+    /// so a source running at exactly the target rate is not halved by
+    /// scheduling noise.
+    pub fn ready(&mut self, now: Instant, interval: Duration) -> bool {
+        if let Some(next_send) = self.next_send {
+            if now + interval / 4 < next_send {
+                return false;
+            }
+
+            if now < next_send + interval {
+                self.next_send = Some(next_send + interval);
+                return true;
+            }
+        }
+
+        self.next_send = Some(now + interval);
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: RateBounds = RateBounds {
+        min_fps: 5.0,
+        max_fps: 60.0,
+        min_resolution: Resolution::new(20, 10),
+    };
+
+    const CONGESTED: ReceptionReport = ReceptionReport {
+        loss_permille: 150,
+        jitter_ms: 10,
+    };
+
+    const CLEAR: ReceptionReport = ReceptionReport {
+        loss_permille: 0,
+        jitter_ms: 5,
+    };
+
+    #[test]
+    fn congestion_lowers_frame_rate_before_size() {
+        let mut rate = RateController::new(BOUNDS);
+        let max = Resolution::new(100, 40);
+
+        rate.on_report(CONGESTED);
+        assert_eq!(rate.fps(), 45.0);
+        assert_eq!(rate.resolution(max), max);
+
+        for _ in 0..20 {
+            rate.on_report(CONGESTED);
+        }
+        assert_eq!(rate.fps(), BOUNDS.min_fps);
+        assert_eq!(rate.resolution(max), Resolution::new(25, 10));
+    }
+
+    #[test]
+    fn clear_reports_restore_size_then_frame_rate() {
+        let mut rate = RateController::new(BOUNDS);
+        let max = Resolution::new(100, 40);
+
+        for _ in 0..20 {
+            rate.on_report(CONGESTED);
+        }
+        for _ in 0..8 {
+            rate.on_report(CLEAR);
+        }
+        assert_eq!(rate.resolution(max), max);
+        assert_eq!(rate.fps(), BOUNDS.min_fps);
+
+        for _ in 0..20 {
+            rate.on_report(CLEAR);
+        }
+        assert_eq!(rate.fps(), BOUNDS.max_fps);
+    }
+
+    #[test]
+    fn high_jitter_counts_as_congestion() {
+        let mut rate = RateController::new(BOUNDS);
+
+        rate.on_report(ReceptionReport {
+            loss_permille: 0,
+            jitter_ms: 200,
+        });
+
+        assert!(rate.fps() < BOUNDS.max_fps);
+    }
+
+    #[test]
+    fn loss_shortens_keyframe_interval() {
+        let mut rate = RateController::new(BOUNDS);
+        assert_eq!(rate.keyframe_interval(), 60);
+
+        rate.on_report(ReceptionReport {
+            loss_permille: 30,
+            jitter_ms: 0,
+        });
+        assert_eq!(rate.keyframe_interval(), 30);
+    }
+
+    #[test]
+    fn pacer_keeps_source_rate_when_it_matches_target() {
+        let mut pacer = FramePacer::default();
+        let start = Instant::now();
+        let interval = Duration::from_micros(33_333);
+
+        let sent = (0..30)
+            .filter(|&i| pacer.ready(start + interval * i, interval))
+            .count();
+
+        assert_eq!(sent, 30);
+    }
+
+    #[test]
+    fn pacer_drops_frames_above_target() {
+        let mut pacer = FramePacer::default();
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+
+        let sent = (0..100)
+            .filter(|&i| pacer.ready(start + Duration::from_millis(i * 10), interval))
+            .count();
+
+        // The first frame, then one per interval over the next 990ms.
+        assert_eq!(sent, 11);
+    }
+}
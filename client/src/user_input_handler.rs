@@ -1,11 +1,16 @@
+use shared::RoomMode;
+
 pub enum UserCommand {
     Close,
     KeepAlive,
     ListUsers,
-    CreateRoom(String),
+    CreateRoom(String, RoomMode),
     ListRooms,
+    ListCameras,
     JoinRoom(String),
     DeleteRoom(String),
+    Chat(String),
+    DirectMessage(String, String),
 }
 
 pub struct UserInputHandler {}
@@ -19,20 +24,24 @@ impl UserInputHandler {
         match input {
             "" => return Ok(UserCommand::KeepAlive),
             "create room" => {
-                println!("Usage: create room <name>");
+                println!("Usage: create room <name> [composite]");
                 return Ok(UserCommand::KeepAlive);
             }
             create_cmd if create_cmd.starts_with("create room ") => {
                 let create_cmd_parts: Vec<&str> = create_cmd.split(" ").collect();
 
-                if create_cmd_parts.len() != 3 {
-                    println!("Usage: create room <name>");
-                    return Ok(UserCommand::KeepAlive);
-                }
+                let mode = match create_cmd_parts.get(3..) {
+                    Some([]) => RoomMode::Forward,
+                    Some(["composite"]) => RoomMode::Composite,
+                    _ => {
+                        println!("Usage: create room <name> [composite]");
+                        return Ok(UserCommand::KeepAlive);
+                    }
+                };
 
                 let room_name = create_cmd_parts[2];
 
-                return Ok(UserCommand::CreateRoom(room_name.to_string()));
+                return Ok(UserCommand::CreateRoom(room_name.to_string(), mode));
             }
             "delete room" => {
                 println!("Usage: delete room <name>");
@@ -66,8 +75,32 @@ impl UserInputHandler {
 
                 return Ok(UserCommand::JoinRoom(room_name.to_string()));
             }
+            "chat" => {
+                println!("Usage: chat <message>");
+                return Ok(UserCommand::KeepAlive);
+            }
+            chat_cmd if chat_cmd.starts_with("chat ") => {
+                let text = chat_cmd["chat ".len()..].trim();
+
+                return Ok(UserCommand::Chat(text.to_string()));
+            }
+            msg_cmd if msg_cmd == "msg" || msg_cmd.starts_with("msg ") => {
+                let (recipient, text) = match msg_cmd["msg".len()..].trim().split_once(' ') {
+                    Some((recipient, text)) => (recipient, text.trim()),
+                    None => {
+                        println!("Usage: msg <user> <message>");
+                        return Ok(UserCommand::KeepAlive);
+                    }
+                };
+
+                return Ok(UserCommand::DirectMessage(
+                    recipient.to_string(),
+                    text.to_string(),
+                ));
+            }
             "list users" => return Ok(UserCommand::ListUsers),
             "list rooms" => return Ok(UserCommand::ListRooms),
+            "list cameras" => return Ok(UserCommand::ListCameras),
             "exit" => return Ok(UserCommand::Close),
             _ => {
                 println!("Unknown command");
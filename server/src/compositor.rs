@@ -0,0 +1,417 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use shared::{
+    FrameFormats, FrameReassembler, MAX_FRAME_SIZE, MediaHeader, MediaKind, ProtocolError,
+    Resolution, VideoDecoder, VideoEncoder, VideoFormat, VideoFrame,
+};
+
+/// How long a partially received frame is kept waiting for its remaining
+/// fragments.
+const REASSEMBLY_DEADLINE: Duration = Duration::from_millis(250);
+
+/// Shortest time between two keyframe requests to the same sender.
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Composite frames between keyframes, so a receiver that missed one
+/// recovers without asking.
+const KEYFRAME_INTERVAL: u32 = 60;
+
+/// Dark cells left between tiles.
+const TILE_GAP: usize = 1;
+
+/// Formats a composite frame may be sent in, most preferred first.
+const COMPOSITE_FORMATS: [VideoFormat; 4] = [
+    VideoFormat::ByteColor,
+    VideoFormat::NibbleColor,
+    VideoFormat::ByteGray,
+    VideoFormat::NibbleGray,
+];
+
+/// Decodes the video sent into composite rooms and lays it out into one
+/// grid frame per receiver, leaving out the receiver's own stream.
+#[derive(Default)]
+pub struct Compositor {
+    senders: HashMap<String, SenderState>,
+    receivers: HashMap<String, ReceiverState>,
+}
+
+struct SenderState {
+    reassembler: FrameReassembler,
+    decoder: VideoDecoder,
+    frame: Option<VideoFrame>,
+    /// Bumped with every new frame, so receivers can tell what they have
+    /// already been sent.
+    version: u64,
+    last_keyframe_request: Option<Instant>,
+}
+
+struct ReceiverState {
+    encoder: VideoEncoder,
+    sequence: u32,
+    /// Senders, their frame versions and the resolution the last composite
+    /// frame was built from.
+    composed_from: Option<(Vec<(String, u64)>, Resolution)>,
+}
+
+/// One composite frame, encoded and ready to fragment.
+pub struct CompositeFrame {
+    pub kind: MediaKind,
+    pub sequence: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Compositor {
+    /// Adds one of `username`'s video datagrams. Returns `true` when their
+    /// stream cannot be decoded without a keyframe and one should be asked
+    /// for.
+    pub fn push(&mut self, username: &str, header: &MediaHeader, payload: &[u8]) -> bool {
+        let sender = self
+            .senders
+            .entry(username.to_string())
+            .or_insert_with(|| SenderState {
+                reassembler: FrameReassembler::new(REASSEMBLY_DEADLINE, MAX_FRAME_SIZE),
+                decoder: VideoDecoder::default(),
+                frame: None,
+                version: 0,
+                last_keyframe_request: None,
+            });
+
+        let frame = match sender.reassembler.push(header, payload) {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        match sender.decoder.decode(&frame) {
+            Ok(video_frame) => {
+                sender.frame = Some(video_frame);
+                sender.version += 1;
+                return false;
+            }
+            Err(ProtocolError::MissingKeyframe(_)) => {
+                let now = Instant::now();
+
+                if let Some(last_keyframe_request) = sender.last_keyframe_request
+                    && now.duration_since(last_keyframe_request) < KEYFRAME_REQUEST_INTERVAL
+                {
+                    return false;
+                }
+
+                sender.last_keyframe_request = Some(now);
+                return true;
+            }
+            Err(_) => return false,
+        }
+    }
+
+    /// Makes the next frame built for `receiver` a keyframe, and builds one
+    /// even if nothing changed.
+    pub fn request_keyframe(&mut self, receiver: &str) {
+        if let Some(receiver) = self.receivers.get_mut(receiver) {
+            receiver.encoder.request_keyframe();
+            receiver.composed_from = None;
+        }
+    }
+
+    /// Lays out the latest frames of `senders`, in order, into a grid of up
+    /// to `resolution` in a format `frame_formats` allows, and encodes it for
+    /// `receiver`. The grid is made smaller if its frames would not fit in
+    /// `max_frame_size` bytes. Returns `None` when nothing changed since the
+    /// last one.
+    pub fn compose(
+        &mut self,
+        receiver: &str,
+        senders: &[&str],
+        resolution: Resolution,
+        frame_formats: FrameFormats,
+        max_frame_size: u32,
+    ) -> Option<CompositeFrame> {
+        let composed_from = senders
+            .iter()
+            .map(|username| {
+                let version = self
+                    .senders
+                    .get(*username)
+                    .map_or(0, |sender| sender.version);
+                (username.to_string(), version)
+            })
+            .collect::<Vec<_>>();
+
+        let receiver_state = self
+            .receivers
+            .entry(receiver.to_string())
+            .or_insert_with(|| ReceiverState {
+                encoder: VideoEncoder::new(KEYFRAME_INTERVAL),
+                sequence: 0,
+                composed_from: None,
+            });
+
+        let composed_from = Some((composed_from, resolution));
+        if receiver_state.composed_from == composed_from {
+            return None;
+        }
+        receiver_state.composed_from = composed_from;
+
+        let frames: Vec<Option<&VideoFrame>> = senders
+            .iter()
+            .map(|username| {
+                self.senders
+                    .get(*username)
+                    .and_then(|sender| sender.frame.as_ref())
+            })
+            .collect();
+
+        let video_frame = lay_out(&frames, resolution, frame_formats, max_frame_size);
+
+        let sequence = receiver_state.sequence;
+        receiver_state.sequence = sequence.wrapping_add(1);
+
+        let (kind, payload) = receiver_state.encoder.encode(sequence, &video_frame);
+
+        return Some(CompositeFrame {
+            kind,
+            sequence,
+            payload,
+        });
+    }
+
+    /// Forgets everyone `keep` returns `false` for.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.senders.retain(|username, _| keep(username));
+        self.receivers.retain(|username, _| keep(username));
+    }
+}
+
+/// Draws `frames` as tiles of a grid of up to `resolution`, in the richest
+/// format that `frame_formats` allows and the frames need, and small enough
+/// to fit in `max_frame_size` bytes. Senders with no frame yet are left dark.
+fn lay_out(
+    frames: &[Option<&VideoFrame>],
+    resolution: Resolution,
+    frame_formats: FrameFormats,
+    max_frame_size: u32,
+) -> VideoFrame {
+    let present = frames.iter().flatten();
+    let wants_color = present.clone().any(|frame| frame.format.has_color());
+    let wants_bytes = present
+        .clone()
+        .any(|frame| frame.format.luminance_bits() == 8);
+
+    let format = COMPOSITE_FORMATS
+        .into_iter()
+        .filter(|format| wants_color || !format.has_color())
+        .filter(|format| wants_bytes || format.luminance_bits() == 4)
+        .find(|format| frame_formats.contains(format.frame_format()))
+        .unwrap_or(VideoFormat::NibbleGray);
+
+    let resolution = format.fit_frame_size(resolution, max_frame_size as usize);
+
+    let width = resolution.width as usize;
+    let height = resolution.height as usize;
+
+    let mut luminance = vec![0u8; resolution.cell_count()];
+    let mut colors = vec![0u8; resolution.cell_count()];
+
+    let (columns, rows) = grid_shape(frames.len(), width, height);
+    let tile_width = width.saturating_sub((columns - 1) * TILE_GAP) / columns;
+    let tile_height = height.saturating_sub((rows - 1) * TILE_GAP) / rows;
+
+    for (tile, frame) in frames.iter().enumerate() {
+        let frame = match frame {
+            Some(frame) => frame,
+            None => continue,
+        };
+
+        let left = tile % columns * (tile_width + TILE_GAP);
+        let top = tile / columns * (tile_height + TILE_GAP);
+
+        let src_width = frame.resolution.width as usize;
+        let src_height = frame.resolution.height as usize;
+        let src_luminance = frame.luminance();
+        let src_colors = frame.colors();
+
+        if src_width == 0 || src_height == 0 {
+            continue;
+        }
+
+        for y in 0..tile_height {
+            for x in 0..tile_width {
+                let src = (y * src_height / tile_height) * src_width + x * src_width / tile_width;
+                let cell = (top + y) * width + left + x;
+
+                luminance[cell] = src_luminance[src];
+                colors[cell] = match src_colors {
+                    Some(src_colors) => src_colors[src],
+                    None => gray_cube_index(src_luminance[src]),
+                };
+            }
+        }
+    }
+
+    return VideoFrame::from_cells(resolution, format, &luminance, &colors);
+}
+
+/// Columns and rows of the grid that gives `tiles` tiles the most area in
+/// a `width` by `height` frame.
+fn grid_shape(tiles: usize, width: usize, height: usize) -> (usize, usize) {
+    let tiles = tiles.max(1);
+
+    return (1..=tiles)
+        .map(|columns| (columns, tiles.div_ceil(columns)))
+        .max_by_key(|&(columns, rows)| {
+            let tile_width = width.saturating_sub((columns - 1) * TILE_GAP) / columns;
+            let tile_height = height.saturating_sub((rows - 1) * TILE_GAP) / rows;
+
+            // Terminal cells are about twice as tall as wide.
+            (tile_width.min(tile_height * 2), tile_width * tile_height)
+        })
+        .unwrap_or((1, 1));
+}
+
+/// Index of the gray in the 6x6x6 color cube closest to `luminance`.
+fn gray_cube_index(luminance: u8) -> u8 {
+    let level = ((luminance as u16 * 5 + 127) / 255) as u8;
+
+    return level * (36 + 6 + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_frame(resolution: Resolution, luminance: u8) -> VideoFrame {
+        VideoFrame {
+            resolution,
+            format: VideoFormat::ByteGray,
+            cells: vec![luminance; resolution.cell_count()],
+        }
+    }
+
+    #[test]
+    fn picks_grid_shape_for_terminal_cells() {
+        assert_eq!(grid_shape(1, 80, 24), (1, 1));
+        assert_eq!(grid_shape(2, 80, 24), (2, 1));
+        assert_eq!(grid_shape(4, 80, 24), (2, 2));
+        assert_eq!(grid_shape(2, 20, 40), (1, 2));
+    }
+
+    #[test]
+    fn lays_out_tiles_with_a_gap() {
+        let left = gray_frame(Resolution::new(4, 2), 200);
+        let right = gray_frame(Resolution::new(8, 4), 100);
+
+        let frame = lay_out(
+            &[Some(&left), Some(&right)],
+            Resolution::new(9, 2),
+            FrameFormats::all(),
+            MAX_FRAME_SIZE,
+        );
+
+        assert_eq!(frame.format, VideoFormat::ByteGray);
+        assert_eq!(
+            frame.luminance(),
+            [[200, 200, 200, 200, 0, 100, 100, 100, 100]; 2].concat()
+        );
+    }
+
+    #[test]
+    fn falls_back_to_formats_the_receiver_supports() {
+        let frame = gray_frame(Resolution::new(2, 1), 255);
+
+        let composite = lay_out(
+            &[Some(&frame)],
+            Resolution::new(2, 1),
+            FrameFormats::NIBBLE_GRAY,
+            MAX_FRAME_SIZE,
+        );
+
+        assert_eq!(composite.format, VideoFormat::NibbleGray);
+        assert_eq!(composite.cells, vec![0xff]);
+    }
+
+    #[test]
+    fn composes_only_when_a_frame_changed() {
+        let mut compositor = Compositor::default();
+        let resolution = Resolution::new(4, 2);
+
+        assert!(
+            compositor
+                .compose(
+                    "bob",
+                    &["alice"],
+                    resolution,
+                    FrameFormats::all(),
+                    MAX_FRAME_SIZE
+                )
+                .is_some()
+        );
+        assert!(
+            compositor
+                .compose(
+                    "bob",
+                    &["alice"],
+                    resolution,
+                    FrameFormats::all(),
+                    MAX_FRAME_SIZE
+                )
+                .is_none()
+        );
+
+        compositor.request_keyframe("bob");
+        let frame = compositor
+            .compose(
+                "bob",
+                &["alice"],
+                resolution,
+                FrameFormats::all(),
+                MAX_FRAME_SIZE,
+            )
+            .unwrap();
+        assert_eq!(frame.kind, MediaKind::Keyframe);
+        assert_eq!(frame.sequence, 1);
+    }
+
+    #[test]
+    fn shrinks_the_grid_to_the_max_frame_size() {
+        let mut compositor = Compositor::default();
+        let max_frame_size = 1_000;
+
+        let frame = compositor
+            .compose(
+                "bob",
+                &["alice"],
+                Resolution::new(u16::MAX, u16::MAX),
+                FrameFormats::all(),
+                max_frame_size,
+            )
+            .unwrap();
+
+        assert!(frame.payload.len() <= max_frame_size as usize);
+
+        let video_frame = VideoFrame::decode(&frame.payload).unwrap();
+        assert!(video_frame.resolution.width > 1 && video_frame.resolution.height > 1);
+    }
+
+    #[test]
+    fn drops_sender_frames_that_do_not_match_their_resolution() {
+        let mut compositor = Compositor::default();
+
+        // Claims 1000x1000 cells but carries four.
+        let mut payload = gray_frame(Resolution::new(2, 2), 255).encode();
+        payload[..4].copy_from_slice(&[0x03, 0xe8, 0x03, 0xe8]);
+
+        let header = MediaHeader {
+            kind: MediaKind::Keyframe,
+            sequence: 0,
+            timestamp: 0,
+            fragment_index: 0,
+            fragment_count: 1,
+            payload_len: payload.len() as u16,
+        };
+
+        assert!(!compositor.push("alice", &header, &payload));
+        assert!(compositor.senders["alice"].frame.is_none());
+    }
+}
@@ -4,6 +4,7 @@ use wes_sfu::WeSFU;
 
 use clap::Parser;
 
+mod compositor;
 mod room;
 mod tcp_handler;
 mod udp_handler;
@@ -1,8 +1,19 @@
-use shared::RoomStreamID;
-use std::collections::HashMap;
+use shared::{FrameFormats, ReceptionReport, Resolution, RoomMode, RoomStreamID};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Debug)]
 pub struct Room {
     pub name: String,
+    pub mode: RoomMode,
     pub username_to_rsid: HashMap<String, RoomStreamID>,
+    pub username_to_preferred_resolution: HashMap<String, Resolution>,
+    pub username_to_frame_formats: HashMap<String, FrameFormats>,
+    /// Negotiated maximum frame size of each participant, in bytes.
+    pub username_to_max_frame_size: HashMap<String, u32>,
+    /// Latest report on each stream in the room, keyed by sender and then
+    /// receiver.
+    pub reception_reports: HashMap<(String, String), ReceptionReport>,
+    /// Receivers in a composite room that asked for a composite keyframe
+    /// since the last composite frames were built.
+    pub composite_keyframe_requests: HashSet<String>,
 }
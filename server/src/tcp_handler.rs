@@ -1,24 +1,40 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use log::{error, info};
+use log::{error, info, warn};
 use rand::{Rng, rng};
 use shared::{
-    RoomStreamID, StreamID, TcpCommand, TcpCommandType, read_command_from_tcp_stream,
-    write_command_to_tcp_stream,
+    COMPOSITE_RSID, Capabilities, ChatScope, FrameFormats, LEGACY_PROTOCOL_VERSION, MAX_CHAT_LEN,
+    Message, MessageStream, PROTOCOL_VERSION, ProtocolError, ReceptionReport, Resolution, RoomMode,
+    RoomStreamID, StreamID, VideoFormat, negotiate_protocol_version,
 };
 use tokio::{
-    net::TcpStream,
-    sync::{Mutex, broadcast},
+    io::{AsyncRead, AsyncWrite},
+    sync::{
+        Mutex, broadcast,
+        mpsc::{self, error::TrySendError},
+    },
 };
 
 use crate::room::Room;
 
+/// Chat and direct messages a connection may have queued before it misses
+/// any. Chat has its own channel, so a burst of it never crowds out the room
+/// updates on the command channel.
+const CHAT_CHANNEL_CAPACITY: usize = 64;
+
 pub struct TcpHandler {
+    protocol_version: u16,
+    capabilities: Capabilities,
     current_username: Arc<Mutex<Option<String>>>,
     active_usernames: Arc<Mutex<Vec<String>>>,
     public_rooms: Arc<Mutex<Vec<Room>>>,
     sid_to_username_map: Arc<Mutex<HashMap<StreamID, String>>>,
-    username_to_command_channel_tx: Arc<Mutex<HashMap<String, broadcast::Sender<TcpCommand>>>>,
+    username_to_command_channel_tx: Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>,
+    username_to_chat_channel_tx: Arc<Mutex<HashMap<String, mpsc::Sender<Message>>>>,
 }
 
 impl TcpHandler {
@@ -26,45 +42,88 @@ impl TcpHandler {
         active_usernames: Arc<Mutex<Vec<String>>>,
         public_rooms: Arc<Mutex<Vec<Room>>>,
         sid_to_username_map: Arc<Mutex<HashMap<StreamID, String>>>,
-        username_to_command_channel_tx: Arc<Mutex<HashMap<String, broadcast::Sender<TcpCommand>>>>,
+        username_to_command_channel_tx: Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>,
+        username_to_chat_channel_tx: Arc<Mutex<HashMap<String, mpsc::Sender<Message>>>>,
     ) -> Self {
         let current_username = Arc::new(Mutex::new(None));
 
         Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
             current_username,
             active_usernames,
             public_rooms,
             sid_to_username_map,
             username_to_command_channel_tx,
+            username_to_chat_channel_tx,
         }
     }
 
-    pub async fn handle_stream(
+    pub async fn handle_stream<S: AsyncRead + AsyncWrite + Unpin>(
         &mut self,
-        stream: &mut TcpStream,
+        stream: &mut MessageStream<S>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let first_command_from_client_option = read_command_from_tcp_stream(stream).await?;
-
-        let potential_username = match first_command_from_client_option {
-            Some(TcpCommand::WithStringPayload {
-                command_type: TcpCommandType::HelloFromClient,
-                payload,
-            }) => payload,
-            _ => {
-                return Err("Expected HelloFromClient command with payload".into());
+        let first_message_from_client_option = stream.read_hello().await?;
+
+        let (client_protocol_version, client_capabilities, potential_username) =
+            match first_message_from_client_option {
+                Some(Message::HelloFromClient {
+                    protocol_version,
+                    capabilities,
+                    username,
+                }) => (protocol_version, capabilities, username),
+                _ => {
+                    return Err("Expected HelloFromClient message".into());
+                }
+            };
+
+        let protocol_version = match negotiate_protocol_version(client_protocol_version) {
+            Some(protocol_version) => protocol_version,
+            None => {
+                let reason = format!(
+                    "Client protocol version {} is not supported. Server speaks version {}, please update your client.",
+                    client_protocol_version, PROTOCOL_VERSION
+                );
+
+                if client_protocol_version == LEGACY_PROTOCOL_VERSION {
+                    stream.write_legacy_rejection(&reason).await?;
+                } else {
+                    stream
+                        .write_message(Message::IncompatibleProtocol { reason })
+                        .await?;
+                }
+
+                info!(
+                    "Client sent unsupported protocol version: {}",
+                    client_protocol_version
+                );
+
+                return Ok(());
             }
         };
 
+        let capabilities = Capabilities::default().negotiate(&client_capabilities);
+
+        if capabilities.frame_formats.is_empty() {
+            stream
+                .write_message(Message::IncompatibleProtocol {
+                    reason: "Client and server have no frame format in common.".to_string(),
+                })
+                .await?;
+
+            info!("Client sent no supported frame formats");
+
+            return Ok(());
+        }
+
         if !is_valid_username(&potential_username) {
-            write_command_to_tcp_stream(
-                TcpCommand::WithStringPayload {
-                    command_type: TcpCommandType::InvalidUsername,
-                    payload: "Username must contain only letters, numbers, underscores (_), or hyphens (-)."
+            let response_message = Message::InvalidUsername {
+                reason:
+                    "Username must contain only letters, numbers, underscores (_), or hyphens (-)."
                         .to_string(),
-                },
-                stream,
-            )
-            .await?;
+            };
+
+            stream.write_message(response_message).await?;
 
             info!("Client sent invalid username");
 
@@ -72,14 +131,11 @@ impl TcpHandler {
         }
 
         if potential_username.len() > 20 {
-            write_command_to_tcp_stream(
-                TcpCommand::WithStringPayload {
-                    command_type: TcpCommandType::InvalidUsername,
-                    payload: "Username must be less than or equal to 20 characters.".to_string(),
-                },
-                stream,
-            )
-            .await?;
+            stream
+                .write_message(Message::InvalidUsername {
+                    reason: "Username must be less than or equal to 20 characters.".to_string(),
+                })
+                .await?;
 
             info!("Client sent invalid username");
 
@@ -92,144 +148,288 @@ impl TcpHandler {
             .await
             .contains(&potential_username)
         {
-            write_command_to_tcp_stream(
-                TcpCommand::WithStringPayload {
-                    command_type: TcpCommandType::InvalidUsername,
-                    payload: format!("Username '{}' is already taken.", potential_username),
-                },
-                stream,
-            )
-            .await?;
+            stream
+                .write_message(Message::InvalidUsername {
+                    reason: format!("Username '{}' is already taken.", potential_username),
+                })
+                .await?;
 
             info!("Client sent invalid username");
 
             return Ok(());
         }
 
-        write_command_to_tcp_stream(TcpCommand::Simple(TcpCommandType::HelloFromServer), stream)
+        stream
+            .write_message(Message::HelloFromServer {
+                protocol_version,
+                capabilities,
+            })
             .await?;
 
+        self.protocol_version = protocol_version;
+        self.capabilities = capabilities;
+
         let current_username = potential_username;
 
-        self.handle_connect_user(&current_username).await;
+        // Both channels are listened on from the start, so lobby chat
+        // reaches users who are not in a call yet.
+        let mut chat_channel_rx = self.handle_connect_user(&current_username).await;
+
+        let mut tcp_command_channel_rx = match self
+            .username_to_command_channel_tx
+            .lock()
+            .await
+            .get(&current_username)
+        {
+            Some(tx) => tx.subscribe(),
+            None => {
+                return Err(format!(
+                    "Could not find tcp_command_channel_rx for user: {}",
+                    current_username
+                )
+                .into());
+            }
+        };
 
         loop {
             tokio::select! {
 
-                result = read_command_from_tcp_stream(stream) => {
+                result = stream.read_message() => {
 
-                    let command_option = result?;
+                    let message_option = match result {
+                        Ok(message_option) => message_option,
+                        Err(e) if e.is_recoverable() => {
+                            reply_malformed_message(stream, &e).await?;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
 
-                    let command = match command_option {
-                        Some(command) => command,
+                    let message = match message_option {
+                        Some(message) => message,
                         None => return Ok(()),
                     };
 
-                    let started_call = self.handle_command_from_user(command, stream).await?;
+                    let started_call = self.handle_message_from_user(message, stream).await?;
 
                     if started_call {
                         break;
                     }
                 }
-            }
-        }
 
-        let tcp_command_channel_rx = match self
-            .username_to_command_channel_tx
-            .lock()
-            .await
-            .get(&current_username)
-        {
-            Some(tx) => tx.subscribe(),
-            None => {
-                return Err(format!(
-                    "Could not find tcp_command_channel_rx for user: {}",
-                    current_username
-                )
-                .into());
+                result = tcp_command_channel_rx.recv() => {
+
+                    let message = match result {
+                        Ok(message) => message,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            self.log_lagged(skipped).await;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    self.forward_to_user(message, stream).await?;
+                }
+
+                message_option = chat_channel_rx.recv() => {
+
+                    let message = match message_option {
+                        Some(message) => message,
+                        None => return Err("Chat channel closed".into()),
+                    };
+
+                    self.forward_to_user(message, stream).await?;
+                }
             }
-        };
+        }
 
-        self.handle_call_stream(stream, tcp_command_channel_rx)
+        self.handle_call_stream(stream, tcp_command_channel_rx, chat_channel_rx)
             .await?;
 
         return Ok(());
     }
 
-    async fn handle_call_stream(
+    async fn handle_call_stream<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
-        stream: &mut TcpStream,
-        mut tcp_command_channel_rx: broadcast::Receiver<TcpCommand>,
+        stream: &mut MessageStream<S>,
+        mut tcp_command_channel_rx: broadcast::Receiver<Message>,
+        mut chat_channel_rx: mpsc::Receiver<Message>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         loop {
             tokio::select! {
 
-                result = read_command_from_tcp_stream(stream) => {
+                result = stream.read_message() => {
 
-                    if result? == None {
-                        return Ok(());
+                    match result {
+                        Ok(Some(Message::SetPreferredResolution { resolution })) => {
+                            self.handle_preferred_resolution(resolution).await?;
+                        }
+                        Ok(Some(Message::RequestKeyframe { rsid })) => {
+                            self.handle_keyframe_request(rsid).await?;
+                        }
+                        Ok(Some(Message::ReportReception { rsid, report })) => {
+                            self.handle_reception_report(rsid, report).await?;
+                        }
+                        Ok(Some(Message::SendChat { scope, text })) => {
+                            self.handle_send_chat(scope, text, stream).await?;
+                        }
+                        Ok(Some(Message::DirectMessage { recipient, text })) => {
+                            self.handle_direct_message(recipient, text, stream).await?;
+                        }
+                        Ok(Some(_)) => {}
+                        Ok(None) => return Ok(()),
+                        Err(e) if e.is_recoverable() => {
+                            reply_malformed_message(stream, &e).await?;
+                        }
+                        Err(e) => return Err(e.into()),
                     }
                 }
 
                 result = tcp_command_channel_rx.recv() => {
 
-                    let command = result?;
+                    let message = match result {
+                        Ok(message) => message,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            self.log_lagged(skipped).await;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    self.forward_to_user(message, stream).await?;
+                }
 
-                    write_command_to_tcp_stream(command, stream).await?;
+                message_option = chat_channel_rx.recv() => {
+
+                    let message = match message_option {
+                        Some(message) => message,
+                        None => return Err("Chat channel closed".into()),
+                    };
+
+                    self.forward_to_user(message, stream).await?;
                 }
             }
         }
     }
 
-    pub async fn handle_command_from_user(
+    /// Logs that this connection fell behind its command channel. Chat has
+    /// its own channel, so only a flood of room updates gets here; the user
+    /// stays connected and misses `skipped` of them.
+    async fn log_lagged(&self, skipped: u64) {
+        let current_username = self.current_username.lock().await.clone();
+
+        warn!(
+            "{} fell behind and missed {} messages",
+            current_username.unwrap_or_default(),
+            skipped
+        );
+    }
+
+    /// Passes on a message from another user's connection, unless this
+    /// client's protocol version predates it. The sender of a direct message
+    /// is told once it has been written out, or that it never will be.
+    async fn forward_to_user<S: AsyncWrite + Unpin>(
         &self,
-        command: TcpCommand,
-        stream: &mut TcpStream,
+        message: Message,
+        stream: &mut MessageStream<S>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = self
+            .current_username
+            .lock()
+            .await
+            .clone()
+            .unwrap_or_default();
+
+        let direct_message_sender = match &message {
+            Message::DirectMessageReceived {
+                sender, timestamp, ..
+            } => Some((sender.clone(), *timestamp)),
+            _ => None,
+        };
+
+        if message.min_protocol_version() > self.protocol_version {
+            if let Some((sender, _)) = direct_message_sender {
+                let reason = format!(
+                    "User '{}' is using a client that cannot receive direct messages.",
+                    current_username
+                );
+                self.send_to_user(&sender, Message::InvalidDirectMessage { reason })
+                    .await;
+            }
+
+            return Ok(());
+        }
+
+        stream.write_message(message).await?;
+
+        if let Some((sender, timestamp)) = direct_message_sender {
+            let acknowledgement = Message::DirectMessageDelivered {
+                recipient: current_username,
+                timestamp,
+            };
+            self.send_to_user(&sender, acknowledgement).await;
+        }
+
+        return Ok(());
+    }
+
+    /// Queues `message` on `username`'s channel, if they are still connected.
+    async fn send_to_user(&self, username: &str, message: Message) {
+        if let Some(tx) = self
+            .username_to_command_channel_tx
+            .lock()
+            .await
+            .get(username)
+            && let Err(e) = tx.send(message)
+        {
+            error!("Error sending to channel: {} for user: {}", e, username);
+        }
+    }
+
+    pub async fn handle_message_from_user<S: AsyncWrite + Unpin>(
+        &self,
+        message: Message,
+        stream: &mut MessageStream<S>,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        match command {
-            TcpCommand::Simple(TcpCommandType::GetActiveUsers) => {
+        match message {
+            Message::GetActiveUsers => {
                 let active_usernames: Vec<String> =
                     self.active_usernames.lock().await.iter().cloned().collect();
 
-                let response_command = TcpCommand::WithMultiStringPayload {
-                    command_type: TcpCommandType::ReturnActiveUsers,
-                    payload: active_usernames,
+                let response_message = Message::ReturnActiveUsers {
+                    usernames: active_usernames,
                 };
 
-                write_command_to_tcp_stream(response_command, stream).await?;
+                stream.write_message(response_message).await?;
                 return Ok(false);
             }
-            TcpCommand::WithStringPayload {
-                command_type: TcpCommandType::CreateRoom,
-                payload,
+            Message::CreateRoom {
+                room: room_name,
+                mode,
             } => {
-                let room_name = payload;
-
                 let current_username = match self.current_username.lock().await.clone() {
                     Some(current_username) => current_username,
                     None => return Err("Invalid user when creating room".into()),
                 };
 
                 if !is_valid_room_name(&room_name) {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidRoomName,
-                        payload:
+                    let response_message = Message::InvalidRoomName {
+                        reason:
                             "Room name must contain only letters, numbers, underscores (_), or hyphens (-)."
                                 .to_string(),
                     };
 
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
                     return Ok(false);
                 }
 
                 if room_name.len() > 20 {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidRoomName,
-                        payload: "Room name must be less than or equal to 20 characters."
+                    let response_message = Message::InvalidRoomName {
+                        reason: "Room name must be less than or equal to 20 characters."
                             .to_string(),
                     };
 
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
                     return Ok(false);
                 }
 
@@ -239,34 +439,37 @@ impl TcpHandler {
                     public_rooms_guard.iter().any(|room| room.name == room_name);
 
                 if room_name_is_taken {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidRoomName,
-                        payload: format!("Room: '{}' already exists.", room_name).to_string(),
+                    let response_message = Message::InvalidRoomName {
+                        reason: format!("Room: '{}' already exists.", room_name).to_string(),
                     };
 
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
                     return Ok(false);
                 }
 
                 public_rooms_guard.push(Room {
                     name: room_name.clone(),
+                    mode,
                     username_to_rsid: HashMap::new(),
+                    username_to_preferred_resolution: HashMap::new(),
+                    username_to_frame_formats: HashMap::new(),
+                    username_to_max_frame_size: HashMap::new(),
+                    reception_reports: HashMap::new(),
+                    composite_keyframe_requests: HashSet::new(),
                 });
 
-                info!("{} created room: {}", current_username, room_name);
+                info!(
+                    "{} created room: {} ({:?})",
+                    current_username, room_name, mode
+                );
 
-                let response_command = TcpCommand::Simple(TcpCommandType::CreateRoomSuccess);
-                write_command_to_tcp_stream(response_command, stream).await?;
+                let response_message = Message::CreateRoomSuccess;
+                stream.write_message(response_message).await?;
 
                 return Ok(false);
             }
 
-            TcpCommand::WithStringPayload {
-                command_type: TcpCommandType::DeleteRoom,
-                payload,
-            } => {
-                let room_name = payload;
-
+            Message::DeleteRoom { room: room_name } => {
                 let current_username = match self.current_username.lock().await.clone() {
                     Some(current_username) => current_username,
                     None => return Err("Invalid user when creating room".into()),
@@ -276,19 +479,18 @@ impl TcpHandler {
 
                 let room_contains_users = public_rooms
                     .iter()
-                    .any(|r| r.name == room_name && r.username_to_rsid.len() > 0);
+                    .any(|r| r.name == room_name && !r.username_to_rsid.is_empty());
 
                 if room_contains_users {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidRoomName,
-                        payload: format!(
+                    let response_message = Message::InvalidRoomName {
+                        reason: format!(
                             "Room '{}' is in use and cannot be deleted at this time.",
                             room_name
                         )
                         .to_string(),
                     };
 
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
                     return Ok(false);
                 }
 
@@ -299,24 +501,23 @@ impl TcpHandler {
                 let rooms_deleted = before_len - after_len;
 
                 if rooms_deleted == 0 {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidRoomName,
-                        payload: format!("Room: '{}' does not exist.", room_name).to_string(),
+                    let response_message = Message::InvalidRoomName {
+                        reason: format!("Room: '{}' does not exist.", room_name).to_string(),
                     };
 
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
                     return Ok(false);
                 }
 
                 info!("{} deleted room: {}", current_username, room_name);
 
-                let response_command = TcpCommand::Simple(TcpCommandType::DeleteRoomSuccess);
-                write_command_to_tcp_stream(response_command, stream).await?;
+                let response_message = Message::DeleteRoomSuccess;
+                stream.write_message(response_message).await?;
 
                 return Ok(false);
             }
 
-            TcpCommand::Simple(TcpCommandType::GetRooms) => {
+            Message::GetRooms => {
                 let room_names = self
                     .public_rooms
                     .lock()
@@ -325,21 +526,13 @@ impl TcpHandler {
                     .map(|room| room.name.clone())
                     .collect();
 
-                let response_command = TcpCommand::WithMultiStringPayload {
-                    command_type: TcpCommandType::ReturnRooms,
-                    payload: room_names,
-                };
+                let response_message = Message::ReturnRooms { rooms: room_names };
 
-                write_command_to_tcp_stream(response_command, stream).await?;
+                stream.write_message(response_message).await?;
                 return Ok(false);
             }
 
-            TcpCommand::WithStringPayload {
-                command_type: TcpCommandType::JoinRoom,
-                payload,
-            } => {
-                let room_name = payload;
-
+            Message::JoinRoom { room: room_name } => {
                 let mut rooms = self.public_rooms.lock().await;
 
                 if let Some(room) = rooms.iter_mut().find(|room| room.name == room_name) {
@@ -360,7 +553,7 @@ impl TcpHandler {
                         let guard = self.current_username.lock().await;
                         guard
                             .clone()
-                            .ok_or_else(|| "Could not find username when assigning StreamID")?
+                            .ok_or("Could not find username when assigning StreamID")?
                     };
 
                     self.sid_to_username_map
@@ -368,9 +561,16 @@ impl TcpHandler {
                         .await
                         .insert(sid, current_username.clone());
 
-                    let rsid: RoomStreamID = rng().random();
+                    let mut rsid: RoomStreamID = rng().random();
+                    while rsid == COMPOSITE_RSID {
+                        rsid = rng().random();
+                    }
 
                     room.username_to_rsid.insert(current_username.clone(), rsid);
+                    room.username_to_frame_formats
+                        .insert(current_username.clone(), self.capabilities.frame_formats);
+                    room.username_to_max_frame_size
+                        .insert(current_username.clone(), self.capabilities.max_frame_size);
 
                     for user in room.username_to_rsid.keys() {
                         if user == &current_username {
@@ -382,66 +582,399 @@ impl TcpHandler {
                         let tx_option = username_to_command_channel_tx_guard.get(user);
 
                         if let Some(tx) = tx_option {
-                            let command = TcpCommand::WithRoomStreamIDPayload {
-                                command_type: TcpCommandType::OtherUserJoinedRoom,
-                                payload: rsid,
-                            };
+                            let message = Message::OtherUserJoinedRoom { rsid };
 
-                            if let Err(e) = tx.send(command) {
+                            if let Err(e) = tx.send(message) {
                                 error!("Error sending to channel: {} for user: {}", e, user);
                             }
                         }
                     }
 
+                    self.send_to_others_in_room(room, &current_username, |user| {
+                        supported_frame_formats(room, user)
+                            .map(|frame_formats| Message::SupportedFrameFormats { frame_formats })
+                    })
+                    .await;
+
                     info!("{} joined room: {}", current_username, room.name);
 
-                    let response_command = TcpCommand::WithStreamIDPayload {
-                        command_type: TcpCommandType::JoinRoomSuccess,
-                        payload: sid,
+                    let response_message = Message::JoinRoomSuccess {
+                        sid,
+                        mode: room.mode,
                     };
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
 
                     for (user, rsid) in room.username_to_rsid.iter() {
                         if user != &current_username {
-                            let add_user_command = TcpCommand::WithRoomStreamIDPayload {
-                                command_type: TcpCommandType::OtherUserJoinedRoom,
-                                payload: *rsid,
-                            };
-                            write_command_to_tcp_stream(add_user_command, stream).await?;
+                            let add_user_message = Message::OtherUserJoinedRoom { rsid: *rsid };
+                            stream.write_message(add_user_message).await?;
                         }
                     }
 
+                    if room.mode == RoomMode::Composite {
+                        let composite_message = Message::OtherUserJoinedRoom {
+                            rsid: COMPOSITE_RSID,
+                        };
+                        stream.write_message(composite_message).await?;
+                    }
+
+                    if let Some(resolution) = requested_resolution(room, &current_username) {
+                        let response_message = Message::RequestResolution { resolution };
+                        stream.write_message(response_message).await?;
+                    }
+
+                    if let Some(frame_formats) = supported_frame_formats(room, &current_username) {
+                        let response_message = Message::SupportedFrameFormats { frame_formats };
+                        stream.write_message(response_message).await?;
+                    }
+
                     return Ok(true);
                 } else {
-                    let response_command = TcpCommand::WithStringPayload {
-                        command_type: TcpCommandType::InvalidJoinRoom,
-                        payload: "Room not found".to_string(),
+                    let response_message = Message::InvalidJoinRoom {
+                        reason: "Room not found".to_string(),
                     };
-                    write_command_to_tcp_stream(response_command, stream).await?;
+                    stream.write_message(response_message).await?;
 
                     return Ok(false);
                 }
             }
 
-            _ => return Err(format!("Command not handled {:?}", command).into()),
+            Message::SendChat { scope, text } => {
+                self.handle_send_chat(scope, text, stream).await?;
+                return Ok(false);
+            }
+
+            Message::DirectMessage { recipient, text } => {
+                self.handle_direct_message(recipient, text, stream).await?;
+                return Ok(false);
+            }
+
+            _ => return Err(format!("Message not handled {:?}", message).into()),
+        }
+    }
+
+    async fn handle_preferred_resolution(
+        &self,
+        resolution: Resolution,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = match self.current_username.lock().await.clone() {
+            Some(current_username) => current_username,
+            None => return Err("Invalid user when setting preferred resolution".into()),
+        };
+
+        let mut rooms = self.public_rooms.lock().await;
+
+        let room = match rooms
+            .iter_mut()
+            .find(|room| room.username_to_rsid.contains_key(&current_username))
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        // Senders make frames of up to this size for this user, and in
+        // composite rooms the server does, so it must fit in a frame in any
+        // format this user decodes.
+        let resolution = largest_video_format(self.capabilities.frame_formats)
+            .fit_frame_size(resolution, self.capabilities.max_frame_size as usize);
+
+        room.username_to_preferred_resolution
+            .insert(current_username.clone(), resolution);
+
+        self.send_to_others_in_room(room, &current_username, |user| {
+            requested_resolution(room, user)
+                .map(|resolution| Message::RequestResolution { resolution })
+        })
+        .await;
+
+        return Ok(());
+    }
+
+    async fn handle_keyframe_request(
+        &self,
+        rsid: RoomStreamID,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = match self.current_username.lock().await.clone() {
+            Some(current_username) => current_username,
+            None => return Err("Invalid user when requesting keyframe".into()),
+        };
+
+        let sender_username_option = {
+            let mut rooms = self.public_rooms.lock().await;
+
+            let room = match rooms
+                .iter_mut()
+                .find(|room| room.username_to_rsid.contains_key(&current_username))
+            {
+                Some(room) => room,
+                None => return Ok(()),
+            };
+
+            // The composite stream is built here, so its keyframes are too.
+            if rsid == COMPOSITE_RSID {
+                room.composite_keyframe_requests.insert(current_username);
+                return Ok(());
+            }
+
+            username_for_rsid(room, rsid)
+        };
+
+        let sender_username = match sender_username_option {
+            Some(sender_username) => sender_username,
+            None => return Ok(()),
+        };
+
+        if let Some(tx) = self
+            .username_to_command_channel_tx
+            .lock()
+            .await
+            .get(&sender_username)
+            && let Err(e) = tx.send(Message::KeyframeRequested)
+        {
+            error!(
+                "Error sending to channel: {} for user: {}",
+                e, sender_username
+            );
+        }
+
+        return Ok(());
+    }
+
+    async fn handle_reception_report(
+        &self,
+        rsid: RoomStreamID,
+        report: ReceptionReport,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = match self.current_username.lock().await.clone() {
+            Some(current_username) => current_username,
+            None => return Err("Invalid user when reporting reception".into()),
+        };
+
+        let mut rooms = self.public_rooms.lock().await;
+
+        let room = match rooms
+            .iter_mut()
+            .find(|room| room.username_to_rsid.contains_key(&current_username))
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        let sender_username = match username_for_rsid(room, rsid) {
+            Some(sender_username) => sender_username,
+            None => return Ok(()),
+        };
+
+        room.reception_reports
+            .insert((sender_username.clone(), current_username), report);
+
+        let report = match reception_feedback(room, &sender_username) {
+            Some(report) => report,
+            None => return Ok(()),
+        };
+
+        if let Some(tx) = self
+            .username_to_command_channel_tx
+            .lock()
+            .await
+            .get(&sender_username)
+            && let Err(e) = tx.send(Message::ReceptionFeedback { report })
+        {
+            error!(
+                "Error sending to channel: {} for user: {}",
+                e, sender_username
+            );
+        }
+
+        return Ok(());
+    }
+
+    async fn handle_send_chat<S: AsyncWrite + Unpin>(
+        &self,
+        scope: ChatScope,
+        text: String,
+        stream: &mut MessageStream<S>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = match self.current_username.lock().await.clone() {
+            Some(current_username) => current_username,
+            None => return Err("Invalid user when sending chat".into()),
+        };
+
+        if let Some(reason) = chat_text_problem(&text) {
+            stream
+                .write_message(Message::InvalidChat { reason })
+                .await?;
+            return Ok(());
+        }
+
+        let recipients: Vec<String> = match scope {
+            ChatScope::Lobby => self.active_usernames.lock().await.clone(),
+            ChatScope::Room => {
+                let rooms = self.public_rooms.lock().await;
+
+                match rooms
+                    .iter()
+                    .find(|room| room.username_to_rsid.contains_key(&current_username))
+                {
+                    Some(room) => room.username_to_rsid.keys().cloned().collect(),
+                    None => {
+                        let response_message = Message::InvalidChat {
+                            reason: "You are not in a room.".to_string(),
+                        };
+
+                        stream.write_message(response_message).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let message = Message::ChatMessage {
+            scope,
+            sender: current_username.clone(),
+            timestamp: unix_millis(),
+            text,
+        };
+
+        let username_to_chat_channel_tx_guard = self.username_to_chat_channel_tx.lock().await;
+
+        // Users too far behind to queue more chat miss this message, rather
+        // than holding up everyone else's.
+        for user in recipients {
+            if let Some(tx) = username_to_chat_channel_tx_guard.get(&user)
+                && let Err(e) = tx.try_send(message.clone())
+            {
+                warn!("Error sending chat to channel: {} for user: {}", e, user);
+            }
+        }
+
+        info!("{} sent a {:?} chat message", current_username, scope);
+
+        return Ok(());
+    }
+
+    async fn handle_direct_message<S: AsyncWrite + Unpin>(
+        &self,
+        recipient: String,
+        text: String,
+        stream: &mut MessageStream<S>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current_username = match self.current_username.lock().await.clone() {
+            Some(current_username) => current_username,
+            None => return Err("Invalid user when sending direct message".into()),
+        };
+
+        if let Some(reason) = chat_text_problem(&text) {
+            let response_message = Message::InvalidDirectMessage { reason };
+            stream.write_message(response_message).await?;
+            return Ok(());
+        }
+
+        let timestamp = unix_millis();
+
+        let message = Message::DirectMessageReceived {
+            sender: current_username.clone(),
+            timestamp,
+            text,
+        };
+
+        // Every connection listens on its chat channel from the handshake
+        // on, whether in a call or not, and a full channel turns the message
+        // away rather than dropping it later, so the sender always hears
+        // back: now if it cannot be queued, or from the recipient's
+        // connection once it has written the message out.
+        let queued = match self
+            .username_to_chat_channel_tx
+            .lock()
+            .await
+            .get(&recipient)
+        {
+            Some(tx) => tx.try_send(message),
+            None => Err(TrySendError::Closed(message)),
+        };
+
+        let reason = match queued {
+            Ok(()) => None,
+            Err(TrySendError::Full(_)) => Some(format!(
+                "User '{}' is not keeping up with messages. Try again later.",
+                recipient
+            )),
+            Err(TrySendError::Closed(_)) => Some(format!("User '{}' is not online.", recipient)),
+        };
+
+        if let Some(reason) = reason {
+            stream
+                .write_message(Message::InvalidDirectMessage { reason })
+                .await?;
+            return Ok(());
+        }
+
+        info!(
+            "{} sent a direct message to {}",
+            current_username, recipient
+        );
+
+        return Ok(());
+    }
+
+    /// Sends everyone in `room` except `changed_username` the message
+    /// `message_for` builds for them, after something about
+    /// `changed_username` changed.
+    async fn send_to_others_in_room(
+        &self,
+        room: &Room,
+        changed_username: &str,
+        message_for: impl Fn(&str) -> Option<Message>,
+    ) {
+        let username_to_command_channel_tx_guard = self.username_to_command_channel_tx.lock().await;
+
+        for user in room.username_to_rsid.keys() {
+            if user == changed_username {
+                continue;
+            }
+
+            let message = match message_for(user) {
+                Some(message) => message,
+                None => continue,
+            };
+
+            if let Some(tx) = username_to_command_channel_tx_guard.get(user)
+                && let Err(e) = tx.send(message)
+            {
+                error!("Error sending to channel: {} for user: {}", e, user);
+            }
         }
     }
 
-    pub async fn handle_connect_user(&self, current_username: &str) {
+    /// Registers the user's channels, returning the receiving end of their
+    /// chat channel.
+    pub async fn handle_connect_user(&self, current_username: &str) -> mpsc::Receiver<Message> {
         let mut current_username_guard = self.current_username.lock().await;
         *current_username_guard = Some(current_username.to_string());
 
         let mut active_usernames_guard = self.active_usernames.lock().await;
         active_usernames_guard.push(current_username.to_string());
 
-        let tx = broadcast::Sender::new(16);
+        let tx = broadcast::Sender::new(64);
 
         self.username_to_command_channel_tx
             .lock()
             .await
             .insert(current_username.to_string(), tx);
 
-        info!("{} is connected", current_username);
+        let (chat_tx, chat_rx) = mpsc::channel(CHAT_CHANNEL_CAPACITY);
+
+        self.username_to_chat_channel_tx
+            .lock()
+            .await
+            .insert(current_username.to_string(), chat_tx);
+
+        info!(
+            "{} is connected (protocol version {}, {:?})",
+            current_username, self.protocol_version, self.capabilities
+        );
+
+        return chat_rx;
     }
 
     pub async fn handle_disconnect_user(&self) {
@@ -453,6 +986,10 @@ impl TcpHandler {
                 .lock()
                 .await
                 .remove(&current_username);
+            self.username_to_chat_channel_tx
+                .lock()
+                .await
+                .remove(&current_username);
 
             for room in self.public_rooms.lock().await.iter_mut() {
                 if let Some(rsid) = room.username_to_rsid.get(&current_username) {
@@ -466,20 +1003,44 @@ impl TcpHandler {
                         let tx_option = username_to_command_channel_tx_guard.get(user);
 
                         if let Some(tx) = tx_option {
-                            let command = TcpCommand::WithRoomStreamIDPayload {
-                                command_type: TcpCommandType::OtherUserLeftRoom,
-                                payload: *rsid,
-                            };
+                            let message = Message::OtherUserLeftRoom { rsid: *rsid };
 
-                            if let Err(e) = tx.send(command) {
+                            if let Err(e) = tx.send(message) {
                                 error!("Error sending to channel: {}", e);
                             }
                         }
                     }
                 }
 
-                if let Some(_) = room.username_to_rsid.remove(&current_username) {
+                if room.username_to_rsid.remove(&current_username).is_some() {
                     info!("{} left room: {}", current_username, room.name);
+
+                    room.username_to_frame_formats.remove(&current_username);
+                    room.username_to_max_frame_size.remove(&current_username);
+                    room.username_to_preferred_resolution
+                        .remove(&current_username);
+                    room.reception_reports.retain(|(sender, receiver), _| {
+                        sender != &current_username && receiver != &current_username
+                    });
+                    room.composite_keyframe_requests.remove(&current_username);
+
+                    self.send_to_others_in_room(room, &current_username, |user| {
+                        supported_frame_formats(room, user)
+                            .map(|frame_formats| Message::SupportedFrameFormats { frame_formats })
+                    })
+                    .await;
+
+                    self.send_to_others_in_room(room, &current_username, |user| {
+                        requested_resolution(room, user)
+                            .map(|resolution| Message::RequestResolution { resolution })
+                    })
+                    .await;
+
+                    self.send_to_others_in_room(room, &current_username, |user| {
+                        reception_feedback(room, user)
+                            .map(|report| Message::ReceptionFeedback { report })
+                    })
+                    .await;
                 }
             }
 
@@ -488,14 +1049,258 @@ impl TcpHandler {
     }
 }
 
+fn requested_resolution(room: &Room, username: &str) -> Option<Resolution> {
+    room.username_to_preferred_resolution
+        .iter()
+        .filter(|(other_username, _)| other_username.as_str() != username)
+        .map(|(_, resolution)| *resolution)
+        .reduce(Resolution::max)
+}
+
+/// Worst reception of `username`'s stream among the others in `room`.
+fn reception_feedback(room: &Room, username: &str) -> Option<ReceptionReport> {
+    room.reception_reports
+        .iter()
+        .filter(|((sender, _), _)| sender.as_str() == username)
+        .map(|(_, report)| *report)
+        .reduce(ReceptionReport::worst)
+}
+
+/// The format in `frame_formats` whose frames take the most bytes.
+fn largest_video_format(frame_formats: FrameFormats) -> VideoFormat {
+    return [
+        VideoFormat::ByteColor,
+        VideoFormat::NibbleColor,
+        VideoFormat::ByteGray,
+        VideoFormat::NibbleGray,
+    ]
+    .into_iter()
+    .find(|format| frame_formats.contains(format.frame_format()))
+    .unwrap_or(VideoFormat::NibbleGray);
+}
+
+fn username_for_rsid(room: &Room, rsid: RoomStreamID) -> Option<String> {
+    room.username_to_rsid
+        .iter()
+        .find(|(_, user_rsid)| **user_rsid == rsid)
+        .map(|(username, _)| username.clone())
+}
+
+/// Formats `username` may send in, which everyone else in `room` can decode.
+/// Composite rooms decode on the server, so there any format goes.
+fn supported_frame_formats(room: &Room, username: &str) -> Option<FrameFormats> {
+    if room.mode == RoomMode::Composite {
+        return None;
+    }
+
+    room.username_to_frame_formats
+        .iter()
+        .filter(|(other_username, _)| other_username.as_str() != username)
+        .map(|(_, frame_formats)| *frame_formats)
+        .reduce(FrameFormats::intersection)
+}
+
+async fn reply_malformed_message<S: AsyncWrite + Unpin>(
+    stream: &mut MessageStream<S>,
+    error: &ProtocolError,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!("Client sent malformed message: {}", error);
+
+    let response_message = Message::MalformedMessage {
+        reason: error.to_string(),
+    };
+    stream.write_message(response_message).await?;
+
+    return Ok(());
+}
+
 fn is_valid_username(username: &str) -> bool {
     username
         .chars()
         .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
 
+/// Why `text` cannot be sent as a chat or direct message, if it cannot.
+fn chat_text_problem(text: &str) -> Option<String> {
+    if text.trim().is_empty() {
+        return Some("Message is empty.".to_string());
+    }
+
+    if text.len() > MAX_CHAT_LEN {
+        return Some(format!(
+            "Message must be less than or equal to {} bytes.",
+            MAX_CHAT_LEN
+        ));
+    }
+
+    if text.chars().any(char::is_control) {
+        return Some("Message must not contain control characters.".to_string());
+    }
+
+    return None;
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 fn is_valid_room_name(room_name: &str) -> bool {
     room_name
         .chars()
         .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
+
+#[cfg(test)]
+mod tests {
+    use shared::{MAX_FRAME_SIZE, VIDEO_HEADER_LEN};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_preferred_resolution_is_clamped() {
+        let room = Room {
+            name: "lobby".to_string(),
+            mode: RoomMode::Forward,
+            username_to_rsid: HashMap::from([("alice".to_string(), [1])]),
+            username_to_preferred_resolution: HashMap::new(),
+            username_to_frame_formats: HashMap::new(),
+            username_to_max_frame_size: HashMap::new(),
+            reception_reports: HashMap::new(),
+            composite_keyframe_requests: HashSet::new(),
+        };
+        let public_rooms = Arc::new(Mutex::new(vec![room]));
+
+        let handler = TcpHandler::new(
+            Arc::new(Mutex::new(Vec::new())),
+            public_rooms.clone(),
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(HashMap::new())),
+        )
+        .await;
+        handler.handle_connect_user("alice").await;
+
+        handler
+            .handle_preferred_resolution(Resolution::new(u16::MAX, u16::MAX))
+            .await
+            .unwrap();
+
+        let rooms = public_rooms.lock().await;
+        let resolution = rooms[0].username_to_preferred_resolution["alice"];
+
+        let frame_len = VIDEO_HEADER_LEN + VideoFormat::ByteColor.cells_len(resolution);
+        assert!(frame_len <= MAX_FRAME_SIZE as usize);
+        assert!(resolution.width > 1 && resolution.height > 1);
+    }
+
+    type CommandChannels = Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>;
+
+    /// Handlers for `usernames`, connected to one another, with each one's
+    /// chat channel.
+    async fn connect_users(
+        usernames: &[&str],
+    ) -> (Vec<(TcpHandler, mpsc::Receiver<Message>)>, CommandChannels) {
+        let active_usernames = Arc::new(Mutex::new(Vec::new()));
+        let public_rooms = Arc::new(Mutex::new(Vec::new()));
+        let sid_to_username_map = Arc::new(Mutex::new(HashMap::new()));
+        let username_to_command_channel_tx = Arc::new(Mutex::new(HashMap::new()));
+        let username_to_chat_channel_tx = Arc::new(Mutex::new(HashMap::new()));
+
+        let mut handlers = Vec::new();
+        for username in usernames {
+            let handler = TcpHandler::new(
+                active_usernames.clone(),
+                public_rooms.clone(),
+                sid_to_username_map.clone(),
+                username_to_command_channel_tx.clone(),
+                username_to_chat_channel_tx.clone(),
+            )
+            .await;
+            let chat_rx = handler.handle_connect_user(username).await;
+            handlers.push((handler, chat_rx));
+        }
+
+        return (handlers, username_to_command_channel_tx);
+    }
+
+    #[tokio::test]
+    async fn chat_floods_do_not_crowd_out_room_updates() {
+        let (handlers, command_channels) = connect_users(&["alice", "bob"]).await;
+        let alice = &handlers[0].0;
+        let mut bob_rx = command_channels.lock().await["bob"].subscribe();
+
+        let (alice_stream, _alice_client) = tokio::io::duplex(1024);
+        let mut alice_stream = MessageStream::new(alice_stream);
+        for _ in 0..CHAT_CHANNEL_CAPACITY * 2 {
+            alice
+                .handle_send_chat(ChatScope::Lobby, "spam".to_string(), &mut alice_stream)
+                .await
+                .unwrap();
+        }
+
+        command_channels.lock().await["bob"]
+            .send(Message::KeyframeRequested)
+            .unwrap();
+
+        assert!(matches!(bob_rx.try_recv(), Ok(Message::KeyframeRequested)));
+    }
+
+    #[tokio::test]
+    async fn direct_message_is_confirmed_once_written_to_the_recipient() {
+        let (handlers, command_channels) = connect_users(&["alice", "bob"]).await;
+        let mut handlers = handlers.into_iter();
+        let (alice, _) = handlers.next().unwrap();
+        let (bob, mut bob_chat_rx) = handlers.next().unwrap();
+
+        let mut alice_rx = command_channels.lock().await["alice"].subscribe();
+
+        let (alice_stream, _alice_client) = tokio::io::duplex(1024);
+        let mut alice_stream = MessageStream::new(alice_stream);
+        alice
+            .handle_direct_message("bob".to_string(), "psst".to_string(), &mut alice_stream)
+            .await
+            .unwrap();
+
+        // Nothing is confirmed before bob's connection writes it out.
+        assert!(alice_rx.try_recv().is_err());
+
+        let (bob_stream, bob_client) = tokio::io::duplex(1024);
+        let mut bob_stream = MessageStream::new(bob_stream);
+        let message = bob_chat_rx.recv().await.unwrap();
+        bob.forward_to_user(message, &mut bob_stream).await.unwrap();
+
+        let mut bob_client = MessageStream::new(bob_client);
+        assert!(matches!(
+            bob_client.read_message().await.unwrap(),
+            Some(Message::DirectMessageReceived { sender, .. }) if sender == "alice"
+        ));
+        assert!(matches!(
+            alice_rx.try_recv().unwrap(),
+            Message::DirectMessageDelivered { recipient, .. } if recipient == "bob"
+        ));
+    }
+
+    #[tokio::test]
+    async fn direct_message_to_a_lagging_recipient_is_refused() {
+        let (handlers, _command_channels) = connect_users(&["alice", "bob"]).await;
+        let (alice, _) = &handlers[0];
+
+        let (alice_stream, alice_client) = tokio::io::duplex(1 << 16);
+        let mut alice_stream = MessageStream::new(alice_stream);
+        for _ in 0..=CHAT_CHANNEL_CAPACITY {
+            alice
+                .handle_direct_message("bob".to_string(), "psst".to_string(), &mut alice_stream)
+                .await
+                .unwrap();
+        }
+
+        let mut alice_client = MessageStream::new(alice_client);
+        assert!(matches!(
+            alice_client.read_message().await.unwrap(),
+            Some(Message::InvalidDirectMessage { reason }) if reason.contains("not keeping up")
+        ));
+    }
+}
@@ -1,10 +1,30 @@
 use core::error::Error;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use shared::StreamID;
-use tokio::{net::UdpSocket, sync::Mutex};
+use log::error;
+use shared::{
+    COMPOSITE_RSID, FrameFormats, MAX_DATAGRAM_LEN, MAX_FRAME_SIZE, MEDIA_HEADER_LEN, MediaHeader,
+    MediaKind, Message, RoomMode, StreamID, fragment_frame, parity_fragments,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{Mutex, broadcast},
+    time::{MissedTickBehavior, interval},
+};
 
-use crate::room::Room;
+use crate::{compositor::Compositor, room::Room};
+
+/// How often composite rooms get new grid frames, when anything changed.
+const COMPOSITE_INTERVAL: Duration = Duration::from_millis(1000 / 30);
+
+/// Composite fragments covered by each parity fragment, so a receiver can
+/// rebuild one lost fragment per group without waiting for a keyframe.
+const COMPOSITE_FEC_GROUP_SIZE: u8 = 4;
 
 pub struct UdpHandler {}
 
@@ -13,23 +33,49 @@ impl UdpHandler {
         socket: UdpSocket,
         sid_to_username_map: Arc<Mutex<HashMap<StreamID, String>>>,
         rooms: Arc<Mutex<Vec<Room>>>,
+        username_to_command_channel_tx: Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut username_to_socket_addr_map = HashMap::new();
-        let mut buf = [0; 1500];
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let mut compositor = Compositor::default();
+        let mut composite_interval = interval(COMPOSITE_INTERVAL);
+        composite_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
         loop {
-            let (n, from_addr) = socket.recv_from(&mut buf).await?;
+            let (n, from_addr) = tokio::select! {
+                result = socket.recv_from(&mut buf) => result?,
 
-            if n < StreamID::default().len() + 1 {
-                continue;
-            }
+                _ = composite_interval.tick() => {
+                    let datagrams = compose_rooms(
+                        &mut compositor,
+                        &rooms,
+                        &username_to_socket_addr_map,
+                    )
+                    .await;
+
+                    for (send_addr, datagram) in datagrams {
+                        socket.send_to(&datagram, send_addr).await?;
+                    }
+
+                    continue;
+                }
+            };
 
             let sid_len = StreamID::default().len();
 
+            if n < sid_len + MEDIA_HEADER_LEN {
+                continue;
+            }
+
             let sid: StreamID = buf[0..sid_len]
                 .try_into()
                 .expect("Invalid SID slice length");
 
+            let (header, media_payload) = match MediaHeader::decode(&buf[sid_len..n]) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+
             let from_username_option = {
                 let guard = sid_to_username_map.lock().await;
                 guard.get(&sid).cloned()
@@ -58,6 +104,25 @@ impl UdpHandler {
                     None => continue,
                 };
 
+                // Video in composite rooms goes into the grid instead of
+                // being forwarded; audio is forwarded either way.
+                if current_room.mode == RoomMode::Composite && header.kind != MediaKind::Audio {
+                    if compositor.push(&from_username, &header, media_payload)
+                        && let Some(tx) = username_to_command_channel_tx
+                            .lock()
+                            .await
+                            .get(&from_username)
+                        && let Err(e) = tx.send(Message::KeyframeRequested)
+                    {
+                        error!(
+                            "Error sending to channel: {} for user: {}",
+                            e, from_username
+                        );
+                    }
+
+                    continue;
+                }
+
                 for to_username in current_room.username_to_rsid.keys() {
                     if to_username == &from_username {
                         continue;
@@ -80,3 +145,152 @@ impl UdpHandler {
         }
     }
 }
+
+/// Builds the grid frame of everyone in a composite room whose grid changed,
+/// and returns the datagrams to send them.
+async fn compose_rooms(
+    compositor: &mut Compositor,
+    rooms: &Mutex<Vec<Room>>,
+    username_to_socket_addr_map: &HashMap<String, SocketAddr>,
+) -> Vec<(SocketAddr, Vec<u8>)> {
+    let mut datagrams = Vec::new();
+    let mut rooms = rooms.lock().await;
+
+    compositor.retain(|username| {
+        rooms.iter().any(|room| {
+            room.mode == RoomMode::Composite && room.username_to_rsid.contains_key(username)
+        })
+    });
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0);
+
+    for room in rooms
+        .iter_mut()
+        .filter(|room| room.mode == RoomMode::Composite)
+    {
+        for receiver in room.composite_keyframe_requests.drain() {
+            compositor.request_keyframe(&receiver);
+        }
+
+        let mut usernames: Vec<&str> = room.username_to_rsid.keys().map(String::as_str).collect();
+        usernames.sort();
+
+        for receiver in &usernames {
+            let send_addr = match username_to_socket_addr_map.get(*receiver) {
+                Some(send_addr) => *send_addr,
+                None => continue,
+            };
+
+            // Sized to the receiver's video area, which it sends once it
+            // starts drawing.
+            let resolution = match room.username_to_preferred_resolution.get(*receiver) {
+                Some(resolution) => *resolution,
+                None => continue,
+            };
+
+            let frame_formats = room
+                .username_to_frame_formats
+                .get(*receiver)
+                .copied()
+                .unwrap_or(FrameFormats::NIBBLE_GRAY);
+
+            let max_frame_size = room
+                .username_to_max_frame_size
+                .get(*receiver)
+                .copied()
+                .unwrap_or(MAX_FRAME_SIZE);
+
+            let senders: Vec<&str> = usernames
+                .iter()
+                .copied()
+                .filter(|sender| sender != receiver)
+                .collect();
+
+            let frame = match compositor.compose(
+                receiver,
+                &senders,
+                resolution,
+                frame_formats,
+                max_frame_size,
+            ) {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            let fragments =
+                match fragment_frame(frame.kind, frame.sequence, timestamp, &frame.payload) {
+                    Ok(fragments) => fragments,
+                    Err(e) => {
+                        error!("Failed to fragment composite frame for {}: {}", receiver, e);
+                        continue;
+                    }
+                };
+
+            let parity = parity_fragments(&fragments, COMPOSITE_FEC_GROUP_SIZE);
+            let parity = parity
+                .iter()
+                .map(|(header, payload)| (*header, payload.as_slice()));
+
+            for (header, payload) in fragments.iter().copied().chain(parity) {
+                let mut datagram = Vec::with_capacity(MAX_DATAGRAM_LEN);
+                datagram.extend_from_slice(&COMPOSITE_RSID);
+                header.encode(&mut datagram);
+                datagram.extend_from_slice(payload);
+
+                datagrams.push((send_addr, datagram));
+            }
+        }
+    }
+
+    return datagrams;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use shared::Resolution;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn composite_frames_carry_parity() {
+        let room = Room {
+            name: "grid".to_string(),
+            mode: RoomMode::Composite,
+            username_to_rsid: HashMap::from([("alice".to_string(), [1]), ("bob".to_string(), [2])]),
+            username_to_preferred_resolution: HashMap::from([(
+                "bob".to_string(),
+                Resolution::new(4, 2),
+            )]),
+            username_to_frame_formats: HashMap::new(),
+            username_to_max_frame_size: HashMap::new(),
+            reception_reports: HashMap::new(),
+            composite_keyframe_requests: HashSet::new(),
+        };
+        let bob_addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+
+        let datagrams = compose_rooms(
+            &mut Compositor::default(),
+            &Mutex::new(vec![room]),
+            &HashMap::from([("bob".to_string(), bob_addr)]),
+        )
+        .await;
+
+        let kinds: Vec<MediaKind> = datagrams
+            .iter()
+            .map(|(addr, datagram)| {
+                assert_eq!(*addr, bob_addr);
+                assert_eq!(datagram[..COMPOSITE_RSID.len()], COMPOSITE_RSID);
+
+                let (header, _) = MediaHeader::decode(&datagram[COMPOSITE_RSID.len()..]).unwrap();
+                header.kind
+            })
+            .collect();
+
+        assert_eq!(kinds, [MediaKind::Keyframe, MediaKind::Parity]);
+    }
+}
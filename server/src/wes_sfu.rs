@@ -2,10 +2,10 @@ use core::error::Error;
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use log::{error, info};
-use shared::{StreamID, TcpCommand};
+use shared::{Message, MessageStream, StreamID};
 use tokio::{
     net::{TcpListener, TcpStream, UdpSocket},
-    sync::{Mutex, broadcast},
+    sync::{Mutex, broadcast, mpsc},
 };
 
 use crate::{room::Room, tcp_handler::TcpHandler, udp_handler::UdpHandler};
@@ -16,7 +16,8 @@ pub struct WeSFU {
     active_usernames: Arc<Mutex<Vec<String>>>,
     public_rooms: Arc<Mutex<Vec<Room>>>,
     sid_to_username_map: Arc<Mutex<HashMap<StreamID, String>>>,
-    username_to_tcp_command_channel: Arc<Mutex<HashMap<String, broadcast::Sender<TcpCommand>>>>,
+    username_to_tcp_command_channel: Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>,
+    username_to_chat_channel: Arc<Mutex<HashMap<String, mpsc::Sender<Message>>>>,
 }
 
 impl WeSFU {
@@ -31,6 +32,7 @@ impl WeSFU {
             public_rooms: Arc::new(Mutex::new(Vec::new())),
             sid_to_username_map: Arc::new(Mutex::new(HashMap::new())),
             username_to_tcp_command_channel: Arc::new(Mutex::new(HashMap::new())),
+            username_to_chat_channel: Arc::new(Mutex::new(HashMap::new())),
         });
     }
 
@@ -41,17 +43,21 @@ impl WeSFU {
         let udp_public_rooms = self.public_rooms.clone();
         let tcp_public_rooms = self.public_rooms.clone();
 
+        let udp_username_to_tcp_command_channel = self.username_to_tcp_command_channel.clone();
+
         let mut udp_task = tokio::spawn(async move {
             let _ = UdpHandler::handle_socket(
                 self.udp_socket,
                 udp_sid_to_username_map,
                 udp_public_rooms,
+                udp_username_to_tcp_command_channel,
             )
             .await;
         });
 
         loop {
             let username_to_tcp_command_channel = self.username_to_tcp_command_channel.clone();
+            let username_to_chat_channel = self.username_to_chat_channel.clone();
 
             tokio::select! {
 
@@ -65,7 +71,8 @@ impl WeSFU {
                         self.active_usernames.clone(),
                         tcp_public_rooms.clone(),
                         tcp_sid_to_username_map.clone(),
-                        username_to_tcp_command_channel
+                        username_to_tcp_command_channel,
+                        username_to_chat_channel,
                     );
                 }
 
@@ -81,12 +88,13 @@ impl WeSFU {
     }
 
     fn spawn_tcp_thread(
-        mut tcp_stream: TcpStream,
+        tcp_stream: TcpStream,
         tcp_addr: SocketAddr,
         active_usernames: Arc<Mutex<Vec<String>>>,
         public_rooms: Arc<Mutex<Vec<Room>>>,
         sid_to_username_map: Arc<Mutex<HashMap<StreamID, String>>>,
-        username_to_tcp_command_channel: Arc<Mutex<HashMap<String, broadcast::Sender<TcpCommand>>>>,
+        username_to_tcp_command_channel: Arc<Mutex<HashMap<String, broadcast::Sender<Message>>>>,
+        username_to_chat_channel: Arc<Mutex<HashMap<String, mpsc::Sender<Message>>>>,
     ) {
         tokio::spawn(async move {
             info!("Opened Connection to {}", tcp_addr);
@@ -96,10 +104,13 @@ impl WeSFU {
                 public_rooms,
                 sid_to_username_map,
                 username_to_tcp_command_channel,
+                username_to_chat_channel,
             )
             .await;
 
-            if let Err(e) = tcp_handler.handle_stream(&mut tcp_stream).await {
+            let mut message_stream = MessageStream::new(tcp_stream);
+
+            if let Err(e) = tcp_handler.handle_stream(&mut message_stream).await {
                 error!("Error handling connection: {}", e);
             };
 
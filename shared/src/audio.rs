@@ -0,0 +1,204 @@
+use crate::{protocol_error::ProtocolError, wire::PayloadReader};
+
+/// Samples per second of audio on the wire, mono.
+pub const AUDIO_SAMPLE_RATE: u32 = 16_000;
+
+/// Samples carried in one audio packet: 20 ms, small enough that a packet
+/// always fits in one datagram and a lost one is a short gap.
+pub const AUDIO_FRAME_SAMPLES: usize = AUDIO_SAMPLE_RATE as usize / 50;
+
+/// Audio payloads start with `[first sample: i16][step index: u8]
+/// [sample count: u16]`, followed by one 4-bit IMA ADPCM code per further
+/// sample, high nibble first. Every packet carries the coder state it
+/// starts from, so each one decodes on its own.
+pub const AUDIO_HEADER_LEN: usize = 5;
+
+const STEP_SIZES: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+const INDEX_ADJUSTMENTS: [i8; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// IMA ADPCM coder state, shared by the encoder and decoder so both track
+/// the same prediction.
+#[derive(Debug, Clone, Copy)]
+struct AdpcmState {
+    predicted: i32,
+    step_index: usize,
+}
+
+impl AdpcmState {
+    fn encode(&mut self, sample: i16) -> u8 {
+        let step = STEP_SIZES[self.step_index];
+        let mut diff = sample as i32 - self.predicted;
+        let mut code = 0;
+
+        if diff < 0 {
+            code = 8;
+            diff = -diff;
+        }
+
+        let mut threshold = step;
+        for bit in [4, 2, 1] {
+            if diff >= threshold {
+                code |= bit;
+                diff -= threshold;
+            }
+            threshold >>= 1;
+        }
+
+        self.decode(code);
+
+        return code;
+    }
+
+    fn decode(&mut self, code: u8) -> i16 {
+        let step = STEP_SIZES[self.step_index];
+        let mut delta = step >> 3;
+
+        if code & 4 != 0 {
+            delta += step;
+        }
+        if code & 2 != 0 {
+            delta += step >> 1;
+        }
+        if code & 1 != 0 {
+            delta += step >> 2;
+        }
+        if code & 8 != 0 {
+            delta = -delta;
+        }
+
+        self.predicted = (self.predicted + delta).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.step_index = (self.step_index as i32 + INDEX_ADJUSTMENTS[(code & 7) as usize] as i32)
+            .clamp(0, STEP_SIZES.len() as i32 - 1) as usize;
+
+        return self.predicted as i16;
+    }
+}
+
+/// Compresses `samples`, 16-bit mono at `AUDIO_SAMPLE_RATE`, to about a
+/// quarter of their size. `step_index` carries the coder's step size from
+/// one packet to the next, so it need not ramp up again every 20 ms.
+pub fn encode_audio(samples: &[i16], step_index: &mut u8) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(AUDIO_HEADER_LEN + samples.len() / 2 + 1);
+    let first = samples.first().copied().unwrap_or(0);
+
+    payload.extend_from_slice(&first.to_be_bytes());
+    payload.push(*step_index);
+    payload.extend_from_slice(&(samples.len() as u16).to_be_bytes());
+
+    let mut state = AdpcmState {
+        predicted: first as i32,
+        step_index: (*step_index as usize).min(STEP_SIZES.len() - 1),
+    };
+
+    for pair in samples.get(1..).unwrap_or_default().chunks(2) {
+        let high = state.encode(pair[0]);
+        let low = match pair.get(1) {
+            Some(&sample) => state.encode(sample),
+            None => 0,
+        };
+
+        payload.push((high << 4) | low);
+    }
+
+    *step_index = state.step_index as u8;
+
+    return payload;
+}
+
+/// Expands a payload made by `encode_audio` back into samples.
+pub fn decode_audio(payload: &[u8]) -> Result<Vec<i16>, ProtocolError> {
+    let mut reader = PayloadReader::new(payload);
+
+    let first: u16 = reader.read()?;
+    let step_index: u8 = reader.read()?;
+    let sample_count: u16 = reader.read()?;
+
+    let mut samples = Vec::with_capacity(sample_count as usize);
+    if sample_count == 0 {
+        return Ok(samples);
+    }
+
+    let codes = reader.read_bytes((sample_count as usize - 1).div_ceil(2))?;
+
+    let mut state = AdpcmState {
+        predicted: first as i16 as i32,
+        step_index: (step_index as usize).min(STEP_SIZES.len() - 1),
+    };
+
+    samples.push(first as i16);
+
+    for code in codes.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]) {
+        if samples.len() == sample_count as usize {
+            break;
+        }
+
+        samples.push(state.decode(code));
+    }
+
+    return Ok(samples);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 440 Hz tone at half volume.
+    fn tone(len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| {
+                let t = i as f64 / AUDIO_SAMPLE_RATE as f64;
+                ((t * 440.0 * std::f64::consts::TAU).sin() * 16_000.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_close_to_the_original() {
+        let samples = tone(AUDIO_FRAME_SAMPLES * 5);
+        let mut encode_step = 0;
+        let mut decoded = Vec::new();
+
+        for chunk in samples.chunks(AUDIO_FRAME_SAMPLES) {
+            let payload = encode_audio(chunk, &mut encode_step);
+            assert_eq!(payload.len(), AUDIO_HEADER_LEN + AUDIO_FRAME_SAMPLES / 2);
+            decoded.extend(decode_audio(&payload).unwrap());
+        }
+
+        assert_eq!(decoded.len(), samples.len());
+
+        // Skip the first packet, while the step size adapts to the tone.
+        let error = samples[AUDIO_FRAME_SAMPLES..]
+            .iter()
+            .zip(&decoded[AUDIO_FRAME_SAMPLES..])
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(error < 1_000, "largest error {}", error);
+    }
+
+    #[test]
+    fn keeps_odd_sample_counts() {
+        let samples = tone(7);
+        let decoded = decode_audio(&encode_audio(&samples, &mut 0)).unwrap();
+
+        assert_eq!(decoded.len(), 7);
+        assert_eq!(decoded[0], samples[0]);
+    }
+
+    #[test]
+    fn rejects_truncated_payloads() {
+        let payload = encode_audio(&tone(AUDIO_FRAME_SAMPLES), &mut 0);
+
+        assert!(matches!(
+            decode_audio(&payload[..payload.len() - 1]),
+            Err(ProtocolError::TruncatedFrame)
+        ));
+    }
+}
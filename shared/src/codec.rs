@@ -0,0 +1,180 @@
+use crate::{
+    fragment::ReassembledFrame,
+    media::MediaKind,
+    protocol_error::ProtocolError,
+    video::{Resolution, VideoFrame},
+    wire::PayloadReader,
+};
+
+/// Delta payloads are `[width: u16][height: u16][keyframe sequence: u32]`
+/// followed by runs of `[unchanged: u16][changed: u16][changed cells]`,
+/// applied on top of the cells of that keyframe. Cells after the last run
+/// are unchanged, and the format is always the keyframe's.
+fn encode_delta(keyframe_sequence: u32, keyframe: &VideoFrame, frame: &VideoFrame) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&frame.resolution.width.to_be_bytes());
+    payload.extend_from_slice(&frame.resolution.height.to_be_bytes());
+    payload.extend_from_slice(&keyframe_sequence.to_be_bytes());
+
+    let cells = &frame.cells;
+    let reference = &keyframe.cells;
+    let mut position = 0;
+
+    while position < cells.len() {
+        let unchanged = cells[position..]
+            .iter()
+            .zip(&reference[position..])
+            .take(u16::MAX as usize)
+            .take_while(|(cell, reference_cell)| cell == reference_cell)
+            .count();
+        position += unchanged;
+
+        let changed = cells[position..]
+            .iter()
+            .zip(&reference[position..])
+            .take(u16::MAX as usize)
+            .take_while(|(cell, reference_cell)| cell != reference_cell)
+            .count();
+
+        if changed == 0 && position == cells.len() {
+            break;
+        }
+
+        payload.extend_from_slice(&(unchanged as u16).to_be_bytes());
+        payload.extend_from_slice(&(changed as u16).to_be_bytes());
+        payload.extend_from_slice(&cells[position..position + changed]);
+        position += changed;
+    }
+
+    return payload;
+}
+
+/// Turns a sender's video frames into keyframes and deltas against the most
+/// recent keyframe. Deltas never depend on each other, so losing one only
+/// costs that frame.
+pub struct VideoEncoder {
+    keyframe_interval: u32,
+    frames_since_keyframe: u32,
+    keyframe: Option<(u32, VideoFrame)>,
+    keyframe_requested: bool,
+}
+
+impl VideoEncoder {
+    /// Sends a keyframe at least every `keyframe_interval` frames.
+    pub fn new(keyframe_interval: u32) -> Self {
+        Self {
+            keyframe_interval,
+            frames_since_keyframe: 0,
+            keyframe: None,
+            keyframe_requested: false,
+        }
+    }
+
+    /// Changes how many frames may go by between keyframes from now on.
+    pub fn set_keyframe_interval(&mut self, keyframe_interval: u32) {
+        self.keyframe_interval = keyframe_interval;
+    }
+
+    /// Makes the next frame a keyframe, e.g. because a receiver lost the
+    /// last one.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// Encodes `frame`, which will go out as media packet `sequence`.
+    pub fn encode(&mut self, sequence: u32, frame: &VideoFrame) -> (MediaKind, Vec<u8>) {
+        if !self.keyframe_requested
+            && self.frames_since_keyframe < self.keyframe_interval
+            && let Some((keyframe_sequence, keyframe)) = &self.keyframe
+            && keyframe.resolution == frame.resolution
+            && keyframe.format == frame.format
+            && keyframe.cells.len() == frame.cells.len()
+        {
+            let delta = encode_delta(*keyframe_sequence, keyframe, frame);
+            let keyframe_len = frame.encode().len();
+
+            if delta.len() < keyframe_len {
+                self.frames_since_keyframe += 1;
+                return (MediaKind::Delta, delta);
+            }
+        }
+
+        self.keyframe_requested = false;
+        self.frames_since_keyframe = 0;
+        self.keyframe = Some((sequence, frame.clone()));
+
+        return (MediaKind::Keyframe, frame.encode());
+    }
+}
+
+/// Rebuilds one sender's video frames from keyframes and deltas.
+#[derive(Default)]
+pub struct VideoDecoder {
+    keyframe: Option<(u32, VideoFrame)>,
+}
+
+impl VideoDecoder {
+    /// Decodes `frame`. Deltas against a keyframe this decoder has not seen
+    /// fail with `ProtocolError::MissingKeyframe`.
+    pub fn decode(&mut self, frame: &ReassembledFrame) -> Result<VideoFrame, ProtocolError> {
+        match frame.kind {
+            MediaKind::Keyframe => {
+                let video_frame = VideoFrame::decode(&frame.payload)?;
+                self.keyframe = Some((frame.sequence, video_frame.clone()));
+
+                return Ok(video_frame);
+            }
+            MediaKind::Delta => return self.decode_delta(&frame.payload),
+            // Not video: parity never leaves the reassembler, and audio has
+            // its own path.
+            MediaKind::Parity | MediaKind::Audio => {
+                return Err(ProtocolError::UnknownMediaKind(frame.kind.to_byte()));
+            }
+        }
+    }
+
+    fn decode_delta(&self, payload: &[u8]) -> Result<VideoFrame, ProtocolError> {
+        let mut reader = PayloadReader::new(payload);
+
+        let resolution = Resolution::new(reader.read()?, reader.read()?);
+        let keyframe_sequence: u32 = reader.read()?;
+
+        let keyframe = match &self.keyframe {
+            Some((sequence, keyframe))
+                if *sequence == keyframe_sequence && keyframe.resolution == resolution =>
+            {
+                keyframe
+            }
+            _ => return Err(ProtocolError::MissingKeyframe(keyframe_sequence)),
+        };
+
+        let mut cells = keyframe.cells.clone();
+        let mut position = 0;
+
+        while !reader.is_empty() {
+            let unchanged: u16 = reader.read()?;
+            let changed: u16 = reader.read()?;
+            let changed_cells = reader.read_bytes(changed as usize)?;
+
+            position += unchanged as usize;
+            let end = position + changed as usize;
+
+            if end > cells.len() {
+                return Err(ProtocolError::OversizeField {
+                    field: "Delta run",
+                    len: end,
+                    max: cells.len(),
+                });
+            }
+
+            cells[position..end].copy_from_slice(changed_cells);
+            position = end;
+        }
+
+        return Ok(VideoFrame {
+            resolution,
+            format: keyframe.format,
+            cells,
+        });
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    media::{MAX_FRAGMENT_PAYLOAD_LEN, MediaHeader, MediaKind, PARITY_HEADER_LEN},
+    protocol_error::ProtocolError,
+};
+
+/// How many partially received frames a reassembler keeps at once. When a
+/// new frame starts beyond this, the oldest one is given up on.
+const MAX_PENDING_FRAMES: usize = 16;
+
+/// How many recently completed frames a reassembler remembers, so that
+/// fragments or parity arriving after their frame was delivered are ignored.
+const MAX_COMPLETED_FRAMES: usize = 64;
+
+/// Splits `frame` into pieces that each fit in one datagram, paired with the
+/// header to send in front of them.
+pub fn fragment_frame(
+    kind: MediaKind,
+    sequence: u32,
+    timestamp: u64,
+    frame: &[u8],
+) -> Result<Vec<(MediaHeader, &[u8])>, ProtocolError> {
+    let fragment_count = frame.len().div_ceil(MAX_FRAGMENT_PAYLOAD_LEN).max(1);
+
+    if fragment_count > u16::MAX as usize {
+        return Err(ProtocolError::OversizeField {
+            field: "Media frame",
+            len: frame.len(),
+            max: MAX_FRAGMENT_PAYLOAD_LEN * u16::MAX as usize,
+        });
+    }
+
+    let mut fragments = Vec::with_capacity(fragment_count);
+
+    for fragment_index in 0..fragment_count {
+        let start = fragment_index * MAX_FRAGMENT_PAYLOAD_LEN;
+        let end = (start + MAX_FRAGMENT_PAYLOAD_LEN).min(frame.len());
+        let payload = &frame[start..end];
+
+        let header = MediaHeader {
+            kind,
+            sequence,
+            timestamp,
+            fragment_index: fragment_index as u16,
+            fragment_count: fragment_count as u16,
+            payload_len: payload.len() as u16,
+        };
+
+        fragments.push((header, payload));
+    }
+
+    return Ok(fragments);
+}
+
+/// One parity fragment for every `group_size` consecutive fragments of a
+/// frame, as returned by `fragment_frame`. A receiver missing a single
+/// fragment of a group can rebuild it from the group's parity.
+pub fn parity_fragments(
+    fragments: &[(MediaHeader, &[u8])],
+    group_size: u8,
+) -> Vec<(MediaHeader, Vec<u8>)> {
+    let first_header = match fragments.first() {
+        Some((header, _)) => *header,
+        None => return Vec::new(),
+    };
+
+    let group_size = group_size.max(1);
+
+    return fragments
+        .chunks(group_size as usize)
+        .enumerate()
+        .map(|(group_index, group)| {
+            let longest = group.iter().map(|(_, data)| data.len()).max().unwrap_or(0);
+            let mut payload = vec![0; PARITY_HEADER_LEN + longest];
+            let mut len_xor = 0u16;
+
+            for (_, data) in group {
+                len_xor ^= data.len() as u16;
+                xor_into(&mut payload[PARITY_HEADER_LEN..], data);
+            }
+
+            payload[0] = first_header.kind.to_byte();
+            payload[1] = group_size;
+            payload[2..PARITY_HEADER_LEN].copy_from_slice(&len_xor.to_be_bytes());
+
+            let header = MediaHeader {
+                kind: MediaKind::Parity,
+                payload_len: payload.len() as u16,
+                fragment_index: group_index as u16,
+                ..first_header
+            };
+
+            (header, payload)
+        })
+        .collect();
+}
+
+/// A frame whose fragments have all arrived.
+#[derive(Debug, Clone)]
+pub struct ReassembledFrame {
+    pub kind: MediaKind,
+    pub sequence: u32,
+    pub timestamp: u64,
+    pub payload: Vec<u8>,
+}
+
+struct PendingFrame {
+    kind: MediaKind,
+    timestamp: u64,
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    /// Data fragments per parity group, once a parity fragment has arrived.
+    group_size: Option<usize>,
+    /// Parity payloads by group index, without their parity header.
+    parity: HashMap<usize, (u16, Vec<u8>)>,
+    /// Data fragments rebuilt from parity whose own copy has not arrived.
+    rebuilt: HashSet<usize>,
+    first_seen: Instant,
+}
+
+/// Collects the fragments of one sender's frames. Frames that are still
+/// incomplete once `deadline` has passed since their first fragment are
+/// dropped.
+pub struct FrameReassembler {
+    deadline: Duration,
+    max_fragment_count: usize,
+    pending: HashMap<u32, PendingFrame>,
+    completed: VecDeque<u32>,
+    /// Rebuilt fragments of completed frames, so that the count can be
+    /// corrected if their own copy turns up late.
+    completed_rebuilt: HashMap<u32, HashSet<usize>>,
+    recovered: u64,
+}
+
+impl FrameReassembler {
+    pub fn new(deadline: Duration, max_frame_size: u32) -> Self {
+        Self {
+            deadline,
+            max_fragment_count: (max_frame_size as usize).div_ceil(MAX_FRAGMENT_PAYLOAD_LEN),
+            pending: HashMap::new(),
+            completed: VecDeque::new(),
+            completed_rebuilt: HashMap::new(),
+            recovered: 0,
+        }
+    }
+
+    /// Adds one fragment, data or parity, and returns its frame once every
+    /// data fragment has arrived or been rebuilt. Fragments that disagree
+    /// with the rest of their frame, or would make it larger than allowed,
+    /// are ignored.
+    pub fn push(&mut self, header: &MediaHeader, payload: &[u8]) -> Option<ReassembledFrame> {
+        let now = Instant::now();
+        self.expire(now);
+
+        if self.completed.contains(&header.sequence) {
+            if header.kind != MediaKind::Parity
+                && let Some(rebuilt) = self.completed_rebuilt.get_mut(&header.sequence)
+                && rebuilt.remove(&(header.fragment_index as usize))
+            {
+                self.recovered -= 1;
+            }
+
+            return None;
+        }
+
+        let (kind, parity) = match header.kind {
+            MediaKind::Parity => {
+                let (kind, group_size, len_xor, data) = parse_parity(payload)?;
+                (kind, Some((group_size, len_xor, data)))
+            }
+            kind => (kind, None),
+        };
+
+        if header.fragment_count == 1 && parity.is_none() {
+            self.complete(header.sequence);
+
+            return Some(ReassembledFrame {
+                kind,
+                sequence: header.sequence,
+                timestamp: header.timestamp,
+                payload: payload.to_vec(),
+            });
+        }
+
+        if header.fragment_count as usize > self.max_fragment_count {
+            return None;
+        }
+
+        if !self.pending.contains_key(&header.sequence) && self.pending.len() >= MAX_PENDING_FRAMES
+        {
+            let oldest_sequence = self
+                .pending
+                .iter()
+                .min_by_key(|(_, pending_frame)| pending_frame.first_seen)
+                .map(|(sequence, _)| *sequence);
+
+            if let Some(oldest_sequence) = oldest_sequence
+                && let Some(pending_frame) = self.pending.remove(&oldest_sequence)
+            {
+                self.recovered += pending_frame.rebuilt.len() as u64;
+            }
+        }
+
+        let pending_frame = self
+            .pending
+            .entry(header.sequence)
+            .or_insert_with(|| PendingFrame {
+                kind,
+                timestamp: header.timestamp,
+                fragments: vec![None; header.fragment_count as usize],
+                missing: header.fragment_count as usize,
+                group_size: None,
+                parity: HashMap::new(),
+                rebuilt: HashSet::new(),
+                first_seen: now,
+            });
+
+        if pending_frame.kind != kind
+            || pending_frame.fragments.len() != header.fragment_count as usize
+        {
+            return None;
+        }
+
+        let group = match parity {
+            Some((group_size, len_xor, data)) => {
+                if *pending_frame.group_size.get_or_insert(group_size) != group_size {
+                    return None;
+                }
+
+                let group = header.fragment_index as usize;
+                pending_frame.parity.insert(group, (len_xor, data.to_vec()));
+                group
+            }
+            None => {
+                let slot = &mut pending_frame.fragments[header.fragment_index as usize];
+
+                if slot.is_some() {
+                    // Rebuilt early from parity, so it was not lost after all.
+                    pending_frame
+                        .rebuilt
+                        .remove(&(header.fragment_index as usize));
+                    return None;
+                }
+
+                *slot = Some(payload.to_vec());
+                pending_frame.missing -= 1;
+
+                match pending_frame.group_size {
+                    Some(group_size) => header.fragment_index as usize / group_size,
+                    None => 0,
+                }
+            }
+        };
+
+        pending_frame.recover(group);
+
+        if pending_frame.missing > 0 {
+            return None;
+        }
+
+        let pending_frame = self.pending.remove(&header.sequence)?;
+        self.complete(header.sequence);
+
+        if !pending_frame.rebuilt.is_empty() {
+            self.recovered += pending_frame.rebuilt.len() as u64;
+            self.completed_rebuilt
+                .insert(header.sequence, pending_frame.rebuilt);
+        }
+
+        return Some(ReassembledFrame {
+            kind: pending_frame.kind,
+            sequence: header.sequence,
+            timestamp: pending_frame.timestamp,
+            payload: pending_frame
+                .fragments
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+        });
+    }
+
+    /// Fragments rebuilt from parity that were really lost: their own copy
+    /// had not arrived when their frame was given up on, nor has it since
+    /// the frame was completed.
+    pub fn recovered(&self) -> u64 {
+        self.recovered
+    }
+
+    fn complete(&mut self, sequence: u32) {
+        if self.completed.len() >= MAX_COMPLETED_FRAMES
+            && let Some(forgotten) = self.completed.pop_front()
+        {
+            self.completed_rebuilt.remove(&forgotten);
+        }
+
+        self.completed.push_back(sequence);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let deadline = self.deadline;
+        let mut recovered = 0;
+
+        self.pending.retain(|_, pending_frame| {
+            let keep = now.duration_since(pending_frame.first_seen) < deadline;
+
+            if !keep {
+                recovered += pending_frame.rebuilt.len() as u64;
+            }
+
+            keep
+        });
+
+        self.recovered += recovered;
+    }
+}
+
+impl PendingFrame {
+    /// Rebuilds the data fragment missing from `group`, if it is the only
+    /// one missing and the group's parity has arrived.
+    fn recover(&mut self, group: usize) {
+        let (group_size, (len_xor, parity)) = match (self.group_size, self.parity.get(&group)) {
+            (Some(group_size), Some(parity)) => (group_size, parity),
+            _ => return,
+        };
+
+        let start = group * group_size;
+        let end = (start + group_size).min(self.fragments.len());
+
+        if start >= end {
+            return;
+        }
+
+        let mut missing = (start..end).filter(|&index| self.fragments[index].is_none());
+
+        let missing_index = match (missing.next(), missing.next()) {
+            (Some(missing_index), None) => missing_index,
+            _ => return,
+        };
+
+        let mut data = parity.clone();
+        let mut len = *len_xor;
+
+        for fragment in self.fragments[start..end].iter().flatten() {
+            if fragment.len() > data.len() {
+                return;
+            }
+
+            len ^= fragment.len() as u16;
+            xor_into(&mut data, fragment);
+        }
+
+        if len as usize > data.len() {
+            return;
+        }
+
+        data.truncate(len as usize);
+        self.fragments[missing_index] = Some(data);
+        self.missing -= 1;
+        self.rebuilt.insert(missing_index);
+    }
+}
+
+/// Splits a parity payload into the kind it protects, its group size, the
+/// XOR of the group's lengths and the XOR of the group's payloads.
+fn parse_parity(payload: &[u8]) -> Option<(MediaKind, usize, u16, &[u8])> {
+    if payload.len() < PARITY_HEADER_LEN {
+        return None;
+    }
+
+    let kind = match MediaKind::from_byte(payload[0]) {
+        Ok(MediaKind::Parity) | Err(_) => return None,
+        Ok(kind) => kind,
+    };
+
+    let group_size = payload[1] as usize;
+    if group_size == 0 {
+        return None;
+    }
+
+    let len_xor = u16::from_be_bytes([payload[2], payload[3]]);
+
+    return Some((kind, group_size, len_xor, &payload[PARITY_HEADER_LEN..]));
+}
+
+fn xor_into(target: &mut [u8], data: &[u8]) {
+    for (target, byte) in target.iter_mut().zip(data) {
+        *target ^= byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEADLINE: Duration = Duration::from_secs(1);
+    const MAX_FRAME_SIZE: u32 = 1 << 18;
+
+    /// A frame spanning several fragments, the last one short.
+    fn frame() -> Vec<u8> {
+        (0..MAX_FRAGMENT_PAYLOAD_LEN * 4 + 100)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect()
+    }
+
+    /// Pushes every fragment and parity fragment except the data fragments
+    /// at `lost`, parity first so recovery happens as data arrives.
+    fn deliver(
+        frame: &[u8],
+        group_size: u8,
+        lost: &[usize],
+    ) -> (Option<ReassembledFrame>, FrameReassembler) {
+        let fragments = fragment_frame(MediaKind::Keyframe, 7, 1_000, frame).unwrap();
+        let parity = parity_fragments(&fragments, group_size);
+        let mut reassembler = FrameReassembler::new(DEADLINE, MAX_FRAME_SIZE);
+        let mut reassembled = None;
+
+        for (header, payload) in &parity {
+            reassembled = reassembled.or(reassembler.push(header, payload));
+        }
+
+        for (index, (header, payload)) in fragments.iter().enumerate() {
+            if !lost.contains(&index) {
+                reassembled = reassembled.or(reassembler.push(header, payload));
+            }
+        }
+
+        return (reassembled, reassembler);
+    }
+
+    #[test]
+    fn rebuilds_one_lost_fragment_per_group() {
+        let frame = frame();
+
+        // Fragments 0-2 and 3-4 form the two groups; the short last one is
+        // rebuilt too.
+        let (reassembled, reassembler) = deliver(&frame, 3, &[1, 4]);
+
+        let reassembled = reassembled.unwrap();
+        assert_eq!(reassembled.kind, MediaKind::Keyframe);
+        assert_eq!(reassembled.payload, frame);
+        assert_eq!(reassembler.recovered(), 2);
+    }
+
+    #[test]
+    fn cannot_rebuild_two_losses_in_one_group() {
+        let (reassembled, reassembler) = deliver(&frame(), 3, &[0, 2]);
+
+        // The second group is rebuilt early, as its parity came before its
+        // data, but nothing in it was lost; the first stays two fragments
+        // short.
+        assert!(reassembled.is_none());
+        assert_eq!(reassembler.recovered(), 0);
+    }
+
+    #[test]
+    fn nothing_is_recovered_without_loss() {
+        let frame = frame();
+
+        let (reassembled, reassembler) = deliver(&frame, 2, &[]);
+
+        assert_eq!(reassembled.unwrap().payload, frame);
+        assert_eq!(reassembler.recovered(), 0);
+    }
+
+    #[test]
+    fn parity_after_data_is_ignored() {
+        let frame = b"short frame".to_vec();
+        let fragments = fragment_frame(MediaKind::Delta, 3, 1_000, &frame).unwrap();
+        let parity = parity_fragments(&fragments, 1);
+        let mut reassembler = FrameReassembler::new(DEADLINE, MAX_FRAME_SIZE);
+
+        let (header, payload) = &fragments[0];
+        assert!(reassembler.push(header, payload).is_some());
+
+        let (header, payload) = &parity[0];
+        assert!(reassembler.push(header, payload).is_none());
+    }
+
+    #[test]
+    fn parity_alone_rebuilds_single_fragment_frame() {
+        let frame = b"short frame".to_vec();
+        let fragments = fragment_frame(MediaKind::Delta, 3, 1_000, &frame).unwrap();
+        let parity = parity_fragments(&fragments, 1);
+        let mut reassembler = FrameReassembler::new(DEADLINE, MAX_FRAME_SIZE);
+
+        let (header, payload) = &parity[0];
+        let reassembled = reassembler.push(header, payload).unwrap();
+
+        assert_eq!(reassembled.kind, MediaKind::Delta);
+        assert_eq!(reassembled.payload, frame);
+    }
+}
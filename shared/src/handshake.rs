@@ -0,0 +1,149 @@
+/// Version of the TCP control protocol spoken by this build.
+pub const PROTOCOL_VERSION: u16 = 11;
+
+/// Oldest protocol version this build is still able to talk to. Only raised
+/// when a message's layout changes; versions that merely add messages stay
+/// supported, and those messages are never sent to peers that predate them.
+pub const MIN_PROTOCOL_VERSION: u16 = 9;
+
+/// First protocol version with lobby and room chat.
+pub const CHAT_PROTOCOL_VERSION: u16 = 10;
+
+/// First protocol version with direct messages.
+pub const DIRECT_MESSAGE_PROTOCOL_VERSION: u16 = 11;
+
+/// Version reported for clients from before the hello carried a version.
+/// Their hello is `[opcode][username length: u8][username]`, and they can only
+/// read a rejection in that same layout.
+pub const LEGACY_PROTOCOL_VERSION: u16 = 0;
+
+/// Largest frame (in bytes) this build will fragment or reassemble.
+pub const MAX_FRAME_SIZE: u32 = 1 << 18;
+
+macro_rules! capability_flags {
+    ($name:ident($bits:ty) { $($(#[$meta:meta])* $flag:ident = $value:expr;)* }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name($bits);
+
+        impl $name {
+            $($(#[$meta])* pub const $flag: Self = Self($value);)*
+
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            pub const fn all() -> Self {
+                Self(0 $(| $value)*)
+            }
+
+            pub const fn from_bits(bits: $bits) -> Self {
+                Self(bits)
+            }
+
+            pub const fn bits(self) -> $bits {
+                self.0
+            }
+
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            pub const fn union(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+
+            pub const fn intersection(self, other: Self) -> Self {
+                Self(self.0 & other.0)
+            }
+        }
+    };
+}
+
+capability_flags!(FrameFormats(u8) {
+    /// 4-bit grayscale cells, two per byte.
+    NIBBLE_GRAY = 1 << 0;
+    /// `NIBBLE_GRAY` plus a 6x6x6 color cube index per cell.
+    NIBBLE_COLOR = 1 << 1;
+    /// 8-bit grayscale cells, one per byte.
+    BYTE_GRAY = 1 << 2;
+    /// `BYTE_GRAY` plus a 6x6x6 color cube index per cell.
+    BYTE_COLOR = 1 << 3;
+});
+
+capability_flags!(Features(u32) {
+    /// Audio packets alongside video.
+    AUDIO = 1 << 0;
+});
+
+/// What one side of a session is able to send and receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub frame_formats: FrameFormats,
+    pub max_frame_size: u32,
+    pub features: Features,
+}
+
+impl Default for Capabilities {
+    /// Everything this build supports.
+    fn default() -> Self {
+        Self {
+            frame_formats: FrameFormats::all(),
+            max_frame_size: MAX_FRAME_SIZE,
+            features: Features::all(),
+        }
+    }
+}
+
+impl Capabilities {
+    /// Capabilities both sides support, which are the ones the session may use.
+    pub fn negotiate(&self, peer: &Capabilities) -> Capabilities {
+        Capabilities {
+            frame_formats: self.frame_formats.intersection(peer.frame_formats),
+            max_frame_size: self.max_frame_size.min(peer.max_frame_size),
+            features: self.features.intersection(peer.features),
+        }
+    }
+}
+
+/// Picks the protocol version to use with a peer advertising `peer_version`,
+/// or `None` if the two sides have no version in common.
+pub fn negotiate_protocol_version(peer_version: u16) -> Option<u16> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+
+    if version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+
+    return Some(version);
+}
+
+pub fn is_supported_protocol_version(version: u16) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_down_to_an_older_supported_peer() {
+        assert_eq!(
+            negotiate_protocol_version(PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate_protocol_version(MIN_PROTOCOL_VERSION),
+            Some(MIN_PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn rejects_peers_older_than_the_minimum() {
+        assert_eq!(negotiate_protocol_version(MIN_PROTOCOL_VERSION - 1), None);
+        assert_eq!(negotiate_protocol_version(LEGACY_PROTOCOL_VERSION), None);
+    }
+}
@@ -1,13 +1,73 @@
 pub const TCP_PORT: u16 = 8069;
 pub const UDP_PORT: u16 = 8070;
 
-mod tcp_command;
-mod tcp_command_type;
+mod audio;
+mod codec;
+mod fragment;
+mod handshake;
+mod media;
+mod message;
+mod message_stream;
+mod message_type;
+mod protocol_error;
+mod video;
+mod wire;
 
-pub use tcp_command::TcpCommand;
-pub use tcp_command::read_command_from_tcp_stream;
-pub use tcp_command::write_command_to_tcp_stream;
-pub use tcp_command_type::TcpCommandType;
+pub use audio::AUDIO_FRAME_SAMPLES;
+pub use audio::AUDIO_HEADER_LEN;
+pub use audio::AUDIO_SAMPLE_RATE;
+pub use audio::decode_audio;
+pub use audio::encode_audio;
+
+pub use codec::VideoDecoder;
+pub use codec::VideoEncoder;
+
+pub use fragment::FrameReassembler;
+pub use fragment::ReassembledFrame;
+pub use fragment::fragment_frame;
+pub use fragment::parity_fragments;
+
+pub use handshake::CHAT_PROTOCOL_VERSION;
+pub use handshake::Capabilities;
+pub use handshake::DIRECT_MESSAGE_PROTOCOL_VERSION;
+pub use handshake::Features;
+pub use handshake::FrameFormats;
+pub use handshake::LEGACY_PROTOCOL_VERSION;
+pub use handshake::MAX_FRAME_SIZE;
+pub use handshake::MIN_PROTOCOL_VERSION;
+pub use handshake::PROTOCOL_VERSION;
+pub use handshake::is_supported_protocol_version;
+pub use handshake::negotiate_protocol_version;
+
+pub use media::MAX_DATAGRAM_LEN;
+pub use media::MAX_FRAGMENT_PAYLOAD_LEN;
+pub use media::MEDIA_HEADER_LEN;
+pub use media::MEDIA_VERSION;
+pub use media::MediaHeader;
+pub use media::MediaKind;
+pub use media::ReceptionReport;
+
+pub use message::ChatScope;
+pub use message::MAX_CHAT_LEN;
+pub use message::Message;
+pub use message::RoomMode;
+pub use message::RoomName;
+pub use message::Username;
+pub use message::write_message;
+pub use message_stream::MessageStream;
+pub use message_type::EXTENSION_OPCODES;
+pub use message_type::MessageType;
+
+pub use protocol_error::ProtocolError;
+
+pub use video::Resolution;
+pub use video::VIDEO_HEADER_LEN;
+pub use video::VideoFormat;
+pub use video::VideoFrame;
 
 pub type StreamID = [u8; 4];
 pub type RoomStreamID = [u8; 1];
+
+/// Stream ID of the grid a server sends in composite rooms. Never given to
+/// a participant.
+pub const COMPOSITE_RSID: RoomStreamID = [0];
@@ -0,0 +1,149 @@
+use crate::{StreamID, protocol_error::ProtocolError};
+
+/// Version of the media packet layout, carried in every datagram so a
+/// receiver can reject packets it does not know how to parse.
+pub const MEDIA_VERSION: u8 = 7;
+
+/// Every media datagram carries `[version: u8][kind: u8][sequence: u32]
+/// [timestamp: u64][fragment index: u16][fragment count: u16]
+/// [payload length: u16]` ahead of its payload.
+pub const MEDIA_HEADER_LEN: usize = 20;
+
+/// Largest datagram either side sends, chosen to stay below the path MTU of
+/// common links so datagrams are not fragmented at the IP layer.
+pub const MAX_DATAGRAM_LEN: usize = 1200;
+
+/// Parity payloads start with `[protected kind: u8][group size: u8]
+/// [XOR of the group's payload lengths: u16]` ahead of the XOR of the
+/// group's payloads.
+pub const PARITY_HEADER_LEN: usize = 4;
+
+/// Largest slice of a frame that fits in one datagram next to the stream ID
+/// and media header, leaving room for a parity header so parity over full
+/// fragments fits too.
+pub const MAX_FRAGMENT_PAYLOAD_LEN: usize =
+    MAX_DATAGRAM_LEN - size_of::<StreamID>() - MEDIA_HEADER_LEN - PARITY_HEADER_LEN;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    /// A self-contained video frame, laid out as a `VideoFrame`.
+    Keyframe = 1,
+    /// A video frame encoded against an earlier keyframe.
+    Delta = 2,
+    /// XOR of a group of another kind's fragments, from which any one of
+    /// them can be rebuilt. `fragment_index` is the group's index and
+    /// `fragment_count` the number of data fragments in the frame.
+    Parity = 3,
+    /// 20 ms of sound, laid out as described at `AUDIO_HEADER_LEN`. Always
+    /// a single fragment, with its own sequence numbers apart from video.
+    Audio = 4,
+}
+
+impl MediaKind {
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_byte(kind: u8) -> Result<MediaKind, ProtocolError> {
+        match kind {
+            1 => Ok(MediaKind::Keyframe),
+            2 => Ok(MediaKind::Delta),
+            3 => Ok(MediaKind::Parity),
+            4 => Ok(MediaKind::Audio),
+            _ => Err(ProtocolError::UnknownMediaKind(kind)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaHeader {
+    pub kind: MediaKind,
+    /// Increases by one for every frame a sender puts on the wire, and is
+    /// shared by all fragments of that frame.
+    pub sequence: u32,
+    /// Capture time in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub fragment_index: u16,
+    pub fragment_count: u16,
+    pub payload_len: u16,
+}
+
+impl MediaHeader {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(MEDIA_VERSION);
+        buf.push(self.kind.to_byte());
+        buf.extend_from_slice(&self.sequence.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.fragment_index.to_be_bytes());
+        buf.extend_from_slice(&self.fragment_count.to_be_bytes());
+        buf.extend_from_slice(&self.payload_len.to_be_bytes());
+    }
+
+    /// Parses the header at the start of `packet` and returns it together
+    /// with the payload it announces.
+    pub fn decode(packet: &[u8]) -> Result<(MediaHeader, &[u8]), ProtocolError> {
+        if packet.len() < MEDIA_HEADER_LEN {
+            return Err(ProtocolError::TruncatedFrame);
+        }
+
+        if packet[0] != MEDIA_VERSION {
+            return Err(ProtocolError::UnsupportedMediaVersion(packet[0]));
+        }
+
+        let header = MediaHeader {
+            kind: MediaKind::from_byte(packet[1])?,
+            sequence: u32::from_be_bytes(packet[2..6].try_into().expect("Invalid slice length")),
+            timestamp: u64::from_be_bytes(packet[6..14].try_into().expect("Invalid slice length")),
+            fragment_index: u16::from_be_bytes(
+                packet[14..16].try_into().expect("Invalid slice length"),
+            ),
+            fragment_count: u16::from_be_bytes(
+                packet[16..18].try_into().expect("Invalid slice length"),
+            ),
+            payload_len: u16::from_be_bytes(
+                packet[18..20].try_into().expect("Invalid slice length"),
+            ),
+        };
+
+        if header.fragment_index >= header.fragment_count {
+            return Err(ProtocolError::InvalidFragment {
+                index: header.fragment_index,
+                count: header.fragment_count,
+            });
+        }
+
+        let payload = &packet[MEDIA_HEADER_LEN..];
+
+        if payload.len() < header.payload_len as usize {
+            return Err(ProtocolError::TruncatedFrame);
+        }
+
+        return Ok((header, &payload[..header.payload_len as usize]));
+    }
+}
+
+/// How well one sender's video is reaching one receiver, measured over the
+/// last report interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReceptionReport {
+    /// Frames lost, in thousandths of the frames expected.
+    pub loss_permille: u16,
+    /// Interarrival jitter in milliseconds, as defined by RFC 3550.
+    pub jitter_ms: u16,
+}
+
+impl ReceptionReport {
+    pub fn loss_percent(&self) -> f64 {
+        self.loss_permille as f64 / 10.0
+    }
+
+    /// The worse of two reports in each measure, which is what a sender
+    /// serving both receivers has to adapt to.
+    pub fn worst(self, other: Self) -> Self {
+        Self {
+            loss_permille: self.loss_permille.max(other.loss_permille),
+            jitter_ms: self.jitter_ms.max(other.jitter_ms),
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    RoomStreamID, StreamID,
+    handshake::{
+        CHAT_PROTOCOL_VERSION, Capabilities, DIRECT_MESSAGE_PROTOCOL_VERSION, FrameFormats,
+        MIN_PROTOCOL_VERSION,
+    },
+    media::ReceptionReport,
+    message_type::MessageType,
+    protocol_error::ProtocolError,
+    video::Resolution,
+    wire::{PayloadReader, WireField},
+};
+
+pub type Username = String;
+pub type RoomName = String;
+
+/// How a room's server carries video between participants, chosen when the
+/// room is created.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoomMode {
+    /// Every participant's stream is forwarded to every other participant.
+    #[default]
+    Forward = 0,
+    /// The server lays out everyone else's video into one grid per
+    /// receiver and sends only that, as the stream `COMPOSITE_RSID`.
+    Composite = 1,
+}
+
+impl RoomMode {
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_byte(mode: u8) -> Result<RoomMode, ProtocolError> {
+        match mode {
+            0 => Ok(RoomMode::Forward),
+            1 => Ok(RoomMode::Composite),
+            _ => Err(ProtocolError::UnknownRoomMode(mode)),
+        }
+    }
+}
+
+/// Who a chat message is for.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatScope {
+    /// Everyone connected to the server.
+    Lobby = 0,
+    /// Everyone in the sender's room.
+    Room = 1,
+}
+
+impl ChatScope {
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_byte(scope: u8) -> Result<ChatScope, ProtocolError> {
+        match scope {
+            0 => Ok(ChatScope::Lobby),
+            1 => Ok(ChatScope::Room),
+            _ => Err(ProtocolError::UnknownChatScope(scope)),
+        }
+    }
+}
+
+/// Longest chat or direct message text, in bytes, a server accepts.
+pub const MAX_CHAT_LEN: usize = 500;
+
+/// Everything that is sent over the TCP control connection. Each variant
+/// carries exactly the fields its opcode puts on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    HelloFromClient {
+        protocol_version: u16,
+        capabilities: Capabilities,
+        username: Username,
+    },
+    HelloFromServer {
+        protocol_version: u16,
+        capabilities: Capabilities,
+    },
+    InvalidUsername {
+        reason: String,
+    },
+    GetActiveUsers,
+    ReturnActiveUsers {
+        usernames: Vec<Username>,
+    },
+    CreateRoom {
+        room: RoomName,
+        mode: RoomMode,
+    },
+    InvalidRoomName {
+        reason: String,
+    },
+    CreateRoomSuccess,
+    GetRooms,
+    ReturnRooms {
+        rooms: Vec<RoomName>,
+    },
+    JoinRoom {
+        room: RoomName,
+    },
+    JoinRoomSuccess {
+        sid: StreamID,
+        mode: RoomMode,
+    },
+    InvalidJoinRoom {
+        reason: String,
+    },
+    OtherUserJoinedRoom {
+        rsid: RoomStreamID,
+    },
+    OtherUserLeftRoom {
+        rsid: RoomStreamID,
+    },
+    DeleteRoom {
+        room: RoomName,
+    },
+    DeleteRoomSuccess,
+    IncompatibleProtocol {
+        reason: String,
+    },
+    MalformedMessage {
+        reason: String,
+    },
+    /// Largest grid the sender of this message can make use of while in a call.
+    SetPreferredResolution {
+        resolution: Resolution,
+    },
+    /// Largest grid anyone else in the call has asked for.
+    RequestResolution {
+        resolution: Resolution,
+    },
+    /// Asks the sender of the given stream for a fresh keyframe.
+    RequestKeyframe {
+        rsid: RoomStreamID,
+    },
+    /// Someone in the call needs a keyframe from the receiver of this message.
+    KeyframeRequested,
+    /// Frame formats every other participant in the call can decode.
+    SupportedFrameFormats {
+        frame_formats: FrameFormats,
+    },
+    /// How well the given stream is arriving at the sender of this message.
+    ReportReception {
+        rsid: RoomStreamID,
+        report: ReceptionReport,
+    },
+    /// Worst reception of the receiver of this message's stream among
+    /// everyone in the call.
+    ReceptionFeedback {
+        report: ReceptionReport,
+    },
+    /// Asks the server to pass `text` on to everyone in `scope`.
+    SendChat {
+        scope: ChatScope,
+        text: String,
+    },
+    /// Chat text from `sender`, stamped by the server in milliseconds since
+    /// the Unix epoch. Senders get their own messages back too.
+    ChatMessage {
+        scope: ChatScope,
+        sender: Username,
+        timestamp: u64,
+        text: String,
+    },
+    InvalidChat {
+        reason: String,
+    },
+    /// Asks the server to pass `text` on to `recipient` alone.
+    DirectMessage {
+        recipient: Username,
+        text: String,
+    },
+    /// Text `sender` sent to the receiver of this message alone, stamped like
+    /// `ChatMessage`.
+    DirectMessageReceived {
+        sender: Username,
+        timestamp: u64,
+        text: String,
+    },
+    /// The direct message to `recipient` stamped `timestamp` was written to
+    /// their connection.
+    DirectMessageDelivered {
+        recipient: Username,
+        timestamp: u64,
+    },
+    InvalidDirectMessage {
+        reason: String,
+    },
+}
+
+impl Message {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::HelloFromClient { .. } => MessageType::HelloFromClient,
+            Message::HelloFromServer { .. } => MessageType::HelloFromServer,
+            Message::InvalidUsername { .. } => MessageType::InvalidUsername,
+            Message::GetActiveUsers => MessageType::GetActiveUsers,
+            Message::ReturnActiveUsers { .. } => MessageType::ReturnActiveUsers,
+            Message::CreateRoom { .. } => MessageType::CreateRoom,
+            Message::InvalidRoomName { .. } => MessageType::InvalidRoomName,
+            Message::CreateRoomSuccess => MessageType::CreateRoomSuccess,
+            Message::GetRooms => MessageType::GetRooms,
+            Message::ReturnRooms { .. } => MessageType::ReturnRooms,
+            Message::JoinRoom { .. } => MessageType::JoinRoom,
+            Message::JoinRoomSuccess { .. } => MessageType::JoinRoomSuccess,
+            Message::InvalidJoinRoom { .. } => MessageType::InvalidJoinRoom,
+            Message::OtherUserJoinedRoom { .. } => MessageType::OtherUserJoinedRoom,
+            Message::OtherUserLeftRoom { .. } => MessageType::OtherUserLeftRoom,
+            Message::DeleteRoom { .. } => MessageType::DeleteRoom,
+            Message::DeleteRoomSuccess => MessageType::DeleteRoomSuccess,
+            Message::IncompatibleProtocol { .. } => MessageType::IncompatibleProtocol,
+            Message::MalformedMessage { .. } => MessageType::MalformedMessage,
+            Message::SetPreferredResolution { .. } => MessageType::SetPreferredResolution,
+            Message::RequestResolution { .. } => MessageType::RequestResolution,
+            Message::RequestKeyframe { .. } => MessageType::RequestKeyframe,
+            Message::KeyframeRequested => MessageType::KeyframeRequested,
+            Message::SupportedFrameFormats { .. } => MessageType::SupportedFrameFormats,
+            Message::ReportReception { .. } => MessageType::ReportReception,
+            Message::ReceptionFeedback { .. } => MessageType::ReceptionFeedback,
+            Message::SendChat { .. } => MessageType::SendChat,
+            Message::ChatMessage { .. } => MessageType::ChatMessage,
+            Message::InvalidChat { .. } => MessageType::InvalidChat,
+            Message::DirectMessage { .. } => MessageType::DirectMessage,
+            Message::DirectMessageReceived { .. } => MessageType::DirectMessageReceived,
+            Message::DirectMessageDelivered { .. } => MessageType::DirectMessageDelivered,
+            Message::InvalidDirectMessage { .. } => MessageType::InvalidDirectMessage,
+        }
+    }
+
+    /// Oldest protocol version that has this message. It must not be sent to
+    /// a peer that negotiated anything older.
+    pub fn min_protocol_version(&self) -> u16 {
+        match self {
+            Message::SendChat { .. }
+            | Message::ChatMessage { .. }
+            | Message::InvalidChat { .. } => CHAT_PROTOCOL_VERSION,
+            Message::DirectMessage { .. }
+            | Message::DirectMessageReceived { .. }
+            | Message::DirectMessageDelivered { .. }
+            | Message::InvalidDirectMessage { .. } => DIRECT_MESSAGE_PROTOCOL_VERSION,
+            _ => MIN_PROTOCOL_VERSION,
+        }
+    }
+
+    fn encode_payload(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        match self {
+            Message::HelloFromClient {
+                protocol_version,
+                capabilities,
+                username,
+            } => {
+                protocol_version.encode(buf)?;
+                capabilities.encode(buf)?;
+                username.encode(buf)?;
+            }
+
+            Message::HelloFromServer {
+                protocol_version,
+                capabilities,
+            } => {
+                protocol_version.encode(buf)?;
+                capabilities.encode(buf)?;
+            }
+
+            Message::GetActiveUsers
+            | Message::CreateRoomSuccess
+            | Message::GetRooms
+            | Message::DeleteRoomSuccess
+            | Message::KeyframeRequested => {}
+
+            Message::InvalidUsername { reason }
+            | Message::InvalidRoomName { reason }
+            | Message::InvalidJoinRoom { reason }
+            | Message::IncompatibleProtocol { reason }
+            | Message::MalformedMessage { reason }
+            | Message::InvalidChat { reason }
+            | Message::InvalidDirectMessage { reason } => reason.encode(buf)?,
+
+            Message::CreateRoom { room, mode } => {
+                room.encode(buf)?;
+                mode.encode(buf)?;
+            }
+
+            Message::JoinRoom { room } | Message::DeleteRoom { room } => room.encode(buf)?,
+
+            Message::ReturnActiveUsers { usernames } => usernames.encode(buf)?,
+            Message::ReturnRooms { rooms } => rooms.encode(buf)?,
+
+            Message::JoinRoomSuccess { sid, mode } => {
+                sid.encode(buf)?;
+                mode.encode(buf)?;
+            }
+
+            Message::OtherUserJoinedRoom { rsid }
+            | Message::OtherUserLeftRoom { rsid }
+            | Message::RequestKeyframe { rsid } => rsid.encode(buf)?,
+
+            Message::SupportedFrameFormats { frame_formats } => frame_formats.encode(buf)?,
+
+            Message::ReportReception { rsid, report } => {
+                rsid.encode(buf)?;
+                report.encode(buf)?;
+            }
+
+            Message::ReceptionFeedback { report } => report.encode(buf)?,
+
+            Message::SendChat { scope, text } => {
+                scope.encode(buf)?;
+                text.encode(buf)?;
+            }
+
+            Message::ChatMessage {
+                scope,
+                sender,
+                timestamp,
+                text,
+            } => {
+                scope.encode(buf)?;
+                sender.encode(buf)?;
+                timestamp.encode(buf)?;
+                text.encode(buf)?;
+            }
+
+            Message::DirectMessage { recipient, text } => {
+                recipient.encode(buf)?;
+                text.encode(buf)?;
+            }
+
+            Message::DirectMessageReceived {
+                sender,
+                timestamp,
+                text,
+            } => {
+                sender.encode(buf)?;
+                timestamp.encode(buf)?;
+                text.encode(buf)?;
+            }
+
+            Message::DirectMessageDelivered {
+                recipient,
+                timestamp,
+            } => {
+                recipient.encode(buf)?;
+                timestamp.encode(buf)?;
+            }
+
+            Message::SetPreferredResolution { resolution }
+            | Message::RequestResolution { resolution } => resolution.encode(buf)?,
+        }
+
+        return Ok(());
+    }
+
+    fn decode_payload(message_type: MessageType, payload: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = PayloadReader::new(payload);
+
+        let message = match message_type {
+            MessageType::HelloFromClient => Message::HelloFromClient {
+                protocol_version: reader.read()?,
+                capabilities: reader.read()?,
+                username: reader.read()?,
+            },
+            MessageType::HelloFromServer => Message::HelloFromServer {
+                protocol_version: reader.read()?,
+                capabilities: reader.read()?,
+            },
+            MessageType::InvalidUsername => Message::InvalidUsername {
+                reason: reader.read()?,
+            },
+            MessageType::GetActiveUsers => Message::GetActiveUsers,
+            MessageType::ReturnActiveUsers => Message::ReturnActiveUsers {
+                usernames: reader.read()?,
+            },
+            MessageType::CreateRoom => Message::CreateRoom {
+                room: reader.read()?,
+                mode: reader.read()?,
+            },
+            MessageType::InvalidRoomName => Message::InvalidRoomName {
+                reason: reader.read()?,
+            },
+            MessageType::CreateRoomSuccess => Message::CreateRoomSuccess,
+            MessageType::GetRooms => Message::GetRooms,
+            MessageType::ReturnRooms => Message::ReturnRooms {
+                rooms: reader.read()?,
+            },
+            MessageType::JoinRoom => Message::JoinRoom {
+                room: reader.read()?,
+            },
+            MessageType::JoinRoomSuccess => Message::JoinRoomSuccess {
+                sid: reader.read()?,
+                mode: reader.read()?,
+            },
+            MessageType::InvalidJoinRoom => Message::InvalidJoinRoom {
+                reason: reader.read()?,
+            },
+            MessageType::OtherUserJoinedRoom => Message::OtherUserJoinedRoom {
+                rsid: reader.read()?,
+            },
+            MessageType::OtherUserLeftRoom => Message::OtherUserLeftRoom {
+                rsid: reader.read()?,
+            },
+            MessageType::DeleteRoom => Message::DeleteRoom {
+                room: reader.read()?,
+            },
+            MessageType::DeleteRoomSuccess => Message::DeleteRoomSuccess,
+            MessageType::IncompatibleProtocol => Message::IncompatibleProtocol {
+                reason: reader.read()?,
+            },
+            MessageType::MalformedMessage => Message::MalformedMessage {
+                reason: reader.read()?,
+            },
+            MessageType::SetPreferredResolution => Message::SetPreferredResolution {
+                resolution: reader.read()?,
+            },
+            MessageType::RequestResolution => Message::RequestResolution {
+                resolution: reader.read()?,
+            },
+            MessageType::RequestKeyframe => Message::RequestKeyframe {
+                rsid: reader.read()?,
+            },
+            MessageType::KeyframeRequested => Message::KeyframeRequested,
+            MessageType::SupportedFrameFormats => Message::SupportedFrameFormats {
+                frame_formats: reader.read()?,
+            },
+            MessageType::ReportReception => Message::ReportReception {
+                rsid: reader.read()?,
+                report: reader.read()?,
+            },
+            MessageType::ReceptionFeedback => Message::ReceptionFeedback {
+                report: reader.read()?,
+            },
+            MessageType::SendChat => Message::SendChat {
+                scope: reader.read()?,
+                text: reader.read()?,
+            },
+            MessageType::ChatMessage => Message::ChatMessage {
+                scope: reader.read()?,
+                sender: reader.read()?,
+                timestamp: reader.read()?,
+                text: reader.read()?,
+            },
+            MessageType::InvalidChat => Message::InvalidChat {
+                reason: reader.read()?,
+            },
+            MessageType::DirectMessage => Message::DirectMessage {
+                recipient: reader.read()?,
+                text: reader.read()?,
+            },
+            MessageType::DirectMessageReceived => Message::DirectMessageReceived {
+                sender: reader.read()?,
+                timestamp: reader.read()?,
+                text: reader.read()?,
+            },
+            MessageType::DirectMessageDelivered => Message::DirectMessageDelivered {
+                recipient: reader.read()?,
+                timestamp: reader.read()?,
+            },
+            MessageType::InvalidDirectMessage => Message::InvalidDirectMessage {
+                reason: reader.read()?,
+            },
+        };
+
+        return Ok(message);
+    }
+}
+
+/// Every message is framed as `[opcode: u8][payload length: u32][payload]`.
+pub const FRAME_HEADER_LEN: usize = 5;
+
+/// Upper bound on a single frame's payload, so a corrupt length cannot make
+/// the reader allocate without limit.
+pub const MAX_FRAME_PAYLOAD_LEN: u32 = 1 << 20;
+
+/// Returns the payload length announced by the frame header at the start of
+/// `buffer`, or `None` if the header has not been fully received yet.
+pub(crate) fn frame_payload_len(buffer: &[u8]) -> Option<u32> {
+    if buffer.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+
+    let payload_len = u32::from_be_bytes(
+        buffer[1..FRAME_HEADER_LEN]
+            .try_into()
+            .expect("Invalid frame header slice length"),
+    );
+
+    return Some(payload_len);
+}
+
+/// Decodes one complete frame. Frames with an extension opcode decode to
+/// `None` so the caller can skip them.
+pub(crate) fn decode_frame(frame: &[u8]) -> Result<Option<Message>, ProtocolError> {
+    if MessageType::is_extension(frame[0]) {
+        return Ok(None);
+    }
+
+    let message_type = MessageType::from_byte(frame[0])?;
+
+    return Ok(Some(Message::decode_payload(
+        message_type,
+        &frame[FRAME_HEADER_LEN..],
+    )?));
+}
+
+pub(crate) fn encode_frame(message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    let mut frame = vec![message.message_type().to_byte(), 0, 0, 0, 0];
+    message.encode_payload(&mut frame)?;
+
+    let payload_len = frame.len() - FRAME_HEADER_LEN;
+
+    if payload_len > MAX_FRAME_PAYLOAD_LEN as usize {
+        return Err(ProtocolError::OversizeField {
+            field: "Message payload",
+            len: payload_len,
+            max: MAX_FRAME_PAYLOAD_LEN as usize,
+        });
+    }
+
+    frame[1..FRAME_HEADER_LEN].copy_from_slice(&(payload_len as u32).to_be_bytes());
+
+    return Ok(frame);
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    message: Message,
+    writer: &mut W,
+) -> Result<(), ProtocolError> {
+    let frame = encode_frame(&message)?;
+    writer.write_all(&frame).await?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handshake::{Features, PROTOCOL_VERSION},
+        message_type::EXTENSION_OPCODES,
+    };
+
+    fn assert_round_trips(message: Message) {
+        let frame = encode_frame(&message).unwrap();
+
+        assert_eq!(frame[0], message.message_type().to_byte());
+        assert_eq!(decode_frame(&frame).unwrap(), Some(message));
+    }
+
+    macro_rules! round_trip_tests {
+        ($($name:ident: $message:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_round_trips($message);
+                }
+            )*
+        };
+    }
+
+    fn report() -> ReceptionReport {
+        ReceptionReport {
+            loss_permille: 125,
+            jitter_ms: 40,
+        }
+    }
+
+    round_trip_tests! {
+        hello_from_client: Message::HelloFromClient {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities {
+                frame_formats: FrameFormats::NIBBLE_GRAY.union(FrameFormats::BYTE_COLOR),
+                max_frame_size: 4096,
+                features: Features::AUDIO,
+            },
+            username: "alice".to_string(),
+        },
+        hello_from_server: Message::HelloFromServer {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+        },
+        invalid_username: Message::InvalidUsername { reason: "taken".to_string() },
+        get_active_users: Message::GetActiveUsers,
+        return_active_users: Message::ReturnActiveUsers {
+            usernames: vec!["alice".to_string(), "bob".to_string()],
+        },
+        create_room: Message::CreateRoom {
+            room: "lobby".to_string(),
+            mode: RoomMode::Composite,
+        },
+        invalid_room_name: Message::InvalidRoomName { reason: "bad".to_string() },
+        create_room_success: Message::CreateRoomSuccess,
+        get_rooms: Message::GetRooms,
+        return_rooms: Message::ReturnRooms { rooms: Vec::new() },
+        join_room: Message::JoinRoom { room: "lobby".to_string() },
+        join_room_success: Message::JoinRoomSuccess {
+            sid: [1, 2, 3, 4],
+            mode: RoomMode::Forward,
+        },
+        invalid_join_room: Message::InvalidJoinRoom { reason: "full".to_string() },
+        other_user_joined_room: Message::OtherUserJoinedRoom { rsid: [7] },
+        other_user_left_room: Message::OtherUserLeftRoom { rsid: [7] },
+        delete_room: Message::DeleteRoom { room: "lobby".to_string() },
+        delete_room_success: Message::DeleteRoomSuccess,
+        incompatible_protocol: Message::IncompatibleProtocol { reason: "old".to_string() },
+        malformed_message: Message::MalformedMessage { reason: "bad".to_string() },
+        set_preferred_resolution: Message::SetPreferredResolution {
+            resolution: Resolution::new(92, 28),
+        },
+        request_resolution: Message::RequestResolution {
+            resolution: Resolution::new(46, 14),
+        },
+        request_keyframe: Message::RequestKeyframe { rsid: [3] },
+        keyframe_requested: Message::KeyframeRequested,
+        supported_frame_formats: Message::SupportedFrameFormats {
+            frame_formats: FrameFormats::all(),
+        },
+        report_reception: Message::ReportReception { rsid: [3], report: report() },
+        reception_feedback: Message::ReceptionFeedback { report: report() },
+        send_chat: Message::SendChat {
+            scope: ChatScope::Room,
+            text: "héllo".to_string(),
+        },
+        chat_message: Message::ChatMessage {
+            scope: ChatScope::Lobby,
+            sender: "alice".to_string(),
+            timestamp: 1_700_000_000_000,
+            text: "hi".to_string(),
+        },
+        invalid_chat: Message::InvalidChat { reason: "empty".to_string() },
+        direct_message: Message::DirectMessage {
+            recipient: "bob".to_string(),
+            text: "psst".to_string(),
+        },
+        direct_message_received: Message::DirectMessageReceived {
+            sender: "alice".to_string(),
+            timestamp: 1_700_000_000_000,
+            text: "psst".to_string(),
+        },
+        direct_message_delivered: Message::DirectMessageDelivered {
+            recipient: "bob".to_string(),
+            timestamp: 1_700_000_000_000,
+        },
+        invalid_direct_message: Message::InvalidDirectMessage { reason: "offline".to_string() },
+    }
+
+    #[test]
+    fn string_longer_than_the_payload_is_truncated() {
+        let mut frame = encode_frame(&Message::JoinRoom {
+            room: "lobby".to_string(),
+        })
+        .unwrap();
+
+        // Claims one more byte of room name than the payload holds.
+        frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + 2].copy_from_slice(&6u16.to_be_bytes());
+
+        assert!(matches!(
+            decode_frame(&frame),
+            Err(ProtocolError::TruncatedFrame)
+        ));
+    }
+
+    #[test]
+    fn frame_round_trips() {
+        let message = Message::JoinRoom {
+            room: "lobby".to_string(),
+        };
+
+        let frame = encode_frame(&message).unwrap();
+
+        assert_eq!(frame[0], MessageType::JoinRoom.to_byte());
+        assert_eq!(
+            frame_payload_len(&frame),
+            Some((frame.len() - FRAME_HEADER_LEN) as u32)
+        );
+        assert_eq!(decode_frame(&frame).unwrap(), Some(message));
+    }
+
+    #[test]
+    fn truncated_header_has_no_payload_len() {
+        let frame = encode_frame(&Message::GetRooms).unwrap();
+
+        for len in 0..FRAME_HEADER_LEN {
+            assert_eq!(frame_payload_len(&frame[..len]), None);
+        }
+        assert_eq!(frame_payload_len(&frame), Some(0));
+    }
+
+    #[test]
+    fn extension_frames_decode_to_none() {
+        let frame = [*EXTENSION_OPCODES.start(), 0, 0, 0, 2, 0xAB, 0xCD];
+
+        assert_eq!(decode_frame(&frame).unwrap(), None);
+    }
+
+    /// A `ReturnRooms` whose payload is exactly `target_len` bytes: a `u16`
+    /// count, then rooms that each take a `u16` length plus their name.
+    fn return_rooms_with_payload_len(target_len: usize) -> Message {
+        let mut rooms = Vec::new();
+        let mut remaining = target_len - 2;
+
+        while remaining > 0 {
+            let name_len = remaining.min(u16::MAX as usize + 2) - 2;
+            rooms.push("r".repeat(name_len));
+            remaining -= name_len + 2;
+        }
+
+        return Message::ReturnRooms { rooms };
+    }
+
+    #[test]
+    fn payload_at_the_limit_is_accepted() {
+        let message = return_rooms_with_payload_len(MAX_FRAME_PAYLOAD_LEN as usize);
+
+        let frame = encode_frame(&message).unwrap();
+
+        assert_eq!(frame_payload_len(&frame), Some(MAX_FRAME_PAYLOAD_LEN));
+        assert_eq!(decode_frame(&frame).unwrap(), Some(message));
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let message = return_rooms_with_payload_len(MAX_FRAME_PAYLOAD_LEN as usize + 2);
+
+        assert!(matches!(
+            encode_frame(&message),
+            Err(ProtocolError::OversizeField { len, max, .. })
+                if len == MAX_FRAME_PAYLOAD_LEN as usize + 2 && max == MAX_FRAME_PAYLOAD_LEN as usize
+        ));
+    }
+}
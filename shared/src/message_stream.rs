@@ -0,0 +1,373 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    Message, ProtocolError,
+    handshake::{Capabilities, Features, FrameFormats, LEGACY_PROTOCOL_VERSION},
+    message::{
+        FRAME_HEADER_LEN, MAX_FRAME_PAYLOAD_LEN, decode_frame, frame_payload_len, write_message,
+    },
+    message_type::MessageType,
+};
+
+const READ_CHUNK_LEN: usize = 4096;
+
+/// Message reader that owns its partial-read state.
+///
+/// Bytes are buffered until a whole frame has arrived, and a message is only
+/// yielded once it is fully decoded. `read_message` is therefore cancellation
+/// safe: if it loses a `tokio::select!` race, nothing already received is
+/// lost and the next call carries on from where it stopped.
+pub struct MessageStream<S> {
+    stream: S,
+    buffer: Vec<u8>,
+    discard_len: usize,
+}
+
+impl<S> MessageStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+            discard_len: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    fn discard_buffered(&mut self) {
+        let len = self.discard_len.min(self.buffer.len());
+        self.buffer.drain(..len);
+        self.discard_len -= len;
+    }
+}
+
+impl<S: AsyncRead + Unpin> MessageStream<S> {
+    /// Reads the next message, skipping frames with an extension opcode.
+    /// Returns `None` once the peer closes the stream between messages.
+    ///
+    /// Frames are consumed in full even when they fail to decode (oversized
+    /// frames are discarded as they arrive), so after an error for which
+    /// `ProtocolError::is_recoverable` holds the caller may keep reading.
+    pub async fn read_message(&mut self) -> Result<Option<Message>, ProtocolError> {
+        loop {
+            self.discard_buffered();
+
+            if self.discard_len == 0
+                && let Some(payload_len) = frame_payload_len(&self.buffer)
+            {
+                let frame_len = FRAME_HEADER_LEN + payload_len as usize;
+
+                if payload_len > MAX_FRAME_PAYLOAD_LEN {
+                    self.discard_len = frame_len;
+
+                    return Err(ProtocolError::OversizeField {
+                        field: "Message payload",
+                        len: payload_len as usize,
+                        max: MAX_FRAME_PAYLOAD_LEN as usize,
+                    });
+                }
+
+                if self.buffer.len() >= frame_len {
+                    let frame: Vec<u8> = self.buffer.drain(..frame_len).collect();
+
+                    match decode_frame(&frame)? {
+                        Some(message) => return Ok(Some(message)),
+                        None => continue,
+                    }
+                }
+            }
+
+            if !self.fill_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads the first message of a connection. Besides everything
+    /// `read_message` accepts, this recognises the hello of clients from
+    /// before the hello was versioned, and returns it as a
+    /// `Message::HelloFromClient` with `LEGACY_PROTOCOL_VERSION`.
+    ///
+    /// A current hello's length header always starts with a zero byte, since
+    /// payloads are capped at `MAX_FRAME_PAYLOAD_LEN`, while a legacy hello has
+    /// the username length there.
+    pub async fn read_hello(&mut self) -> Result<Option<Message>, ProtocolError> {
+        let hello_opcode = MessageType::HelloFromClient.to_byte();
+
+        loop {
+            if self.buffer.len() >= 2 {
+                if self.buffer[0] != hello_opcode || self.buffer[1] == 0 {
+                    return self.read_message().await;
+                }
+
+                let hello_len = 2 + self.buffer[1] as usize;
+
+                if self.buffer.len() >= hello_len {
+                    let hello: Vec<u8> = self.buffer.drain(..hello_len).collect();
+
+                    return Ok(Some(Message::HelloFromClient {
+                        protocol_version: LEGACY_PROTOCOL_VERSION,
+                        capabilities: Capabilities {
+                            frame_formats: FrameFormats::NIBBLE_GRAY,
+                            max_frame_size: 0,
+                            features: Features::empty(),
+                        },
+                        username: std::str::from_utf8(&hello[2..])?.to_string(),
+                    }));
+                }
+            }
+
+            if !self.fill_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads one more chunk into the buffer. Returns `false` if the peer
+    /// closed the stream between messages.
+    async fn fill_buffer(&mut self) -> Result<bool, ProtocolError> {
+        let mut chunk = [0; READ_CHUNK_LEN];
+        let n = self.stream.read(&mut chunk).await?;
+
+        if n == 0 {
+            if self.buffer.is_empty() && self.discard_len == 0 {
+                return Ok(false);
+            }
+
+            return Err(ProtocolError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed in the middle of a message",
+            )));
+        }
+
+        self.buffer.extend_from_slice(&chunk[..n]);
+
+        return Ok(true);
+    }
+}
+
+impl<S: AsyncWrite + Unpin> MessageStream<S> {
+    pub async fn write_message(&mut self, message: Message) -> Result<(), ProtocolError> {
+        return write_message(message, &mut self.stream).await;
+    }
+
+    /// Turns away a client whose hello was read as `LEGACY_PROTOCOL_VERSION`,
+    /// in the only reply layout it understands: an `InvalidUsername` with a
+    /// `u8` length. `reason` is cut short to fit.
+    pub async fn write_legacy_rejection(&mut self, reason: &str) -> Result<(), ProtocolError> {
+        let mut len = reason.len().min(u8::MAX as usize);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut reply = vec![MessageType::InvalidUsername.to_byte(), len as u8];
+        reply.extend_from_slice(&reason.as_bytes()[..len]);
+
+        self.stream.write_all(&reply).await?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt, duplex},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::{
+        handshake::{PROTOCOL_VERSION, negotiate_protocol_version},
+        message::encode_frame,
+        message_type::EXTENSION_OPCODES,
+    };
+
+    fn join_room(room: &str) -> Message {
+        Message::JoinRoom {
+            room: room.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_eof_between_messages_is_none() {
+        let (mut client, server) = duplex(1024);
+        let mut server = MessageStream::new(server);
+
+        client
+            .write_all(&encode_frame(&join_room("lobby")).unwrap())
+            .await
+            .unwrap();
+        drop(client);
+
+        assert_eq!(
+            server.read_message().await.unwrap(),
+            Some(join_room("lobby"))
+        );
+        assert!(server.read_message().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn eof_mid_frame_is_unexpected_eof() {
+        let (mut client, server) = duplex(1024);
+        let mut server = MessageStream::new(server);
+
+        let frame = encode_frame(&join_room("lobby")).unwrap();
+        client.write_all(&frame[..frame.len() - 1]).await.unwrap();
+        drop(client);
+
+        let error = server.read_message().await.unwrap_err();
+
+        assert!(matches!(
+            &error,
+            ProtocolError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        assert!(!error.is_recoverable());
+    }
+
+    #[tokio::test]
+    async fn dropping_a_read_mid_frame_loses_nothing() {
+        let (mut client, server) = duplex(1024);
+        let mut server = MessageStream::new(server);
+
+        let frame = encode_frame(&join_room("lobby")).unwrap();
+        let (first, rest) = frame.split_at(FRAME_HEADER_LEN + 2);
+
+        client.write_all(first).await.unwrap();
+
+        // Loses the race, as it would against another `select!` branch, after
+        // having buffered the first part of the frame.
+        let cancelled = timeout(Duration::from_millis(20), server.read_message()).await;
+        assert!(cancelled.is_err());
+
+        client.write_all(rest).await.unwrap();
+
+        assert_eq!(
+            server.read_message().await.unwrap(),
+            Some(join_room("lobby"))
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_extension_frames_and_recovers_from_unknown_opcodes() {
+        let (mut client, server) = duplex(1024);
+        let mut server = MessageStream::new(server);
+
+        let mut bytes = vec![*EXTENSION_OPCODES.end(), 0, 0, 0, 3, 1, 2, 3];
+        bytes.extend(encode_frame(&join_room("first")).unwrap());
+        bytes.extend([0x10, 0, 0, 0, 2, 4, 5]);
+        bytes.extend(encode_frame(&join_room("second")).unwrap());
+        client.write_all(&bytes).await.unwrap();
+
+        assert_eq!(
+            server.read_message().await.unwrap(),
+            Some(join_room("first"))
+        );
+
+        let error = server.read_message().await.unwrap_err();
+        assert!(matches!(error, ProtocolError::UnknownOpcode(0x10)));
+        assert!(error.is_recoverable());
+
+        assert_eq!(
+            server.read_message().await.unwrap(),
+            Some(join_room("second"))
+        );
+    }
+
+    #[tokio::test]
+    async fn discards_oversized_frames_without_losing_sync() {
+        let (mut client, server) = duplex(READ_CHUNK_LEN);
+        let mut server = MessageStream::new(server);
+
+        let oversized_len = MAX_FRAME_PAYLOAD_LEN + 1;
+
+        let writer = tokio::spawn(async move {
+            let mut header = vec![MessageType::GetRooms.to_byte()];
+            header.extend(oversized_len.to_be_bytes());
+            client.write_all(&header).await.unwrap();
+            client
+                .write_all(&vec![0xAA; oversized_len as usize])
+                .await
+                .unwrap();
+            client
+                .write_all(&encode_frame(&join_room("after")).unwrap())
+                .await
+                .unwrap();
+            client
+        });
+
+        let error = server.read_message().await.unwrap_err();
+        assert!(matches!(
+            error,
+            ProtocolError::OversizeField { len, .. } if len == oversized_len as usize
+        ));
+        assert!(error.is_recoverable());
+
+        assert_eq!(
+            server.read_message().await.unwrap(),
+            Some(join_room("after"))
+        );
+        assert!(server.buffer.len() < READ_CHUNK_LEN);
+
+        drop(writer.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn legacy_hello_gets_a_version_unsupported_reply_it_can_read() {
+        let (mut client, server) = duplex(1024);
+        let mut server = MessageStream::new(server);
+
+        let mut legacy_hello = vec![MessageType::HelloFromClient.to_byte(), 5];
+        legacy_hello.extend_from_slice(b"alice");
+        client.write_all(&legacy_hello).await.unwrap();
+
+        let Some(Message::HelloFromClient {
+            protocol_version,
+            username,
+            ..
+        }) = server.read_hello().await.unwrap()
+        else {
+            panic!("Expected HelloFromClient");
+        };
+
+        assert_eq!(protocol_version, LEGACY_PROTOCOL_VERSION);
+        assert_eq!(username, "alice");
+        assert_eq!(negotiate_protocol_version(protocol_version), None);
+
+        let reason = "Client protocol version is not supported, please update your client.";
+        server.write_legacy_rejection(reason).await.unwrap();
+
+        let mut reply = vec![0; 2 + reason.len()];
+        client.read_exact(&mut reply).await.unwrap();
+
+        assert_eq!(reply[0], MessageType::InvalidUsername.to_byte());
+        assert_eq!(reply[1] as usize, reason.len());
+        assert_eq!(&reply[2..], reason.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn read_hello_passes_current_hellos_through() {
+        let (client, server) = duplex(1024);
+        let mut client = MessageStream::new(client);
+        let mut server = MessageStream::new(server);
+
+        let hello = Message::HelloFromClient {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            username: "alice".to_string(),
+        };
+        client.write_message(hello.clone()).await.unwrap();
+
+        assert_eq!(server.read_hello().await.unwrap(), Some(hello));
+    }
+}
@@ -0,0 +1,98 @@
+use std::ops::RangeInclusive;
+
+use crate::protocol_error::ProtocolError;
+
+/// Opcodes are part of the wire format. Never renumber an existing variant;
+/// new messages take the next free value below `EXTENSION_OPCODES`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum MessageType {
+    HelloFromClient = 69,
+    HelloFromServer = 70,
+    InvalidUsername = 71,
+    GetActiveUsers = 72,
+    ReturnActiveUsers = 73,
+    CreateRoom = 74,
+    InvalidRoomName = 75,
+    CreateRoomSuccess = 76,
+    GetRooms = 77,
+    ReturnRooms = 78,
+    JoinRoom = 79,
+    JoinRoomSuccess = 80,
+    InvalidJoinRoom = 81,
+    OtherUserJoinedRoom = 82,
+    OtherUserLeftRoom = 83,
+    DeleteRoom = 84,
+    DeleteRoomSuccess = 85,
+    IncompatibleProtocol = 86,
+    MalformedMessage = 87,
+    SetPreferredResolution = 88,
+    RequestResolution = 89,
+    RequestKeyframe = 90,
+    KeyframeRequested = 91,
+    SupportedFrameFormats = 92,
+    ReportReception = 93,
+    ReceptionFeedback = 94,
+    SendChat = 95,
+    ChatMessage = 96,
+    InvalidChat = 97,
+    DirectMessage = 98,
+    DirectMessageReceived = 99,
+    DirectMessageDelivered = 100,
+    InvalidDirectMessage = 101,
+}
+
+/// Opcodes reserved for extensions. Like every other frame, their payload is
+/// length-prefixed, and readers silently skip the ones they do not understand.
+pub const EXTENSION_OPCODES: RangeInclusive<u8> = 0xE0..=0xFF;
+
+impl MessageType {
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_byte(opcode: u8) -> Result<MessageType, ProtocolError> {
+        let message_type = match opcode {
+            69 => MessageType::HelloFromClient,
+            70 => MessageType::HelloFromServer,
+            71 => MessageType::InvalidUsername,
+            72 => MessageType::GetActiveUsers,
+            73 => MessageType::ReturnActiveUsers,
+            74 => MessageType::CreateRoom,
+            75 => MessageType::InvalidRoomName,
+            76 => MessageType::CreateRoomSuccess,
+            77 => MessageType::GetRooms,
+            78 => MessageType::ReturnRooms,
+            79 => MessageType::JoinRoom,
+            80 => MessageType::JoinRoomSuccess,
+            81 => MessageType::InvalidJoinRoom,
+            82 => MessageType::OtherUserJoinedRoom,
+            83 => MessageType::OtherUserLeftRoom,
+            84 => MessageType::DeleteRoom,
+            85 => MessageType::DeleteRoomSuccess,
+            86 => MessageType::IncompatibleProtocol,
+            87 => MessageType::MalformedMessage,
+            88 => MessageType::SetPreferredResolution,
+            89 => MessageType::RequestResolution,
+            90 => MessageType::RequestKeyframe,
+            91 => MessageType::KeyframeRequested,
+            92 => MessageType::SupportedFrameFormats,
+            93 => MessageType::ReportReception,
+            94 => MessageType::ReceptionFeedback,
+            95 => MessageType::SendChat,
+            96 => MessageType::ChatMessage,
+            97 => MessageType::InvalidChat,
+            98 => MessageType::DirectMessage,
+            99 => MessageType::DirectMessageReceived,
+            100 => MessageType::DirectMessageDelivered,
+            101 => MessageType::InvalidDirectMessage,
+            _ => return Err(ProtocolError::UnknownOpcode(opcode)),
+        };
+
+        return Ok(message_type);
+    }
+
+    pub fn is_extension(opcode: u8) -> bool {
+        EXTENSION_OPCODES.contains(&opcode)
+    }
+}
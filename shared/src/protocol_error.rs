@@ -0,0 +1,101 @@
+use std::{fmt, io, str::Utf8Error};
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The frame's opcode is neither a known message nor an extension.
+    UnknownOpcode(u8),
+    InvalidUtf8(Utf8Error),
+    OversizeField {
+        field: &'static str,
+        len: usize,
+        max: usize,
+    },
+    /// The frame ended before all of its fields were read.
+    TruncatedFrame,
+    /// A media packet was built with a layout version this build cannot parse.
+    UnsupportedMediaVersion(u8),
+    UnknownMediaKind(u8),
+    UnknownVideoFormat(u8),
+    UnknownRoomMode(u8),
+    UnknownChatScope(u8),
+    /// A video frame whose cells are not as many as its resolution and
+    /// format call for.
+    InvalidVideoFrame {
+        expected: usize,
+        len: usize,
+    },
+    /// A delta frame refers to a keyframe that was never received.
+    MissingKeyframe(u32),
+    /// A media fragment whose index does not fall within its fragment count.
+    InvalidFragment {
+        index: u16,
+        count: u16,
+    },
+    Io(io::Error),
+}
+
+impl ProtocolError {
+    /// Whether the stream is still aligned on a frame boundary after this
+    /// error, so the connection can keep being used.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, ProtocolError::Io(_))
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::UnknownOpcode(opcode) => write!(f, "Unknown message opcode {}", opcode),
+            ProtocolError::InvalidUtf8(e) => write!(f, "Message contains invalid UTF-8: {}", e),
+            ProtocolError::OversizeField { field, len, max } => {
+                write!(f, "{} too long: {} bytes (maximum is {})", field, len, max)
+            }
+            ProtocolError::TruncatedFrame => write!(f, "Message payload truncated"),
+            ProtocolError::UnsupportedMediaVersion(version) => {
+                write!(f, "Unsupported media packet version {}", version)
+            }
+            ProtocolError::UnknownMediaKind(kind) => write!(f, "Unknown media kind {}", kind),
+            ProtocolError::UnknownVideoFormat(format) => {
+                write!(f, "Unknown video format {}", format)
+            }
+            ProtocolError::UnknownRoomMode(mode) => write!(f, "Unknown room mode {}", mode),
+            ProtocolError::UnknownChatScope(scope) => write!(f, "Unknown chat scope {}", scope),
+            ProtocolError::InvalidVideoFrame { expected, len } => {
+                write!(
+                    f,
+                    "Video frame has {} bytes of cells, expected {}",
+                    len, expected
+                )
+            }
+            ProtocolError::MissingKeyframe(sequence) => {
+                write!(f, "Delta frame refers to missing keyframe {}", sequence)
+            }
+            ProtocolError::InvalidFragment { index, count } => {
+                write!(f, "Invalid media fragment {} of {}", index, count)
+            }
+            ProtocolError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtocolError::InvalidUtf8(e) => Some(e),
+            ProtocolError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+
+impl From<Utf8Error> for ProtocolError {
+    fn from(e: Utf8Error) -> Self {
+        ProtocolError::InvalidUtf8(e)
+    }
+}
@@ -0,0 +1,413 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    handshake::{FrameFormats, MAX_FRAME_SIZE},
+    protocol_error::ProtocolError,
+};
+
+/// Every video frame payload starts with `[width: u16][height: u16]
+/// [format: u8]`, describing the cells that follow.
+pub const VIDEO_HEADER_LEN: usize = 5;
+
+/// Size of a frame's cell grid. A renderer may draw several cells in one
+/// terminal cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Resolution {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Resolution {
+    pub const fn new(width: u16, height: u16) -> Self {
+        Self { width, height }
+    }
+
+    /// The smaller of each dimension.
+    pub fn min(self, other: Resolution) -> Resolution {
+        Resolution::new(self.width.min(other.width), self.height.min(other.height))
+    }
+
+    /// The larger of each dimension.
+    pub fn max(self, other: Resolution) -> Resolution {
+        Resolution::new(self.width.max(other.width), self.height.max(other.height))
+    }
+
+    /// Bytes taken by one 4-bit value per cell, packed two per byte. Rows of
+    /// odd width end in a padding cell.
+    pub fn nibble_len(&self) -> usize {
+        self.height as usize * (self.width as usize).div_ceil(2)
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// This resolution scaled down, keeping roughly its aspect ratio, until
+    /// it has no more than `max_cells` cells.
+    pub fn fit_cells(self, max_cells: usize) -> Resolution {
+        if self.cell_count() <= max_cells {
+            return self;
+        }
+
+        let scale = (max_cells as f64 / self.cell_count() as f64).sqrt();
+        let mut width = ((self.width as f64 * scale) as u16).max(1);
+        let mut height = ((self.height as f64 * scale) as u16).max(1);
+
+        while width as usize * height as usize > max_cells && (width > 1 || height > 1) {
+            if width >= height {
+                width -= 1;
+            } else {
+                height -= 1;
+            }
+        }
+
+        return Resolution::new(width, height);
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    /// Parses `WIDTHxHEIGHT`, e.g. `92x28`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("Expected WIDTHxHEIGHT, got '{}'", s))?;
+
+        let width: u16 = width
+            .parse()
+            .map_err(|_| format!("Invalid width '{}'", width))?;
+        let height: u16 = height
+            .parse()
+            .map_err(|_| format!("Invalid height '{}'", height))?;
+
+        if width == 0 || height == 0 {
+            return Err("Width and height must be greater than zero".to_string());
+        }
+
+        return Ok(Resolution::new(width, height));
+    }
+}
+
+/// How the cells of a video frame are laid out.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// 4-bit luminance per cell.
+    NibbleGray = 1,
+    /// `NibbleGray` followed by one 6x6x6 color cube index per cell, in the
+    /// same order.
+    NibbleColor = 2,
+    /// 8-bit luminance per cell.
+    ByteGray = 3,
+    /// `ByteGray` followed by one 6x6x6 color cube index per cell, in the
+    /// same order.
+    ByteColor = 4,
+}
+
+impl VideoFormat {
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_byte(format: u8) -> Result<VideoFormat, ProtocolError> {
+        match format {
+            1 => Ok(VideoFormat::NibbleGray),
+            2 => Ok(VideoFormat::NibbleColor),
+            3 => Ok(VideoFormat::ByteGray),
+            4 => Ok(VideoFormat::ByteColor),
+            _ => Err(ProtocolError::UnknownVideoFormat(format)),
+        }
+    }
+
+    /// Capability a peer needs to decode this format.
+    pub fn frame_format(&self) -> FrameFormats {
+        match self {
+            VideoFormat::NibbleGray => FrameFormats::NIBBLE_GRAY,
+            VideoFormat::NibbleColor => FrameFormats::NIBBLE_COLOR,
+            VideoFormat::ByteGray => FrameFormats::BYTE_GRAY,
+            VideoFormat::ByteColor => FrameFormats::BYTE_COLOR,
+        }
+    }
+
+    /// Precision of each cell's luminance.
+    pub fn luminance_bits(&self) -> u8 {
+        match self {
+            VideoFormat::NibbleGray | VideoFormat::NibbleColor => 4,
+            VideoFormat::ByteGray | VideoFormat::ByteColor => 8,
+        }
+    }
+
+    /// Bytes of luminance at the start of a frame's cells.
+    pub fn luminance_len(&self, resolution: Resolution) -> usize {
+        match self.luminance_bits() {
+            4 => resolution.nibble_len(),
+            _ => resolution.cell_count(),
+        }
+    }
+
+    /// Whether a color cube index per cell follows the luminance.
+    pub fn has_color(&self) -> bool {
+        matches!(self, VideoFormat::NibbleColor | VideoFormat::ByteColor)
+    }
+
+    /// Bytes of cells a frame of `resolution` has in this format.
+    pub fn cells_len(&self, resolution: Resolution) -> usize {
+        match self.has_color() {
+            true => self.luminance_len(resolution) + resolution.cell_count(),
+            false => self.luminance_len(resolution),
+        }
+    }
+
+    /// `resolution`, scaled down until an encoded frame of it in this
+    /// format fits in `max_frame_size` bytes.
+    pub fn fit_frame_size(&self, resolution: Resolution, max_frame_size: usize) -> Resolution {
+        let mut resolution = resolution.fit_cells(max_frame_size);
+
+        while VIDEO_HEADER_LEN + self.cells_len(resolution) > max_frame_size
+            && resolution.cell_count() > 1
+        {
+            resolution = resolution.fit_cells(resolution.cell_count() * 15 / 16);
+        }
+
+        return resolution;
+    }
+}
+
+/// One video frame as carried in a media payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoFrame {
+    pub resolution: Resolution,
+    pub format: VideoFormat,
+    pub cells: Vec<u8>,
+}
+
+impl VideoFrame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(VIDEO_HEADER_LEN + self.cells.len());
+        payload.extend_from_slice(&self.resolution.width.to_be_bytes());
+        payload.extend_from_slice(&self.resolution.height.to_be_bytes());
+        payload.push(self.format.to_byte());
+        payload.extend_from_slice(&self.cells);
+
+        return payload;
+    }
+
+    /// Builds a frame in `format` from 8-bit luminance and, for color
+    /// formats, a color cube index per cell, both row by row.
+    pub fn from_cells(
+        resolution: Resolution,
+        format: VideoFormat,
+        luminance: &[u8],
+        colors: &[u8],
+    ) -> VideoFrame {
+        let width = (resolution.width as usize).max(1);
+        let mut cells = Vec::with_capacity(format.luminance_len(resolution) + colors.len());
+
+        match format.luminance_bits() {
+            4 => {
+                for row in luminance.chunks(width) {
+                    for pair in row.chunks(2) {
+                        let high = to_nibble(pair[0]);
+                        let low = pair.get(1).copied().map(to_nibble).unwrap_or(0);
+
+                        cells.push((high << 4) | low);
+                    }
+                }
+            }
+            _ => cells.extend_from_slice(luminance),
+        }
+
+        if format.has_color() {
+            cells.extend_from_slice(colors);
+        }
+
+        return VideoFrame {
+            resolution,
+            format,
+            cells,
+        };
+    }
+
+    /// 8-bit luminance of every cell, row by row, whatever the format.
+    /// Cells missing from a short frame are black.
+    pub fn luminance(&self) -> Vec<u8> {
+        let width = self.resolution.width as usize;
+        let mut luminance = Vec::with_capacity(self.resolution.cell_count());
+
+        match self.format.luminance_bits() {
+            4 => {
+                let row_len = width.div_ceil(2);
+
+                for row in 0..self.resolution.height as usize {
+                    for col in 0..width {
+                        let byte = self
+                            .cells
+                            .get(row * row_len + col / 2)
+                            .copied()
+                            .unwrap_or(0);
+                        let nibble = if col % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+
+                        luminance.push(nibble * 17);
+                    }
+                }
+            }
+            _ => {
+                luminance.extend(self.cells.iter().take(self.resolution.cell_count()));
+                luminance.resize(self.resolution.cell_count(), 0);
+            }
+        }
+
+        return luminance;
+    }
+
+    /// Color cube index of every cell, row by row, for color formats.
+    pub fn colors(&self) -> Option<&[u8]> {
+        if !self.format.has_color() {
+            return None;
+        }
+
+        let start = self.format.luminance_len(self.resolution);
+
+        return self.cells.get(start..start + self.resolution.cell_count());
+    }
+
+    /// Parses a video payload. The cells must be exactly as many as the
+    /// resolution and format call for, and no more than `MAX_FRAME_SIZE`.
+    pub fn decode(payload: &[u8]) -> Result<VideoFrame, ProtocolError> {
+        if payload.len() < VIDEO_HEADER_LEN {
+            return Err(ProtocolError::TruncatedFrame);
+        }
+
+        let resolution = Resolution::new(
+            u16::from_be_bytes([payload[0], payload[1]]),
+            u16::from_be_bytes([payload[2], payload[3]]),
+        );
+        let format = VideoFormat::from_byte(payload[4])?;
+        let cells = &payload[VIDEO_HEADER_LEN..];
+
+        if resolution.cell_count() > MAX_FRAME_SIZE as usize {
+            return Err(ProtocolError::OversizeField {
+                field: "Video resolution",
+                len: resolution.cell_count(),
+                max: MAX_FRAME_SIZE as usize,
+            });
+        }
+
+        let expected = format.cells_len(resolution);
+
+        if cells.len() != expected {
+            return Err(ProtocolError::InvalidVideoFrame {
+                expected,
+                len: cells.len(),
+            });
+        }
+
+        return Ok(VideoFrame {
+            resolution,
+            format,
+            cells: cells.to_vec(),
+        });
+    }
+}
+
+/// Rounds 8-bit luminance to the nearest of 16 levels.
+fn to_nibble(luminance: u8) -> u8 {
+    return ((luminance as u16 * 15 + 127) / 255) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_round_trip_through_nibble_color() {
+        // An odd width, so each row ends in a padding nibble.
+        let resolution = Resolution::new(3, 2);
+        let luminance = [0, 17, 255, 34, 51, 68];
+        let colors = [1, 2, 3, 4, 5, 6];
+
+        let frame =
+            VideoFrame::from_cells(resolution, VideoFormat::NibbleColor, &luminance, &colors);
+
+        assert_eq!(frame.cells, vec![0x01, 0xf0, 0x23, 0x40, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(frame.luminance(), luminance);
+        assert_eq!(frame.colors(), Some(&colors[..]));
+        assert_eq!(VideoFrame::decode(&frame.encode()).unwrap(), frame);
+    }
+
+    #[test]
+    fn fit_cells_keeps_the_aspect_ratio() {
+        assert_eq!(
+            Resolution::new(92, 28).fit_cells(10_000),
+            Resolution::new(92, 28)
+        );
+        assert_eq!(
+            Resolution::new(400, 100).fit_cells(2_500),
+            Resolution::new(100, 25)
+        );
+        assert!(
+            Resolution::new(u16::MAX, u16::MAX)
+                .fit_cells(1 << 18)
+                .cell_count()
+                <= 1 << 18
+        );
+    }
+
+    #[test]
+    fn fit_frame_size_counts_encoded_bytes() {
+        let resolution = Resolution::new(u16::MAX, u16::MAX);
+
+        for format in [
+            VideoFormat::NibbleGray,
+            VideoFormat::NibbleColor,
+            VideoFormat::ByteGray,
+            VideoFormat::ByteColor,
+        ] {
+            let fitted = format.fit_frame_size(resolution, MAX_FRAME_SIZE as usize);
+
+            assert!(VIDEO_HEADER_LEN + format.cells_len(fitted) <= MAX_FRAME_SIZE as usize);
+            assert!(fitted.width > 1 && fitted.height > 1);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_cells_that_do_not_match_the_header() {
+        let frame = VideoFrame::from_cells(
+            Resolution::new(4, 2),
+            VideoFormat::ByteColor,
+            &[0; 8],
+            &[0; 8],
+        );
+
+        let mut payload = frame.encode();
+        payload.pop();
+
+        assert!(matches!(
+            VideoFrame::decode(&payload),
+            Err(ProtocolError::InvalidVideoFrame {
+                expected: 16,
+                len: 15
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_resolutions_over_the_frame_size_limit() {
+        let mut payload =
+            VideoFrame::from_cells(Resolution::new(1, 1), VideoFormat::NibbleGray, &[0], &[])
+                .encode();
+        payload[..4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+
+        assert!(matches!(
+            VideoFrame::decode(&payload),
+            Err(ProtocolError::OversizeField { .. })
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+use std::str::from_utf8;
+
+use crate::{
+    ProtocolError,
+    handshake::{Capabilities, Features, FrameFormats},
+    media::ReceptionReport,
+    message::{ChatScope, RoomMode},
+    video::Resolution,
+};
+
+/// A value with a fixed binary layout inside a message payload.
+pub(crate) trait WireField: Sized {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError>;
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError>;
+}
+
+/// Cursor over a payload that has already been read in full.
+pub(crate) struct PayloadReader<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    pub(crate) fn new(payload: &'a [u8]) -> Self {
+        Self { payload }
+    }
+
+    pub(crate) fn read<T: WireField>(&mut self) -> Result<T, ProtocolError> {
+        T::decode(self)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        if self.payload.len() < len {
+            return Err(ProtocolError::TruncatedFrame);
+        }
+
+        let (bytes, rest) = self.payload.split_at(len);
+        self.payload = rest;
+
+        return Ok(bytes);
+    }
+}
+
+impl<const N: usize> WireField for [u8; N] {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        buf.extend(self);
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        let bytes = reader.read_bytes(N)?;
+        return Ok(bytes.try_into().expect("read_bytes returned wrong length"));
+    }
+}
+
+impl WireField for u8 {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_be_bytes().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(u8::from_be_bytes(reader.read()?));
+    }
+}
+
+impl WireField for u16 {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_be_bytes().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(u16::from_be_bytes(reader.read()?));
+    }
+}
+
+impl WireField for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_be_bytes().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(u32::from_be_bytes(reader.read()?));
+    }
+}
+
+impl WireField for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_be_bytes().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(u64::from_be_bytes(reader.read()?));
+    }
+}
+
+/// Strings are a `u16` byte length followed by UTF-8.
+impl WireField for String {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        if self.len() > u16::MAX as usize {
+            return Err(ProtocolError::OversizeField {
+                field: "Message string",
+                len: self.len(),
+                max: u16::MAX as usize,
+            });
+        }
+
+        (self.len() as u16).encode(buf)?;
+        buf.extend(self.as_bytes());
+
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        let len: u16 = reader.read()?;
+        let bytes = reader.read_bytes(len as usize)?;
+
+        return Ok(from_utf8(bytes)?.to_string());
+    }
+}
+
+/// Lists are a `u16` element count followed by the elements.
+impl<T: WireField> WireField for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        if self.len() > u16::MAX as usize {
+            return Err(ProtocolError::OversizeField {
+                field: "Message list",
+                len: self.len(),
+                max: u16::MAX as usize,
+            });
+        }
+
+        (self.len() as u16).encode(buf)?;
+        for item in self {
+            item.encode(buf)?;
+        }
+
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        let len: u16 = reader.read()?;
+
+        let mut items = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            items.push(reader.read()?);
+        }
+
+        return Ok(items);
+    }
+}
+
+impl WireField for FrameFormats {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.bits().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(FrameFormats::from_bits(reader.read()?));
+    }
+}
+
+impl WireField for Capabilities {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        self.frame_formats.encode(buf)?;
+        self.max_frame_size.encode(buf)?;
+        self.features.bits().encode(buf)?;
+
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(Capabilities {
+            frame_formats: reader.read()?,
+            max_frame_size: reader.read()?,
+            features: Features::from_bits(reader.read()?),
+        });
+    }
+}
+
+impl WireField for ReceptionReport {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        self.loss_permille.encode(buf)?;
+        self.jitter_ms.encode(buf)?;
+
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(ReceptionReport {
+            loss_permille: reader.read()?,
+            jitter_ms: reader.read()?,
+        });
+    }
+}
+
+impl WireField for RoomMode {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_byte().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return RoomMode::from_byte(reader.read()?);
+    }
+}
+
+impl WireField for ChatScope {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        return self.to_byte().encode(buf);
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return ChatScope::from_byte(reader.read()?);
+    }
+}
+
+impl WireField for Resolution {
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        self.width.encode(buf)?;
+        self.height.encode(buf)?;
+
+        return Ok(());
+    }
+
+    fn decode(reader: &mut PayloadReader<'_>) -> Result<Self, ProtocolError> {
+        return Ok(Resolution::new(reader.read()?, reader.read()?));
+    }
+}